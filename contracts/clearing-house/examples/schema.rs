@@ -1,7 +1,7 @@
 use std::env::current_dir;
 use std::fs::create_dir_all;
 
-use clearing_house::states::history::{CurveRecord, TradeRecord, LiquidationRecord, FundingRateRecord, FundingPaymentRecord, DepositRecord};
+use clearing_house::states::history::{BankruptcyRecord, CurveRecord, TradeRecord, LiquidationRecord, FundingRateRecord, FundingPaymentRecord, DepositRecord};
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use ariel::execute::{ExecuteMsg, InstantiateMsg};
@@ -28,6 +28,7 @@ fn main() {
     export_schema(&schema_for!(FundingPaymentRecord), &out_dir);
     export_schema(&schema_for!(FundingRateRecord), &out_dir);
     export_schema(&schema_for!(LiquidationRecord), &out_dir);
+    export_schema(&schema_for!(BankruptcyRecord), &out_dir);
     export_schema(&schema_for!(market::Market), &out_dir);
     export_schema(&schema_for!(market::Amm), &out_dir);
     export_schema(&schema_for!(state::State), &out_dir);
@@ -76,7 +77,23 @@ fn main() {
     export_schema(&schema_for!(FundingRateHistoryResponse), &out_dir);
     export_schema(&schema_for!(LiquidationHistoryLengthResponse), &out_dir);
     export_schema(&schema_for!(LiquidationHistoryResponse), &out_dir);
+    export_schema(&schema_for!(BankruptcyHistoryLengthResponse), &out_dir);
+    export_schema(&schema_for!(BankruptcyHistoryResponse), &out_dir);
+    export_schema(&schema_for!(OrderHistoryLengthResponse), &out_dir);
+    export_schema(&schema_for!(OrderHistoryResponse), &out_dir);
     export_schema(&schema_for!(TradeHistoryLengthResponse), &out_dir);
     export_schema(&schema_for!(TradeHistoryResponse), &out_dir);
     export_schema(&schema_for!(MarketInfoResponse), &out_dir);
+    export_schema(&schema_for!(OrderResponse), &out_dir);
+    export_schema(&schema_for!(FillableOrderResponse), &out_dir);
+    export_schema(&schema_for!(FillableOrdersResponse), &out_dir);
+    export_schema(&schema_for!(FeeTierVolumesResponse), &out_dir);
+    export_schema(&schema_for!(UserFeeTierResponse), &out_dir);
+    export_schema(&schema_for!(DiscountTokenResponse), &out_dir);
+    export_schema(&schema_for!(ReferralStatsResponse), &out_dir);
+    export_schema(&schema_for!(LiquidatableUsersResponse), &out_dir);
+    export_schema(&schema_for!(NextFundingTimeResponse), &out_dir);
+    export_schema(&schema_for!(FundingRateEstimateResponse), &out_dir);
+    export_schema(&schema_for!(LiquidationAuctionConfigResponse), &out_dir);
+    export_schema(&schema_for!(LiquidationAuctionResponse), &out_dir);
 }
@@ -8,9 +8,10 @@ use cw2::set_contract_version;
 // use cw_utils::maybe_addr;
 
 use crate::states::constants::*;
-use crate::states::history::{DEPOSIT_HISTORY_INFO, DepositInfo, TRADE_HISTORY_INFO, TradeInfo};
+use crate::states::history::{BANKRUPTCY_HISTORY_INFO, BankruptcyInfo, CURVE_HISTORY_INFO, CurveInfo, FUNDING_PAYMENT_HISTORY_INFO, FundingPaymentInfo, FUNDING_RATE_HISTORY_INFO, FundingRateInfo, DEPOSIT_HISTORY_INFO, DepositInfo, LIQUIDATION_HISTORY_INFO, LiquidationInfo, ORDER_HISTORY_INFO, OrderHisInfo, TRADE_HISTORY_INFO, TradeInfo};
+use crate::states::liquidation::{LiquidationAuctionConfig, LIQUIDATION_AUCTION_CONFIG};
 use crate::states::order::OrderState;
-use crate::states::state::{State, ADMIN, FEESTRUCTURE, ORACLEGUARDRAILS, ORDERSTATE, STATE};
+use crate::states::state::{FeeTierVolumes, State, ADMIN, FEESTRUCTURE, FEE_TIER_VOLUMES, ORACLEGUARDRAILS, ORDERSTATE, STATE};
 
 use ariel::execute::{ExecuteMsg, InstantiateMsg};
 use ariel::helper::addr_validate_to_lower;
@@ -36,6 +37,7 @@ pub fn instantiate(
     //TODO:: adding condition to check the initialization, if it's done already
     let fs = FeeStructure {
         fee: Decimal::from_ratio(DEFAULT_FEE_NUMERATOR, DEFAULT_FEE_DENOMINATOR),
+        maker_fee: Number128::new(DEFAULT_MAKER_FEE),
         first_tier_minimum_balance: DEFAULT_DISCOUNT_TOKEN_FIRST_TIER_MINIMUM_BALANCE,
         first_tier_discount: Decimal::from_ratio(
             DEFAULT_DISCOUNT_TOKEN_FIRST_TIER_DISCOUNT_NUMERATOR,
@@ -79,6 +81,18 @@ pub fn instantiate(
         reward: Decimal::zero(),
         time_based_reward_lower_bound: Uint128::zero(), // minimum filler reward for time-based reward
     };
+    let fee_tier_volumes = FeeTierVolumes {
+        first_tier_minimum_volume: DEFAULT_VOLUME_FIRST_TIER_MINIMUM,
+        second_tier_minimum_volume: DEFAULT_VOLUME_SECOND_TIER_MINIMUM,
+        third_tier_minimum_volume: DEFAULT_VOLUME_THIRD_TIER_MINIMUM,
+        fourth_tier_minimum_volume: DEFAULT_VOLUME_FOURTH_TIER_MINIMUM,
+    };
+    let liquidation_auction_config = LiquidationAuctionConfig {
+        enabled: false,
+        duration: DEFAULT_LIQUIDATION_AUCTION_DURATION,
+        start_discount: Decimal::zero(),
+        max_discount: Decimal::percent(DEFAULT_LIQUIDATION_AUCTION_MAX_DISCOUNT_PERCENT),
+    };
     let state = State {
         exchange_paused: false,
         funding_paused: false,
@@ -105,6 +119,8 @@ pub fn instantiate(
     FEESTRUCTURE.save(deps.storage, &fs)?;
     ORACLEGUARDRAILS.save(deps.storage, &oracle_gr)?;
     ORDERSTATE.save(deps.storage, &orderstate)?;
+    FEE_TIER_VOLUMES.save(deps.storage, &fee_tier_volumes)?;
+    LIQUIDATION_AUCTION_CONFIG.save(deps.storage, &liquidation_auction_config)?;
     DEPOSIT_HISTORY_INFO.save(
         deps.storage,
         &DepositInfo{ len: 0}
@@ -113,6 +129,30 @@ pub fn instantiate(
         deps.storage,
         &TradeInfo{ len: 0}
     )?;
+    ORDER_HISTORY_INFO.save(
+        deps.storage,
+        &OrderHisInfo{ len: 0}
+    )?;
+    LIQUIDATION_HISTORY_INFO.save(
+        deps.storage,
+        &LiquidationInfo{ len: 0}
+    )?;
+    BANKRUPTCY_HISTORY_INFO.save(
+        deps.storage,
+        &BankruptcyInfo{ len: 0}
+    )?;
+    CURVE_HISTORY_INFO.save(
+        deps.storage,
+        &CurveInfo{ len: 0}
+    )?;
+    FUNDING_RATE_HISTORY_INFO.save(
+        deps.storage,
+        &FundingRateInfo{ len: 0}
+    )?;
+    FUNDING_PAYMENT_HISTORY_INFO.save(
+        deps.storage,
+        &FundingPaymentInfo{ len: 0}
+    )?;
     Ok(Response::new()
         .add_attribute("method", "instantiate")
         .add_attribute("owner", info.sender.clone()))
@@ -138,6 +178,7 @@ pub fn execute(
             margin_ratio_initial,
             margin_ratio_partial,
             margin_ratio_maintenance,
+            curve_type,
         } => try_initialize_market(
             deps,
             _env,
@@ -152,6 +193,7 @@ pub fn execute(
             margin_ratio_initial,
             margin_ratio_partial,
             margin_ratio_maintenance,
+            curve_type,
         ),
         ExecuteMsg::DepositCollateral { amount, referrer } => {
             try_deposit_collateral(deps, _env, info, amount, referrer)
@@ -178,6 +220,30 @@ pub fn execute(
             market_index,
             order_id,
         } => try_cancel_order(deps, _env, info, market_index, order_id),
+        ExecuteMsg::CancelOrderByUserId { user_order_id } => {
+            try_cancel_order_by_user_id(deps, _env, info, user_order_id)
+        }
+        ExecuteMsg::CancelAllOrders {
+            market_index,
+            direction,
+        } => try_cancel_all_orders(deps, _env, info, market_index, direction),
+        ExecuteMsg::BatchOrders { place, cancel } => {
+            try_batch_orders(deps, _env, info, place, cancel)
+        }
+        ExecuteMsg::ModifyOrder {
+            order_id,
+            new_price,
+            new_base_asset_amount,
+            new_trigger_price,
+        } => try_modify_order(
+            deps,
+            _env,
+            info,
+            order_id,
+            new_price,
+            new_base_asset_amount,
+            new_trigger_price,
+        ),
         ExecuteMsg::ExpireOrders { user_address } => {
             try_expire_orders(deps, _env, info, user_address)
         }
@@ -185,13 +251,45 @@ pub fn execute(
             order_id,
             user_address,
             market_index,
-        } => try_fill_order(deps, _env, info, order_id, user_address, market_index),
+            maker_orders,
+        } => try_fill_order(
+            deps,
+            _env,
+            info,
+            order_id,
+            user_address,
+            market_index,
+            maker_orders,
+        ),
         ExecuteMsg::ClosePosition { market_index } => {
             try_close_position(deps, _env, info, market_index)
         }
         ExecuteMsg::Liquidate { user, market_index } => {
             try_liquidate(deps, _env, info, user, market_index)
         }
+        ExecuteMsg::LiquidatePerp {
+            user,
+            market_index,
+            max_base_asset_amount,
+            limit_price,
+        } => try_liquidate_perp(
+            deps,
+            _env,
+            info,
+            user,
+            market_index,
+            max_base_asset_amount,
+            limit_price,
+        ),
+        ExecuteMsg::StartLiquidationAuction { user, market_index } => {
+            try_start_liquidation_auction(deps, _env, user, market_index)
+        }
+        ExecuteMsg::BidLiquidationAuction {
+            user,
+            market_index,
+            base_asset_amount,
+        } => try_bid_liquidation_auction(deps, _env, info, user, market_index, base_asset_amount),
+        ExecuteMsg::ResolveBankruptcy { user } => try_resolve_bankruptcy(deps, _env, user),
         ExecuteMsg::MoveAMMPrice {
             base_asset_reserve,
             quote_asset_reserve,
@@ -216,13 +314,65 @@ pub fn execute(
             try_reset_amm_oracle_twap(deps, _env, market_index)
         }
         ExecuteMsg::SettleFundingPayment {} => try_settle_funding_payment(deps, _env, info),
+        ExecuteMsg::RegisterReferralCode { code } => try_register_referral_code(deps, info, code),
+        ExecuteMsg::ClaimReferralRewards {} => try_claim_referral_rewards(deps, info),
         ExecuteMsg::UpdateFundingRate { market_index } => {
-            try_update_funding_rate(deps, _env, market_index)
+            try_update_funding_rate(deps, _env, info, market_index)
         }
         ExecuteMsg::UpdateK {
             market_index,
             sqrt_k,
         } => try_update_k(deps, _env, market_index, sqrt_k),
+        ExecuteMsg::UpdateRepegBudget {
+            market_index,
+            repeg_budget,
+        } => try_update_repeg_budget(deps, info, market_index, repeg_budget),
+        ExecuteMsg::UpdateDynamicK {
+            market_index,
+            enabled,
+            minimum_sqrt_k,
+            maximum_sqrt_k,
+        } => try_update_dynamic_k(
+            deps,
+            info,
+            market_index,
+            enabled,
+            minimum_sqrt_k,
+            maximum_sqrt_k,
+        ),
+        ExecuteMsg::UpdateSpread {
+            market_index,
+            base_spread,
+            max_spread,
+        } => try_update_spread(deps, info, market_index, base_spread, max_spread),
+        ExecuteMsg::UpdateFundingParameters {
+            market_index,
+            max_funding_rate,
+            interest_rate,
+            funding_clamp,
+        } => try_update_funding_parameters(
+            deps,
+            info,
+            market_index,
+            max_funding_rate,
+            interest_rate,
+            funding_clamp,
+        ),
+        ExecuteMsg::UpdateFundingFeeShare {
+            market_index,
+            funding_fee_share,
+        } => try_update_funding_fee_share(deps, info, market_index, funding_fee_share),
+        ExecuteMsg::UpdateOracleTwapParameters {
+            market_index,
+            oracle_twap_period,
+            oracle_twap_cap_percent,
+        } => try_update_oracle_twap_parameters(
+            deps,
+            info,
+            market_index,
+            oracle_twap_period,
+            oracle_twap_cap_percent,
+        ),
         ExecuteMsg::UpdateMarginRatio {
             market_index,
             margin_ratio_initial,
@@ -253,6 +403,7 @@ pub fn execute(
         }
         ExecuteMsg::UpdateFee {
             fee_: fee,
+            maker_fee,
             first_tier_minimum_balance,
             first_tier_discount,
             second_tier_minimum_balance,
@@ -267,6 +418,7 @@ pub fn execute(
             deps,
             info,
             fee,
+            maker_fee,
             first_tier_minimum_balance,
             first_tier_discount,
             second_tier_minimum_balance,
@@ -325,6 +477,41 @@ pub fn execute(
             market_index,
             minimum_trade_size,
         ),
+        ExecuteMsg::UpdateReferrerRewardRate {
+            referrer,
+            referrer_reward,
+            referee_discount,
+        } => try_update_referrer_reward_rate(deps, info, referrer, referrer_reward, referee_discount),
+        ExecuteMsg::UpdateFeeTierVolumes {
+            first_tier_minimum_volume,
+            second_tier_minimum_volume,
+            third_tier_minimum_volume,
+            fourth_tier_minimum_volume,
+        } => try_update_fee_tier_volumes(
+            deps,
+            info,
+            first_tier_minimum_volume,
+            second_tier_minimum_volume,
+            third_tier_minimum_volume,
+            fourth_tier_minimum_volume,
+        ),
+        ExecuteMsg::UpdateDiscountToken {
+            token,
+            staking_contract,
+        } => try_update_discount_token(deps, info, token, staking_contract),
+        ExecuteMsg::UpdateLiquidationAuctionConfig {
+            enabled,
+            duration,
+            start_discount,
+            max_discount,
+        } => try_update_liquidation_auction_config(
+            deps,
+            info,
+            enabled,
+            duration,
+            start_discount,
+            max_discount,
+        ),
         ExecuteMsg::UpdateOrderState {
             min_order_quote_asset_amount,
             reward,
@@ -394,6 +581,33 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> Result<Binary, ContractErr
         QueryMsg::GetOracleGuardRails {} => Ok(to_binary(&get_oracle_guard_rails(deps)?)?),
         QueryMsg::GetOrderState {} => Ok(to_binary(&get_order_state(deps)?)?),
         QueryMsg::GetFeeStructure {} => Ok(to_binary(&get_fee_structure(deps)?)?),
+        QueryMsg::GetFeeTierVolumes {} => Ok(to_binary(&get_fee_tier_volumes(deps)?)?),
+        QueryMsg::GetDiscountToken {} => Ok(to_binary(&get_discount_token(deps)?)?),
+        QueryMsg::GetReferralStats {
+            referrer,
+            start_after,
+            limit,
+        } => Ok(to_binary(&get_referral_stats(deps, referrer, start_after, limit)?)?),
+        QueryMsg::GetUserFeeTier { user_address } => Ok(to_binary(&get_user_fee_tier(
+            deps,
+            user_address,
+            _env.block.time.seconds(),
+        )?)?),
+        QueryMsg::GetLiquidatableUsers { start_after, limit } => {
+            Ok(to_binary(&get_liquidatable_users(deps, start_after, limit)?)?)
+        }
+        QueryMsg::GetLiquidationAuctionConfig {} => {
+            Ok(to_binary(&get_liquidation_auction_config(deps)?)?)
+        }
+        QueryMsg::GetLiquidationAuction {
+            user_address,
+            market_index,
+        } => Ok(to_binary(&get_liquidation_auction(
+            deps,
+            user_address,
+            market_index,
+            _env.block.time.seconds(),
+        )?)?),
         QueryMsg::GetCurveHistoryLength {} => Ok(to_binary(&get_curve_history_length(deps)?)?),
         QueryMsg::GetCurveHistory { start_after, limit } => {
             Ok(to_binary(&get_curve_history(deps, start_after, limit)?)?)
@@ -425,6 +639,12 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> Result<Binary, ContractErr
         QueryMsg::GetFundingRateHistoryLength {} => {
             Ok(to_binary(&get_funding_rate_history_length(deps)?)?)
         }
+        QueryMsg::GetFundingRateEstimate { market_index } => {
+            Ok(to_binary(&get_funding_rate_estimate(deps, _env, market_index)?)?)
+        }
+        QueryMsg::GetNextFundingTime { market_index } => {
+            Ok(to_binary(&get_next_funding_time(deps, market_index)?)?)
+        }
         QueryMsg::GetFundingRateHistory { start_after, limit } => Ok(to_binary(
             &get_funding_rate_history(deps, start_after, limit)?,
         )?),
@@ -441,6 +661,34 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> Result<Binary, ContractErr
             start_after,
             limit,
         )?)?),
+        QueryMsg::GetBankruptcyHistoryLength {} => {
+            Ok(to_binary(&get_bankruptcy_history_length(deps)?)?)
+        }
+        QueryMsg::GetBankruptcyHistory {
+            user_address,
+            start_after,
+            limit,
+        } => Ok(to_binary(&get_bankruptcy_history(
+            deps,
+            user_address,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::GetOrderHistoryLength {} => Ok(to_binary(&get_order_history_length(deps)?)?),
+        QueryMsg::GetOrderHistory {
+            user_address,
+            market_index,
+            action,
+            start_after,
+            limit,
+        } => Ok(to_binary(&get_order_history(
+            deps,
+            user_address,
+            market_index,
+            action,
+            start_after,
+            limit,
+        )?)?),
         QueryMsg::GetTradeHistoryLength {} => Ok(to_binary(&get_trade_history_length(deps)?)?),
         QueryMsg::GetTradeHistory { start_after, limit } => {
             Ok(to_binary(&get_trade_history(deps, start_after, limit)?)?)
@@ -448,6 +696,38 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> Result<Binary, ContractErr
         QueryMsg::GetMarketInfo { market_index } => {
             Ok(to_binary(&get_market_info(deps, market_index)?)?)
         }
+        QueryMsg::GetUserOrders {
+            user,
+            market_index,
+            order_type,
+            start_after,
+            limit,
+        } => Ok(to_binary(&get_user_orders(
+            deps,
+            user,
+            market_index,
+            order_type,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::GetFillableOrders {
+            market_index,
+            start_after,
+            limit,
+        } => Ok(to_binary(&get_fillable_orders(
+            deps,
+            _env.block.time.seconds(),
+            market_index,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::GetOrder { user, order_id } => {
+            Ok(to_binary(&get_order(deps, user, order_id)?)?)
+        }
+        QueryMsg::GetOrderByUserId {
+            user,
+            user_order_id,
+        } => Ok(to_binary(&get_order_by_user_id(deps, user, user_order_id)?)?),
     }
 }
 
@@ -1,3 +1,4 @@
+use std::cmp::{max, min};
 use std::ops::Mul;
 use ariel::number::Number128;
 use num::integer::Roots;
@@ -5,15 +6,21 @@ use cosmwasm_std::{DepsMut, Uint128};
 
 use crate::error::{ContractError};
 
-use ariel::types::SwapDirection;
+use ariel::types::{OracleGuardRails, PositionDirection, SwapDirection};
 
+use crate::states::history::{CurveInfo, CurveRecord, Type, CURVEHISTORY, CURVE_HISTORY_INFO};
 use crate::states::market::{Market, MARKETS};
 
 use crate::helpers::amm::{calculate_quote_asset_amount_swapped, calculate_new_oracle_price_twap};
-use crate::states::constants::MARK_PRICE_PRECISION;
+use crate::states::constants::{
+    DYNAMIC_K_STEP_DENOMINATOR, DYNAMIC_K_STEP_NUMERATOR, MARK_PRICE_PRECISION,
+    PRICE_SPREAD_PRECISION_U128,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR,
+};
 use crate::helpers::amm;
 use crate::helpers::position::_calculate_base_asset_value_and_pnl;
-use crate::helpers::position::asset_to_reserve_amount;
+use crate::helpers::position::{asset_to_reserve_amount, reserve_to_asset_amount};
 
 pub fn update_mark_twap(
     deps: &mut DepsMut,
@@ -43,18 +50,22 @@ pub fn update_oracle_price_twap(
         .checked_sub(a.last_oracle_price_twap.i128())
         .ok_or_else(|| (ContractError::MathError))?;
 
-    // cap new oracle update to 33% delta from twap
-    let oracle_price_33pct = oracle_price.checked_div(3).ok_or_else(|| (ContractError::MathError))?;
+    // cap new oracle update to the market's percentage delta from twap
+    let oracle_price_cap = oracle_price
+        .checked_mul(a.oracle_twap_cap_percent as i128)
+        .ok_or(ContractError::MathError)?
+        .checked_div(100)
+        .ok_or(ContractError::MathError)?;
 
     let capped_oracle_update_price =
-        if new_oracle_price_spread.unsigned_abs() > oracle_price_33pct.unsigned_abs() {
+        if new_oracle_price_spread.unsigned_abs() > oracle_price_cap.unsigned_abs() {
             if oracle_price > a.last_oracle_price_twap.i128() {
                 a.last_oracle_price_twap.i128()
-                    .checked_add(oracle_price_33pct)
+                    .checked_add(oracle_price_cap)
                     .ok_or_else(|| (ContractError::MathError))?
             } else {
                 a.last_oracle_price_twap.i128()
-                    .checked_sub(oracle_price_33pct)
+                    .checked_sub(oracle_price_cap)
                     .ok_or_else(|| (ContractError::MathError))?
             }
         } else {
@@ -80,6 +91,23 @@ pub fn update_oracle_price_twap(
     Ok(oracle_price_twap)
 }
 
+/// Refreshes the oracle twap with the freshly queried oracle price, skipped while the oracle is invalid.
+pub fn update_oracle_price_twap_from_oracle(
+    deps: &mut DepsMut,
+    market_index: u64,
+    now: u64,
+    oracle_guard_rails: &OracleGuardRails,
+) -> Result<(), ContractError> {
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let oracle_price_data = market.amm.get_oracle_price()?;
+    if !amm::is_oracle_valid(&market.amm, &oracle_price_data, oracle_guard_rails)? {
+        return Ok(());
+    }
+    let normalised_oracle_price = amm::normalise_oracle_price(&market.amm, &oracle_price_data, None)?;
+    update_oracle_price_twap(deps, market_index, now, normalised_oracle_price)?;
+    Ok(())
+}
+
 /// To find the cost of adjusting k, compare the the net market value before and after adjusting k
 /// Increasing k costs the protocol money because it reduces slippage and improves the exit price for net market position
 /// Decreasing k costs the protocol money because it increases slippage and hurts the exit price for net market position
@@ -104,6 +132,8 @@ pub fn adjust_k_cost(deps: &mut DepsMut, market_index: u64, new_sqrt_k: Uint128)
         return Err(ContractError::InvalidUpdateK.into());
     }
     let new_sqrt_k_val= new_sqrt_k;
+    // scaling both reserves keeps the mark price (and a concentrated range) in place for every
+    // curve type and scales a stableswap invariant with k, so the cost is priced along the curve
     let new_base_asset_reserve = Uint128::from(market.amm.base_asset_reserve)
         .checked_mul(sqrt_k_ratio)?
         .checked_div(ratio_scalar)?;
@@ -129,6 +159,77 @@ pub fn adjust_k_cost(deps: &mut DepsMut, market_index: u64, new_sqrt_k: Uint128)
     Ok(cost)
 }
 
+/// Grows k while the fee pool and open interest have grown since the last k update, and shrinks it
+/// once the fee pool has drawn down below that level. Returns the adjustment cost when k was moved.
+pub fn formulaic_update_k(
+    deps: &mut DepsMut,
+    market_index: u64,
+    now: u64,
+) -> Result<Option<i128>, ContractError> {
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    if !market.amm.dynamic_k_enabled {
+        return Ok(None);
+    }
+
+    // the clearing house's share of fees is never spent on k adjustments
+    let protected_fees = market
+        .amm
+        .total_fee
+        .checked_mul(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR)?
+        .checked_div(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR)?;
+    let total_fee_minus_distributions = market.amm.total_fee_minus_distributions;
+    let sqrt_k = market.amm.sqrt_k;
+    let step = sqrt_k
+        .checked_mul(DYNAMIC_K_STEP_NUMERATOR)?
+        .checked_div(DYNAMIC_K_STEP_DENOMINATOR)?;
+
+    let last_total_fee_minus_distributions = market.amm.last_k_update_total_fee_minus_distributions;
+    let new_sqrt_k = if total_fee_minus_distributions > last_total_fee_minus_distributions
+        && total_fee_minus_distributions > protected_fees
+        && market.open_interest > market.amm.last_k_update_open_interest
+    {
+        min(sqrt_k.checked_add(step)?, market.amm.maximum_sqrt_k)
+    } else if total_fee_minus_distributions < last_total_fee_minus_distributions {
+        max(sqrt_k.saturating_sub(step), market.amm.minimum_sqrt_k)
+    } else {
+        return Ok(None);
+    };
+    if new_sqrt_k == sqrt_k {
+        return Ok(None);
+    }
+
+    let budget = total_fee_minus_distributions.saturating_sub(protected_fees);
+    let adjustment_cost = adjust_k_cost(deps, market_index, new_sqrt_k)?;
+    if adjustment_cost > 0 && adjustment_cost.unsigned_abs() > budget.u128() {
+        // adjust_k_cost already stored the new reserves, put the market back as it was
+        MARKETS.save(deps.storage, market_index.to_string(), &market)?;
+        return Ok(None);
+    }
+
+    let mut market_after = MARKETS.load(deps.storage, market_index.to_string())?;
+    market_after.amm.total_fee_minus_distributions = if adjustment_cost > 0 {
+        total_fee_minus_distributions.checked_sub(Uint128::from(adjustment_cost.unsigned_abs()))?
+    } else {
+        total_fee_minus_distributions.checked_add(Uint128::from(adjustment_cost.unsigned_abs()))?
+    };
+    market_after.amm.last_k_update_open_interest = market_after.open_interest;
+    market_after.amm.last_k_update_total_fee_minus_distributions =
+        market_after.amm.total_fee_minus_distributions;
+    MARKETS.save(deps.storage, market_index.to_string(), &market_after)?;
+
+    record_curve_history(
+        deps,
+        now,
+        market_index,
+        Type::UpdateK,
+        &market,
+        &market_after,
+        adjustment_cost,
+    )?;
+
+    Ok(Some(adjustment_cost))
+}
+
 pub fn swap_quote_asset(
     deps: &mut DepsMut,
     market_index: u64,
@@ -136,7 +237,8 @@ pub fn swap_quote_asset(
     direction: SwapDirection,
     now: u64,
     precomputed_mark_price: Option<Uint128>,
-) -> Result<i128, ContractError> {
+    reduces_position: bool,
+) -> Result<(i128, Uint128), ContractError> {
     let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
     let a = market.amm.clone();
     update_mark_twap(deps, market_index, now, precomputed_mark_price)?;
@@ -147,16 +249,32 @@ pub fn swap_quote_asset(
         return Err(ContractError::TradeSizeTooSmall);
     }
 
+    // longs swap less quote and shorts more, so both receive a worse price than the curve's
+    let position_direction = match direction {
+        SwapDirection::Add => PositionDirection::Long,
+        SwapDirection::Remove => PositionDirection::Short,
+    };
+    let spread = amm::calculate_spread(&a, market.base_asset_amount.i128(), position_direction)?;
+    let spread_reserve_amount = quote_asset_reserve_amount
+        .checked_mul(Uint128::from(spread))?
+        .checked_div(PRICE_SPREAD_PRECISION_U128)?;
+    let quote_asset_reserve_amount = match direction {
+        SwapDirection::Add => quote_asset_reserve_amount.checked_sub(spread_reserve_amount)?,
+        SwapDirection::Remove => quote_asset_reserve_amount.checked_add(spread_reserve_amount)?,
+    };
+    let spread_revenue = reserve_to_asset_amount(spread_reserve_amount, a.peg_multiplier)?;
+
     let initial_base_asset_reserve = a.base_asset_reserve;
     let (new_base_asset_reserve, new_quote_asset_reserve) = amm::calculate_swap_output(
+        &a,
         quote_asset_reserve_amount,
         a.quote_asset_reserve,
         direction,
-        a.sqrt_k,
     )?;
 
     market.amm.base_asset_reserve = new_base_asset_reserve;
     market.amm.quote_asset_reserve = new_quote_asset_reserve;
+    amm::validate_curve_price_range(&market.amm, amm::get_mark_price(&a)?, reduces_position)?;
 
     let base_asset_amount = (initial_base_asset_reserve.u128() as i128)
         .checked_sub(new_base_asset_reserve.u128() as i128)
@@ -166,7 +284,7 @@ pub fn swap_quote_asset(
         Ok(market)
     })?;
 
-    return Ok(base_asset_amount);
+    return Ok((base_asset_amount, spread_revenue));
 }
 
 pub fn swap_base_asset(
@@ -175,8 +293,9 @@ pub fn swap_base_asset(
     base_asset_swap_amount: Uint128,
     direction: SwapDirection,
     now: u64,
-    precomputed_mark_price: Option<Uint128>
-) -> Result<Uint128, ContractError> {
+    precomputed_mark_price: Option<Uint128>,
+    reduces_position: bool,
+) -> Result<(Uint128, Uint128), ContractError> {
     let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
     let a = market.amm.clone();
     
@@ -184,26 +303,50 @@ pub fn swap_base_asset(
 
     let initial_quote_asset_reserve = a.quote_asset_reserve;
     let (new_quote_asset_reserve, new_base_asset_reserve) = amm::calculate_swap_output(
+        &a,
         base_asset_swap_amount,
         a.base_asset_reserve,
         direction,
-        a.sqrt_k,
     )?;
 
     market.amm.base_asset_reserve = new_base_asset_reserve;
     market.amm.quote_asset_reserve = new_quote_asset_reserve;
+    amm::validate_curve_price_range(&market.amm, amm::get_mark_price(&a)?, reduces_position)?;
 
-    MARKETS.update(deps.storage, market_index.to_string(), |_m| -> Result<Market, ContractError> {
-        Ok(market)
-    })?;
-
-    calculate_quote_asset_amount_swapped(
+    let quote_asset_amount = calculate_quote_asset_amount_swapped(
         initial_quote_asset_reserve,
         new_quote_asset_reserve,
         direction,
         a.peg_multiplier,
-    )
+    )?;
+
+    // sellers receive less quote and buyers pay more than the curve's price
+    let position_direction = match direction {
+        SwapDirection::Add => PositionDirection::Short,
+        SwapDirection::Remove => PositionDirection::Long,
+    };
+    let spread = amm::calculate_spread(&a, market.base_asset_amount.i128(), position_direction)?;
+    let spread_amount = quote_asset_amount
+        .checked_mul(Uint128::from(spread))?
+        .checked_div(PRICE_SPREAD_PRECISION_U128)?;
+
+    MARKETS.update(deps.storage, market_index.to_string(), |_m| -> Result<Market, ContractError> {
+        Ok(market)
+    })?;
+
+    match direction {
+        SwapDirection::Add => Ok((quote_asset_amount.checked_sub(spread_amount)?, spread_amount)),
+        SwapDirection::Remove => Ok((quote_asset_amount.checked_add(spread_amount)?, spread_amount)),
+    }
+}
 
+/// Credits the spread charged on a swap to the market's fees, like a trading fee.
+pub fn credit_spread_revenue(market: &mut Market, spread_revenue: Uint128) -> Result<(), ContractError> {
+    market.amm.total_spread_revenue = market.amm.total_spread_revenue.checked_add(spread_revenue)?;
+    market.amm.total_fee = market.amm.total_fee.checked_add(spread_revenue)?;
+    market.amm.total_fee_minus_distributions =
+        market.amm.total_fee_minus_distributions.checked_add(spread_revenue)?;
+    Ok(())
 }
 
 pub fn move_price(
@@ -226,3 +369,58 @@ pub fn move_price(
     })?;
     Ok(())
 }
+
+pub fn record_curve_history(
+    deps: &mut DepsMut,
+    now: u64,
+    market_index: u64,
+    record_type: Type,
+    market_before: &Market,
+    market_after: &Market,
+    adjustment_cost: i128,
+) -> Result<(), ContractError> {
+    let curve_history_info_length = CURVE_HISTORY_INFO
+        .load(deps.storage)?
+        .len
+        .checked_add(1)
+        .ok_or(ContractError::MathError)?;
+    CURVE_HISTORY_INFO.update(
+        deps.storage,
+        |mut i: CurveInfo| -> Result<CurveInfo, ContractError> {
+            i.len = curve_history_info_length;
+            Ok(i)
+        },
+    )?;
+
+    CURVEHISTORY.save(
+        deps.storage,
+        curve_history_info_length.to_string(),
+        &CurveRecord {
+            ts: now,
+            record_id: curve_history_info_length,
+            market_index,
+            record_type,
+            peg_multiplier_before: market_before.amm.peg_multiplier,
+            base_asset_reserve_before: market_before.amm.base_asset_reserve,
+            quote_asset_reserve_before: market_before.amm.quote_asset_reserve,
+            sqrt_k_before: market_before.amm.sqrt_k,
+            peg_multiplier_after: market_after.amm.peg_multiplier,
+            base_asset_reserve_after: market_after.amm.base_asset_reserve,
+            quote_asset_reserve_after: market_after.amm.quote_asset_reserve,
+            sqrt_k_after: market_after.amm.sqrt_k,
+            base_asset_amount_long: Uint128::from(
+                market_after.base_asset_amount_long.i128().unsigned_abs(),
+            ),
+            base_asset_amount_short: Uint128::from(
+                market_after.base_asset_amount_short.i128().unsigned_abs(),
+            ),
+            base_asset_amount: market_after.base_asset_amount,
+            open_interest: market_after.open_interest,
+            total_fee: market_after.amm.total_fee,
+            total_fee_minus_distributions: market_after.amm.total_fee_minus_distributions,
+            adjustment_cost: Number128::new(adjustment_cost),
+            oracle_price: market_after.amm.get_oracle_price()?.price,
+        },
+    )?;
+    Ok(())
+}
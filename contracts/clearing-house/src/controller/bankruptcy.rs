@@ -0,0 +1,145 @@
+use std::cmp::min;
+
+use cosmwasm_std::{to_binary, Addr, CosmosMsg, DepsMut, Order, Uint128, WasmMsg};
+
+use ariel::helper::{query_balance, VaultInterface};
+use ariel::number::Number128;
+
+use crate::error::ContractError;
+use crate::helpers::liquidation::calculate_socialized_loss_funding_rate_delta;
+use crate::states::history::{
+    BankruptcyInfo, BankruptcyRecord, BANKRUPTCY_HISTORY, BANKRUPTCY_HISTORY_INFO,
+};
+use crate::states::liquidation::PENDING_BANKRUPTCY_LOSSES;
+use crate::states::market::{Market, MARKETS};
+use crate::states::state::STATE;
+
+pub fn record_bankruptcy_loss(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    market_index: u64,
+    loss: Uint128,
+) -> Result<(), ContractError> {
+    if loss.is_zero() {
+        return Ok(());
+    }
+    PENDING_BANKRUPTCY_LOSSES.update(
+        deps.storage,
+        (user_addr, market_index.to_string()),
+        |l| -> Result<Uint128, ContractError> {
+            Ok(l.unwrap_or_default().checked_add(loss)?)
+        },
+    )?;
+    Ok(())
+}
+
+/// Settles the losses a user could not pay for. The insurance vault covers what it can,
+/// the rest is charged to the market's open interest through the cumulative funding rates.
+/// Returns the messages moving insurance funds into the collateral vault.
+pub fn resolve_bankruptcy(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    now: u64,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let pending_losses: Vec<(u64, Uint128)> = PENDING_BANKRUPTCY_LOSSES
+        .prefix(user_addr)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (k, loss) = item?;
+            let market_index = String::from_utf8(k)
+                .ok()
+                .and_then(|m| m.parse::<u64>().ok())
+                .ok_or(ContractError::MathError)?;
+            Ok((market_index, loss))
+        })
+        .collect::<Result<Vec<_>, ContractError>>()?;
+    if pending_losses.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let state = STATE.load(deps.storage)?;
+    let mut insurance_balance =
+        Uint128::from(query_balance(&deps.querier, state.insurance_vault.clone())?);
+    let mut messages: Vec<CosmosMsg> = vec![];
+
+    for (market_index, loss) in pending_losses {
+        let insurance_fund_paid = min(loss, insurance_balance);
+        insurance_balance = insurance_balance.checked_sub(insurance_fund_paid)?;
+        if !insurance_fund_paid.is_zero() {
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: state.insurance_vault.to_string(),
+                msg: to_binary(&VaultInterface::Withdraw {
+                    to_address: state.collateral_vault.clone(),
+                    amount: insurance_fund_paid.u128(),
+                })?,
+                funds: vec![],
+            }));
+        }
+
+        let mut socialized_loss = loss.checked_sub(insurance_fund_paid)?;
+        let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+        let cumulative_funding_rate_delta = calculate_socialized_loss_funding_rate_delta(
+            socialized_loss,
+            market.base_asset_amount_long.i128(),
+            market.base_asset_amount_short.i128(),
+        )?;
+        if cumulative_funding_rate_delta.is_zero() {
+            // nobody left to charge
+            socialized_loss = Uint128::zero();
+        } else {
+            let delta = cumulative_funding_rate_delta.u128() as i128;
+            market.amm.cumulative_funding_rate_long = Number128::new(
+                market
+                    .amm
+                    .cumulative_funding_rate_long
+                    .i128()
+                    .checked_add(delta)
+                    .ok_or(ContractError::MathError)?,
+            );
+            market.amm.cumulative_funding_rate_short = Number128::new(
+                market
+                    .amm
+                    .cumulative_funding_rate_short
+                    .i128()
+                    .checked_sub(delta)
+                    .ok_or(ContractError::MathError)?,
+            );
+            MARKETS.update(
+                deps.storage,
+                market_index.to_string(),
+                |_m| -> Result<Market, ContractError> { Ok(market) },
+            )?;
+        }
+
+        PENDING_BANKRUPTCY_LOSSES.remove(deps.storage, (user_addr, market_index.to_string()));
+
+        let bankruptcy_history_info_length = BANKRUPTCY_HISTORY_INFO
+            .load(deps.storage)?
+            .len
+            .checked_add(1)
+            .ok_or(ContractError::MathError)?;
+        BANKRUPTCY_HISTORY_INFO.update(
+            deps.storage,
+            |mut i| -> Result<BankruptcyInfo, ContractError> {
+                i.len = bankruptcy_history_info_length;
+                Ok(i)
+            },
+        )?;
+        BANKRUPTCY_HISTORY.save(
+            deps.storage,
+            (user_addr, bankruptcy_history_info_length.to_string()),
+            &BankruptcyRecord {
+                ts: now,
+                record_id: bankruptcy_history_info_length,
+                user: user_addr.clone(),
+                market_index,
+                loss,
+                insurance_fund_paid,
+                socialized_loss,
+                cumulative_funding_rate_delta,
+            },
+        )?;
+    }
+
+    Ok(messages)
+}
@@ -1,4 +1,4 @@
-use std::cmp::max;
+use std::cmp::min;
 
 use ariel::number::Number128;
 use cosmwasm_std::Addr;
@@ -14,17 +14,23 @@ use crate::states::history::{
 };
 use crate::states::market::{Market, MARKETS};
 use crate::states::state::ORACLEGUARDRAILS;
-use crate::states::state::STATE;
-use crate::states::user::{Position, POSITIONS, User, USERS};
+use crate::states::user::{get_open_positions, Position, POSITIONS, User, USERS};
 
 use crate::helpers::position::calculate_updated_collateral;
 use crate::states::constants::{
-    AMM_TO_QUOTE_PRECISION_RATIO_I128, FUNDING_PAYMENT_PRECISION, ONE_HOUR,
+    AMM_TO_QUOTE_PRECISION_RATIO_I128, MAX_FUNDING_UPDATE_REWARD,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR,
+    SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR,
+};
+use crate::helpers::funding::{
+    calculate_funding_payment, calculate_funding_rate, calculate_funding_rate_long_short,
+    calculate_next_update_wait, calculate_period_adjustment, calculate_premium,
+    sample_premium_index,
 };
-use crate::helpers::funding::{calculate_funding_payment, calculate_funding_rate_long_short};
 use crate::helpers::oracle;
 
 use crate::controller::amm;
+use crate::controller::repeg::formulaic_repeg;
 
 /// Funding payments are settled lazily. The amm tracks its cumulative funding rate (for longs and shorts)
 /// and the user's market position tracks how much funding the user been cumulatively paid for that market.
@@ -43,68 +49,58 @@ pub fn settle_funding_payment(
     else{
         return Ok(());
     }
-    let markets_length = STATE.load(deps.storage)?.markets_length;
-    for n in 1..markets_length {
-        let market_position = POSITIONS.load(deps.storage, (user_addr, n.to_string()));
-        match market_position {
-            Ok(mut m) => {
-                if m.base_asset_amount.i128() == 0 {
-                    continue;
-                }
-                let market = MARKETS.load(deps.storage, n.to_string())?;
-                let amm_cumulative_funding_rate = if m.base_asset_amount.i128() > 0 {
-                    market.amm.cumulative_funding_rate_long.i128()
-                } else {
-                    market.amm.cumulative_funding_rate_short.i128()
-                };
-                if amm_cumulative_funding_rate != m.last_cumulative_funding_rate.i128() {
-                    let market_funding_rate_payment =
-                        calculate_funding_payment(amm_cumulative_funding_rate, &m)?;
-                    let funding_payment_history_info_length = FUNDING_PAYMENT_HISTORY_INFO
-                        .load(deps.storage)?
-                        .len
-                        .checked_add(1)
-                        .ok_or_else(|| (ContractError::MathError))?;
-                    FUNDING_PAYMENT_HISTORY_INFO.update(
-                        deps.storage,
-                        |mut i| -> Result<FundingPaymentInfo, ContractError> {
-                            i.len = funding_payment_history_info_length;
-                            Ok(i)
-                        },
-                    )?;
-                    FUNDING_PAYMENT_HISTORY.save(
-                        deps.storage,
-                        (user_addr, funding_payment_history_info_length.to_string()),
-                        &FundingPaymentRecord {
-                            ts: now,
-                            record_id: funding_payment_history_info_length,
-                            user: user_addr.clone(),
-                            market_index: n,
-                            funding_payment: Number128::new(market_funding_rate_payment), //10e13
-                            user_last_cumulative_funding: m.last_cumulative_funding_rate, //10e14
-                            user_last_funding_rate_ts: m.last_funding_rate_ts,
-                            amm_cumulative_funding_long: market.amm.cumulative_funding_rate_long, //10e14
-                            amm_cumulative_funding_short: market.amm.cumulative_funding_rate_short, //10e14
-                            base_asset_amount: m.base_asset_amount,
-                        },
-                    )?;
-                    funding_payment = funding_payment
-                        .checked_add(market_funding_rate_payment)
-                        .ok_or_else(|| (ContractError::MathError))?;
-        
-                    m.last_cumulative_funding_rate = Number128::new(amm_cumulative_funding_rate);
-                    m.last_funding_rate_ts = market.amm.last_funding_rate_ts;
-        
-                    POSITIONS.update(
-                        deps.storage,
-                        (user_addr, n.to_string()),
-                        |_p| -> Result<Position, ContractError> { Ok(m) },
-                    )?;
-                }
-            }
-            Err(_) => continue, 
+    for mut m in get_open_positions(deps.storage, user_addr)? {
+        let n = m.market_index;
+        let market = MARKETS.load(deps.storage, n.to_string())?;
+        let amm_cumulative_funding_rate = if m.base_asset_amount.i128() > 0 {
+            market.amm.cumulative_funding_rate_long.i128()
+        } else {
+            market.amm.cumulative_funding_rate_short.i128()
+        };
+        if amm_cumulative_funding_rate != m.last_cumulative_funding_rate.i128() {
+            let market_funding_rate_payment =
+                calculate_funding_payment(amm_cumulative_funding_rate, &m)?;
+            let funding_payment_history_info_length = FUNDING_PAYMENT_HISTORY_INFO
+                .load(deps.storage)?
+                .len
+                .checked_add(1)
+                .ok_or(ContractError::MathError)?;
+            FUNDING_PAYMENT_HISTORY_INFO.update(
+                deps.storage,
+                |mut i| -> Result<FundingPaymentInfo, ContractError> {
+                    i.len = funding_payment_history_info_length;
+                    Ok(i)
+                },
+            )?;
+            FUNDING_PAYMENT_HISTORY.save(
+                deps.storage,
+                (user_addr, funding_payment_history_info_length.to_string()),
+                &FundingPaymentRecord {
+                    ts: now,
+                    record_id: funding_payment_history_info_length,
+                    user: user_addr.clone(),
+                    market_index: n,
+                    funding_payment: Number128::new(market_funding_rate_payment), //10e13
+                    user_last_cumulative_funding: m.last_cumulative_funding_rate, //10e14
+                    user_last_funding_rate_ts: m.last_funding_rate_ts,
+                    amm_cumulative_funding_long: market.amm.cumulative_funding_rate_long, //10e14
+                    amm_cumulative_funding_short: market.amm.cumulative_funding_rate_short, //10e14
+                    base_asset_amount: m.base_asset_amount,
+                },
+            )?;
+            funding_payment = funding_payment
+                .checked_add(market_funding_rate_payment)
+                .ok_or(ContractError::MathError)?;
+
+            m.last_cumulative_funding_rate = Number128::new(amm_cumulative_funding_rate);
+            m.last_funding_rate_ts = market.amm.last_funding_rate_ts;
+
+            POSITIONS.update(
+                deps.storage,
+                (user_addr, n.to_string()),
+                |_p| -> Result<Position, ContractError> { Ok(m) },
+            )?;
         }
-        
     }
 
     let funding_payment_collateral = funding_payment
@@ -146,66 +142,43 @@ pub fn update_funding_rate(
     let normalised_oracle_price =
         normalise_oracle_price(&market.amm, &oracle_price_data, precomputed_mark_price)?;
 
-    // round next update time to be available on the hour
-    let mut next_update_wait = market.amm.funding_period;
-    if market.amm.funding_period > 1 {
-        let last_update_delay = market
-            .amm
-            .last_funding_rate_ts
-            .rem_euclid(market.amm.funding_period);
-        if last_update_delay != 0 {
-            let max_delay_for_next_period = market
-                .amm
-                .funding_period
-                .checked_div(3)
-                .ok_or_else(|| (ContractError::MathError))?;
-            if last_update_delay > max_delay_for_next_period {
-                // too late for on the hour next period, delay to following period
-                next_update_wait = market
-                    .amm
-                    .funding_period
-                    .checked_mul(2)
-                    .ok_or_else(|| (ContractError::MathError))?
-                    .checked_sub(last_update_delay)
-                    .ok_or_else(|| (ContractError::MathError))?;
-            } else {
-                // allow update on the hour
-                next_update_wait = market
-                    .amm
-                    .funding_period
-                    .checked_sub(last_update_delay)
-                    .ok_or_else(|| (ContractError::MathError))?;
-            }
-        }
+    // sample the mark/oracle premium, the previous sample is weighted by the time it held
+    if !block_funding_rate_update {
+        let mark_price = match precomputed_mark_price {
+            Some(mark_price) => mark_price,
+            None => market.amm.mark_price()?,
+        };
+        sample_premium_index(&mut market.amm, mark_price, normalised_oracle_price, now)?;
     }
 
+    let next_update_wait = calculate_next_update_wait(&market.amm)?;
+
     if !funding_paused && !block_funding_rate_update && time_since_last_update >= next_update_wait {
+        // the twap updates load and save the market themselves, so keep their results
+        MARKETS.save(deps.storage, market_index.to_string(), &market)?;
         let oracle_price_twap =
             amm::update_oracle_price_twap(deps, market_index, now, normalised_oracle_price)?;
         let mark_price_twap = amm::update_mark_twap(deps, market_index, now, None)?;
+        market = MARKETS.load(deps.storage, market_index.to_string())?;
 
-        let one_hour_i64 = ONE_HOUR.u128() as i64;
-        let period_adjustment = (24_i64)
-            .checked_mul(one_hour_i64)
-            .ok_or_else(|| (ContractError::MathError))?
-            .checked_div(max(one_hour_i64, market.amm.funding_period as i64))
-            .ok_or_else(|| (ContractError::MathError))?;
+        let period_adjustment = calculate_period_adjustment(market.amm.funding_period)?;
 
         // funding period = 1 hour, window = 1 day
         // low periodicity => quickly updating/settled funding rates => lower funding rate payment per interval
-        let price_spread = (mark_price_twap.u128()  as i128)
-            .checked_sub(oracle_price_twap).ok_or_else(|| (ContractError::MathError))?;
+        let premium = calculate_premium(&market.amm, mark_price_twap, oracle_price_twap)?;
+        market.amm.premium_index_sum = Number128::zero();
+        market.amm.premium_index_duration = 0;
 
-        let funding_rate = price_spread
-            .checked_mul(FUNDING_PAYMENT_PRECISION.u128() as i128)
-            .ok_or_else(|| (ContractError::MathError))?
-            .checked_div(period_adjustment as i128)
-            .ok_or_else(|| (ContractError::MathError))?;
+        let funding_rate =
+            calculate_funding_rate(&market.amm, premium, oracle_price_twap, period_adjustment)?;
 
-        let (funding_rate_long, funding_rate_short, new_total_fee_minus_distributions) =
+        let (funding_rate_long, funding_rate_short, funding_pool_update) =
             calculate_funding_rate_long_short(&market, funding_rate)?;
 
-        market.amm.total_fee_minus_distributions = new_total_fee_minus_distributions;
+        market.amm.total_fee_minus_distributions =
+            funding_pool_update.total_fee_minus_distributions;
+        market.amm.funding_pool = funding_pool_update.funding_pool;
+        market.amm.funding_shortfall = funding_pool_update.funding_shortfall;
 
         market.amm.cumulative_funding_rate_long = Number128::new(market
             .amm
@@ -252,9 +225,57 @@ pub fn update_funding_rate(
                 cumulative_funding_rate_short: market.amm.cumulative_funding_rate_short,
                 mark_price_twap,
                 oracle_price_twap: Number128::new(oracle_price_twap),
+                capped_funding_amount: funding_pool_update.capped_funding_amount,
+                funding_pool: market.amm.funding_pool,
+                funding_shortfall: market.amm.funding_shortfall,
             },
         )?;
+
+        // with fresh twaps, pull the peg toward the oracle while the fee pool allows
+        formulaic_repeg(deps, market_index, now)?;
+        amm::formulaic_update_k(deps, market_index, now)?;
+    } else {
+        MARKETS.save(deps.storage, market_index.to_string(), &market)?;
     };
 
     Ok(())
 }
+
+/// Pays whoever advanced the funding rate a reward from the market's distributable fees, at most
+/// `MAX_FUNDING_UPDATE_REWARD` per funding period. Callers without a user account are not paid.
+pub fn pay_funding_update_reward(
+    deps: &mut DepsMut,
+    market_index: u64,
+    keeper: &Addr,
+) -> Result<Uint128, ContractError> {
+    let mut keeper_user = match USERS.may_load(deps.storage, keeper)? {
+        Some(user) => user,
+        None => return Ok(Uint128::zero()),
+    };
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+
+    let total_fee_minus_distributions_lower_bound = market
+        .amm
+        .total_fee
+        .checked_mul(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR)?
+        .checked_div(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR)?;
+    let reward = min(
+        MAX_FUNDING_UPDATE_REWARD,
+        market
+            .amm
+            .total_fee_minus_distributions
+            .saturating_sub(total_fee_minus_distributions_lower_bound),
+    );
+    if reward.is_zero() {
+        return Ok(reward);
+    }
+
+    market.amm.total_fee_minus_distributions =
+        market.amm.total_fee_minus_distributions.checked_sub(reward)?;
+    MARKETS.save(deps.storage, market_index.to_string(), &market)?;
+
+    keeper_user.collateral = keeper_user.collateral.checked_add(reward)?;
+    USERS.save(deps.storage, keeper, &keeper_user)?;
+
+    Ok(reward)
+}
@@ -0,0 +1,476 @@
+use std::cmp::min;
+
+use cosmwasm_std::{Addr, DepsMut, Uint128};
+
+use ariel::number::Number128;
+use ariel::types::{LiquidatedMarket, PositionDirection};
+
+use crate::error::ContractError;
+use crate::helpers::liquidation::{
+    calculate_auction_discount, calculate_auction_price, calculate_liquidatable_base_asset_amount,
+    is_price_within_liquidation_slippage,
+};
+use crate::helpers::oracle::get_oracle_status;
+use crate::helpers::order::calculate_quote_asset_amount_for_maker_order;
+use crate::helpers::position::direction_to_close_position;
+use crate::states::history::{
+    LiquidationInfo, LiquidationRecord, TradeInfo, TradeRecord, LIQUIDATION_HISTORY,
+    LIQUIDATION_HISTORY_INFO, TRADE_HISTORY, TRADE_HISTORY_INFO,
+};
+use crate::states::liquidation::{
+    LiquidationAuction, LIQUIDATION_AUCTIONS, LIQUIDATION_AUCTION_CONFIG,
+};
+use crate::states::market::{LiquidationStatus, LiquidationType, MARKETS};
+use crate::states::state::{ORACLEGUARDRAILS, STATE};
+use crate::states::user::{POSITIONS, USERS};
+
+use super::amm::update_oracle_price_twap_from_oracle;
+use super::funding::settle_funding_payment;
+use super::margin::{calculate_liquidation_status, meets_initial_margin_requirement};
+use super::position::{add_new_position, update_position_with_matched_base_asset_amount};
+
+/// Opens a dutch auction for the liquidatable part of a user's position in a market.
+pub fn start_liquidation_auction(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    market_index: u64,
+    now: u64,
+) -> Result<LiquidationAuction, ContractError> {
+    let config = LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?;
+    if !config.enabled {
+        return Err(ContractError::LiquidationAuctionsDisabled);
+    }
+    if let Some(auction) =
+        LIQUIDATION_AUCTIONS.may_load(deps.storage, (user_addr, market_index.to_string()))?
+    {
+        if now < auction.end_ts {
+            return Err(ContractError::LiquidationAuctionInProgress);
+        }
+    }
+
+    settle_funding_payment(deps, user_addr, now)?;
+
+    let LiquidationStatus {
+        liquidation_type, ..
+    } = calculate_liquidation_status(deps, user_addr)?;
+    if liquidation_type == LiquidationType::NONE {
+        return Err(ContractError::SufficientCollateral);
+    }
+
+    let market_position = POSITIONS
+        .may_load(deps.storage, (user_addr, market_index.to_string()))?
+        .ok_or(ContractError::UserHasNoPositionInMarket)?;
+    let position_base_asset_amount = market_position.base_asset_amount.i128();
+    if position_base_asset_amount == 0 {
+        return Err(ContractError::UserHasNoPositionInMarket);
+    }
+
+    let base_asset_amount = calculate_liquidatable_base_asset_amount(
+        position_base_asset_amount,
+        &liquidation_type,
+        STATE.load(deps.storage)?.partial_liquidation_close_percentage,
+    )?;
+
+    let auction = LiquidationAuction {
+        direction: if position_base_asset_amount > 0 {
+            PositionDirection::Long
+        } else {
+            PositionDirection::Short
+        },
+        base_asset_amount,
+        partial: liquidation_type == LiquidationType::PARTIAL,
+        start_ts: now,
+        end_ts: now.checked_add(config.duration).ok_or(ContractError::MathError)?,
+    };
+    LIQUIDATION_AUCTIONS.save(
+        deps.storage,
+        (user_addr, market_index.to_string()),
+        &auction,
+    )?;
+    Ok(auction)
+}
+
+/// Takes over up to `max_base_asset_amount` of an auctioned position at the current auction price.
+/// Returns the base asset amount taken over.
+pub fn bid_liquidation_auction(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    liquidator_addr: &Addr,
+    market_index: u64,
+    max_base_asset_amount: Uint128,
+    now: u64,
+) -> Result<Uint128, ContractError> {
+    if user_addr == liquidator_addr {
+        return Err(ContractError::UserCantLiquidateThemselves);
+    }
+    if USERS.may_load(deps.storage, liquidator_addr)?.is_none() {
+        return Err(ContractError::UserDoesNotExist);
+    }
+
+    let config = LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?;
+    let mut auction = LIQUIDATION_AUCTIONS
+        .may_load(deps.storage, (user_addr, market_index.to_string()))?
+        .ok_or(ContractError::LiquidationAuctionNotFound)?;
+    if now >= auction.end_ts {
+        return Err(ContractError::LiquidationAuctionExpired);
+    }
+
+    settle_funding_payment(deps, user_addr, now)?;
+    settle_funding_payment(deps, liquidator_addr, now)?;
+
+    let liquidation_status = calculate_liquidation_status(deps, user_addr)?;
+    if liquidation_status.liquidation_type == LiquidationType::NONE {
+        // the user recovered while the auction was running
+        LIQUIDATION_AUCTIONS.remove(deps.storage, (user_addr, market_index.to_string()));
+        return Err(ContractError::SufficientCollateral);
+    }
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+    update_oracle_price_twap_from_oracle(deps, market_index, now, &oracle_guard_rails)?;
+
+    let market_position = POSITIONS.load(deps.storage, (user_addr, market_index.to_string()))?;
+    let base_asset_amount = min(
+        min(max_base_asset_amount, auction.base_asset_amount),
+        Uint128::from(market_position.base_asset_amount.i128().unsigned_abs()),
+    );
+    if base_asset_amount.is_zero() {
+        return Err(ContractError::TradeSizeTooSmall);
+    }
+
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let mark_price = market.amm.mark_price()?;
+    let discount = calculate_auction_discount(&config, auction.start_ts, now)?;
+    let auction_price = calculate_auction_price(mark_price, discount, auction.direction)?;
+
+    let quote_asset_amount = transfer_position(
+        deps,
+        user_addr,
+        liquidator_addr,
+        market_index,
+        base_asset_amount,
+        auction_price,
+        mark_price,
+        market.amm.last_oracle_price,
+        now,
+    )?;
+    let mark_quote_asset_amount =
+        calculate_quote_asset_amount_for_maker_order(base_asset_amount, mark_price)?;
+
+    auction.base_asset_amount = auction.base_asset_amount.checked_sub(base_asset_amount)?;
+    if auction.base_asset_amount.is_zero() {
+        LIQUIDATION_AUCTIONS.remove(deps.storage, (user_addr, market_index.to_string()));
+    } else {
+        LIQUIDATION_AUCTIONS.save(
+            deps.storage,
+            (user_addr, market_index.to_string()),
+            &auction,
+        )?;
+    }
+
+    // value handed to the liquidator through the discounted price
+    let liquidation_fee = abs_diff(mark_quote_asset_amount, quote_asset_amount)?;
+    record_liquidation(
+        deps,
+        user_addr,
+        liquidator_addr,
+        &liquidation_status,
+        auction.partial,
+        market_index,
+        base_asset_amount,
+        mark_quote_asset_amount,
+        liquidation_fee,
+        now,
+    )?;
+
+    Ok(base_asset_amount)
+}
+
+/// Moves up to `max_base_asset_amount` of a liquidatable position to the liquidator at
+/// `limit_price`, which has to be within the max liquidation slippage of the oracle price.
+/// Returns the base and quote asset amounts taken over.
+pub fn liquidate_perp(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    liquidator_addr: &Addr,
+    market_index: u64,
+    max_base_asset_amount: Uint128,
+    limit_price: Uint128,
+    now: u64,
+) -> Result<(Uint128, Uint128), ContractError> {
+    if user_addr == liquidator_addr {
+        return Err(ContractError::UserCantLiquidateThemselves);
+    }
+    if USERS.may_load(deps.storage, liquidator_addr)?.is_none() {
+        return Err(ContractError::UserDoesNotExist);
+    }
+    // a running auction owns the position, liquidators have to bid on it instead
+    if LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?.enabled {
+        if let Some(auction) =
+            LIQUIDATION_AUCTIONS.may_load(deps.storage, (user_addr, market_index.to_string()))?
+        {
+            if now < auction.end_ts {
+                return Err(ContractError::LiquidationAuctionInProgress);
+            }
+        }
+    }
+
+    settle_funding_payment(deps, user_addr, now)?;
+    settle_funding_payment(deps, liquidator_addr, now)?;
+
+    let liquidation_status = calculate_liquidation_status(deps, user_addr)?;
+    if liquidation_status.liquidation_type == LiquidationType::NONE {
+        return Err(ContractError::SufficientCollateral);
+    }
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+    update_oracle_price_twap_from_oracle(deps, market_index, now, &oracle_guard_rails)?;
+
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let mark_price = market.amm.mark_price()?;
+    let oracle_status = get_oracle_status(&market.amm, &oracle_guard_rails, Some(mark_price))?;
+    if !oracle_status.is_valid || oracle_status.price_data.price.i128() <= 0 {
+        return Err(ContractError::LiquidationsBlockedByOracle);
+    }
+    let oracle_price = Uint128::from(oracle_status.price_data.price.i128().unsigned_abs());
+    if !is_price_within_liquidation_slippage(limit_price, oracle_price)? {
+        return Err(ContractError::SlippageOutsideLimit);
+    }
+
+    let market_position = POSITIONS
+        .may_load(deps.storage, (user_addr, market_index.to_string()))?
+        .ok_or(ContractError::UserHasNoPositionInMarket)?;
+    let position_base_asset_amount = market_position.base_asset_amount.i128();
+    if position_base_asset_amount == 0 {
+        return Err(ContractError::UserHasNoPositionInMarket);
+    }
+
+    let liquidatable_base_asset_amount = calculate_liquidatable_base_asset_amount(
+        position_base_asset_amount,
+        &liquidation_status.liquidation_type,
+        STATE.load(deps.storage)?.partial_liquidation_close_percentage,
+    )?;
+    let base_asset_amount = min(max_base_asset_amount, liquidatable_base_asset_amount);
+    if base_asset_amount.is_zero() {
+        return Err(ContractError::TradeSizeTooSmall);
+    }
+
+    let quote_asset_amount = transfer_position(
+        deps,
+        user_addr,
+        liquidator_addr,
+        market_index,
+        base_asset_amount,
+        limit_price,
+        mark_price,
+        oracle_status.price_data.price,
+        now,
+    )?;
+    let oracle_quote_asset_amount =
+        calculate_quote_asset_amount_for_maker_order(base_asset_amount, oracle_price)?;
+
+    // value handed to the liquidator by taking over away from the oracle price
+    let liquidator_gets_better_price = if position_base_asset_amount > 0 {
+        quote_asset_amount < oracle_quote_asset_amount
+    } else {
+        quote_asset_amount > oracle_quote_asset_amount
+    };
+    let liquidation_fee = if liquidator_gets_better_price {
+        abs_diff(oracle_quote_asset_amount, quote_asset_amount)?
+    } else {
+        Uint128::zero()
+    };
+    record_liquidation(
+        deps,
+        user_addr,
+        liquidator_addr,
+        &liquidation_status,
+        liquidation_status.liquidation_type == LiquidationType::PARTIAL,
+        market_index,
+        base_asset_amount,
+        oracle_quote_asset_amount,
+        liquidation_fee,
+        now,
+    )?;
+
+    Ok((base_asset_amount, quote_asset_amount))
+}
+
+fn abs_diff(a: Uint128, b: Uint128) -> Result<Uint128, ContractError> {
+    if a > b {
+        Ok(a.checked_sub(b)?)
+    } else {
+        Ok(b.checked_sub(a)?)
+    }
+}
+
+/// Closes `base_asset_amount` of the user's position and opens it for the liquidator at `price`,
+/// without touching the amm. Returns the quote asset amount exchanged.
+fn transfer_position(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    liquidator_addr: &Addr,
+    market_index: u64,
+    base_asset_amount: Uint128,
+    price: Uint128,
+    mark_price: Uint128,
+    oracle_price: Number128,
+    now: u64,
+) -> Result<Uint128, ContractError> {
+    let quote_asset_amount = calculate_quote_asset_amount_for_maker_order(base_asset_amount, price)?;
+
+    if POSITIONS
+        .may_load(deps.storage, (liquidator_addr, market_index.to_string()))?
+        .is_none()
+    {
+        add_new_position(deps, liquidator_addr, market_index)?;
+    }
+
+    let market_position = POSITIONS.load(deps.storage, (user_addr, market_index.to_string()))?;
+    let direction_to_close = direction_to_close_position(market_position.base_asset_amount.i128());
+    let liquidator_direction = match direction_to_close {
+        PositionDirection::Long => PositionDirection::Short,
+        PositionDirection::Short => PositionDirection::Long,
+    };
+
+    update_position_with_matched_base_asset_amount(
+        deps,
+        base_asset_amount,
+        quote_asset_amount,
+        direction_to_close,
+        user_addr,
+        market_index,
+    )?;
+    let liquidator_risk_increasing = update_position_with_matched_base_asset_amount(
+        deps,
+        base_asset_amount,
+        quote_asset_amount,
+        liquidator_direction,
+        liquidator_addr,
+        market_index,
+    )?;
+    if liquidator_risk_increasing && !meets_initial_margin_requirement(deps, liquidator_addr)? {
+        return Err(ContractError::InsufficientCollateral);
+    }
+
+    record_liquidation_trade(
+        deps,
+        user_addr,
+        direction_to_close,
+        base_asset_amount,
+        quote_asset_amount,
+        mark_price,
+        oracle_price,
+        market_index,
+        now,
+    )?;
+    record_liquidation_trade(
+        deps,
+        liquidator_addr,
+        liquidator_direction,
+        base_asset_amount,
+        quote_asset_amount,
+        mark_price,
+        oracle_price,
+        market_index,
+        now,
+    )?;
+
+    Ok(quote_asset_amount)
+}
+
+fn record_liquidation_trade(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    direction: PositionDirection,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
+    mark_price: Uint128,
+    oracle_price: Number128,
+    market_index: u64,
+    now: u64,
+) -> Result<(), ContractError> {
+    let trade_history_info_length = TRADE_HISTORY_INFO
+        .load(deps.storage)?
+        .len
+        .checked_add(1)
+        .ok_or(ContractError::MathError)?;
+    TRADE_HISTORY_INFO.update(deps.storage, |mut i| -> Result<TradeInfo, ContractError> {
+        i.len = trade_history_info_length;
+        Ok(i)
+    })?;
+    TRADE_HISTORY.save(
+        deps.storage,
+        (user_addr, trade_history_info_length.to_string()),
+        &TradeRecord {
+            ts: now,
+            user: user_addr.clone(),
+            direction,
+            base_asset_amount,
+            quote_asset_amount,
+            mark_price_before: mark_price,
+            mark_price_after: mark_price,
+            fee: Uint128::zero(),
+            maker_fee: Number128::zero(),
+            referrer_reward: Uint128::zero(),
+            referee_discount: Uint128::zero(),
+            token_discount: Uint128::zero(),
+            liquidation: true,
+            market_index,
+            oracle_price,
+        },
+    )?;
+    Ok(())
+}
+
+fn record_liquidation(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    liquidator_addr: &Addr,
+    liquidation_status: &LiquidationStatus,
+    partial: bool,
+    market_index: u64,
+    base_asset_amount: Uint128,
+    base_asset_value_closed: Uint128,
+    liquidation_fee: Uint128,
+    now: u64,
+) -> Result<(), ContractError> {
+    let collateral = USERS.load(deps.storage, user_addr)?.collateral;
+    let liquidation_history_info_length = LIQUIDATION_HISTORY_INFO
+        .load(deps.storage)?
+        .len
+        .checked_add(1)
+        .ok_or(ContractError::MathError)?;
+    LIQUIDATION_HISTORY_INFO.update(
+        deps.storage,
+        |mut i| -> Result<LiquidationInfo, ContractError> {
+            i.len = liquidation_history_info_length;
+            Ok(i)
+        },
+    )?;
+    LIQUIDATION_HISTORY.save(
+        deps.storage,
+        (user_addr.clone(), liquidation_history_info_length.to_string()),
+        &LiquidationRecord {
+            ts: now,
+            record_id: liquidation_history_info_length,
+            user: user_addr.clone(),
+            market_index,
+            partial,
+            markets: vec![LiquidatedMarket {
+                market_index,
+                base_asset_amount,
+                base_asset_value_closed,
+                liquidation_fee,
+            }],
+            base_asset_value: liquidation_status.base_asset_value,
+            base_asset_value_closed,
+            liquidation_fee,
+            liquidator: liquidator_addr.clone(),
+            total_collateral: liquidation_status.total_collateral,
+            collateral,
+            unrealized_pnl: Number128::new(liquidation_status.unrealized_pnl),
+            margin_ratio: liquidation_status.margin_ratio,
+            fee_to_liquidator: liquidation_fee.u128() as u64,
+            fee_to_insurance_fund: 0,
+        },
+    )?;
+    Ok(())
+}
@@ -1,6 +1,10 @@
 pub mod amm;
+pub mod bankruptcy;
 pub mod funding;
+pub mod liquidation;
 pub mod margin;
 pub mod order;
 pub mod position;
-pub mod repeg;
\ No newline at end of file
+pub mod referral;
+pub mod repeg;
+pub mod volume;
\ No newline at end of file
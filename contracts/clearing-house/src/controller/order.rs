@@ -1,16 +1,21 @@
 use crate::error::ContractError;
 use crate::helpers::position::{calculate_updated_collateral, asset_to_reserve_amount};
-use crate::helpers::fees::{calculate_order_fee_tier, calculate_fee_for_order};
-use crate::helpers::order::{validate_order, validate_order_can_be_canceled, calculate_base_asset_amount_market_can_execute, limit_price_satisfied};
+use crate::helpers::fees::{calculate_fee_for_order, calculate_fee_for_matched_order, calculate_maker_fee};
+use crate::controller::referral::{accrue_referral_reward, get_referral_fee_structure};
+use crate::controller::volume::{get_user_fee_tier, record_user_volume};
+use crate::helpers::order::{validate_order, validate_order_can_be_canceled, calculate_base_asset_amount_market_can_execute, limit_price_satisfied, calculate_quote_asset_amount_for_maker_order, is_trigger_condition_satisfied};
 use crate::states::market::{MARKETS, Market};
-use crate::states::order::{ORDERS, get_limit_price};
-use crate::states::history::{OrderRecord, OrderAction, ORDER_HISTORY_INFO, ORDER_HISTORY, OrderHisInfo, TRADE_HISTORY_INFO, TradeInfo, TRADE_HISTORY, TradeRecord};
+use crate::states::order::{ORDERS, USER_ORDER_IDS, get_limit_price, get_order_id_for_user_order_id};
+use crate::states::history::{OrderRecord, ORDER_HISTORY_INFO, ORDER_HISTORY, OrderHisInfo, TRADE_HISTORY_INFO, TradeInfo, TRADE_HISTORY, TradeRecord};
 use crate::states::state::{STATE, ORDERSTATE, FEESTRUCTURE, ORACLEGUARDRAILS};
+use crate::states::order::OrderState;
+use cw_storage_plus::U64Key;
 
 use crate::helpers::order::get_valid_oracle_price;
-use std::cmp::min;
+use std::cmp::{max, min};
 use ariel::number::Number128;
-use ariel::types::{Order, OrderType, PositionDirection, SwapDirection, OrderStatus, OrderParams};
+use ariel::types::{Order, OrderAction, OrderType, PositionDirection, SwapDirection, OrderStatus, OrderParams, OracleGuardRails, MakerOrderId};
+use ariel::helper::addr_validate_to_lower;
 use cosmwasm_std::{DepsMut, Addr, Uint128};
 
 use crate::helpers::amm::{calculate_swap_output, normalise_oracle_price};
@@ -26,24 +31,24 @@ use crate::controller::funding::settle_funding_payment;
 use super::amm::update_oracle_price_twap;
 use super::funding::update_funding_rate;
 use super::margin::{meets_partial_margin_requirement, meets_initial_margin_requirement};
-use super::position::{update_position_with_base_asset_amount, update_position_with_quote_asset_amount};
+use super::position::{update_position_with_base_asset_amount, update_position_with_quote_asset_amount, update_position_with_matched_base_asset_amount};
 
 pub fn calculate_base_asset_amount_user_can_execute(
     deps: &mut DepsMut,
     user_addr: &Addr,
-    order_index: u64,
+    order_id: u64,
     market_index: u64,
 ) -> Result<Uint128, ContractError> {
 
     let position_index = market_index;
     let market = MARKETS.load(deps.storage, market_index.to_string())?;
     
-    let order = ORDERS.load(deps.storage, ((user_addr, market_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
 
     let quote_asset_amount = calculate_available_quote_asset_user_can_execute(
         deps,
         user_addr,
-        order_index,
+        order_id,
         position_index,
     )?;
 
@@ -64,10 +69,10 @@ pub fn calculate_base_asset_amount_user_can_execute(
 
     let initial_base_asset_amount = market.amm.base_asset_reserve;
     let (new_base_asset_amount, _new_quote_asset_amount) = calculate_swap_output(
+        &market.amm,
         quote_asset_reserve_amount,
         market.amm.quote_asset_reserve,
         order_swap_direction,
-        market.amm.sqrt_k,
     )?;
 
     let base_asset_amount = initial_base_asset_amount
@@ -79,7 +84,7 @@ pub fn calculate_base_asset_amount_user_can_execute(
 pub fn calculate_available_quote_asset_user_can_execute(
     deps: &DepsMut,
     user_addr: &Addr,
-    order_index: u64,
+    order_id: u64,
     position_index: u64,
 ) -> Result<Uint128, ContractError> {
 
@@ -88,7 +93,7 @@ pub fn calculate_available_quote_asset_user_can_execute(
     let market_index = position_index;
     let market = MARKETS.load(deps.storage, market_index.to_string())?;
     
-    let order = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
 
     let max_leverage = MARGIN_PRECISION
         .checked_div(
@@ -132,36 +137,75 @@ pub fn place_order(
 ) -> Result<bool, ContractError> {
 
     let order_state = ORDERSTATE.load(deps.storage)?;
-    let fee_structure = FEESTRUCTURE.load(deps.storage)?;
     let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
 
-    let user = USERS.load(deps.storage, &user_addr.clone())?;
-    let position_index = params.market_index;
-    let mut market_position = POSITIONS.load(deps.storage, (&user_addr.clone(), position_index.to_string()))?;
-    
-    let market_index = params.market_index;
-    let market = MARKETS.load(deps.storage, market_index.to_string())?;
-   
     settle_funding_payment(
         deps,
         &user_addr.clone(),
         now,
     )?;
-    
-    let discount_tier = calculate_order_fee_tier(
-        &fee_structure,
-        params.base_asset_amount,
+
+    // user is loaded after funding is settled so the collateral update isn't overwritten
+    let mut user = USERS.load(deps.storage, &user_addr.clone())?;
+
+    insert_order(
+        deps,
+        user_addr,
+        &mut user,
+        now,
+        params,
+        oracle,
+        &order_state,
+        &oracle_guard_rails,
     )?;
 
+    USERS.update(deps.storage, user_addr, |_u| -> Result<User, ContractError> {
+        Ok(user)
+    })?;
+
+    Ok(true)
+}
+
+/// Validates and stores a new order for the user and writes its Place record.
+/// Funding must already be settled and the caller is responsible for saving the user.
+fn insert_order(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    user: &mut User,
+    now: u64,
+    params: OrderParams,
+    oracle: &Addr,
+    order_state: &OrderState,
+    oracle_guard_rails: &OracleGuardRails,
+) -> Result<Order, ContractError> {
+
+    let position_index = params.market_index;
+    let mut market_position = POSITIONS.load(deps.storage, (&user_addr.clone(), position_index.to_string()))?;
+    
+    let market_index = params.market_index;
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+
+    if let Some(user_order_id) = params.user_order_id {
+        if USER_ORDER_IDS.may_load(deps.storage, (user_addr, user_order_id.to_string()))?.is_some() {
+            return Err(ContractError::UserOrderIdAlreadyInUse);
+        }
+    }
+    
+    let discount_tier = get_user_fee_tier(deps.storage, &deps.querier, user_addr, now)?;
+
+    // Order ids are never reused, so they stay stable for the lifetime of the order
+    user.next_order_id = user.next_order_id.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
+    let new_order_id = user.next_order_id;
+
     // Increment open orders for existing position
     market_position.order_length = market_position.order_length.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
 
-    let new_order_idx = market_position.order_length;
-
     let new_order = Order {
         status: OrderStatus::Open,
         order_type: params.order_type,
         ts: now,
+        order_id: new_order_id,
+        user_order_id: params.user_order_id,
         position_index,
         market_index,
         price: params.price,
@@ -176,7 +220,7 @@ pub fn place_order(
         discount_tier,
         trigger_price: params.trigger_price,
         trigger_condition: params.trigger_condition,
-        referrer: match user.referrer {
+        referrer: match user.referrer.clone() {
             Some(referrer) => referrer,
             None => Addr::unchecked(""),
         },
@@ -186,24 +230,31 @@ pub fn place_order(
         immediate_or_cancel: false,
     };
 
-    ORDERS.save(deps.storage, ((&user_addr.clone(), position_index.to_string()), new_order_idx.to_string()),&new_order)?;
-
     let valid_oracle_price = get_valid_oracle_price(
         Some(oracle),
         &market,
         &new_order,
-        &oracle_guard_rails,
+        oracle_guard_rails,
         now
     )?;
 
     validate_order(
         &new_order, 
         &market, 
-        &order_state, 
+        order_state, 
         valid_oracle_price
     )?;
 
-    // Add to the order history account    user.order_length = new_order_idx;
+    ORDERS.save(deps.storage, (user_addr, U64Key::new(new_order_id)), &new_order)?;
+    if let Some(user_order_id) = new_order.user_order_id {
+        USER_ORDER_IDS.save(deps.storage, (user_addr, user_order_id.to_string()), &new_order_id)?;
+    }
+
+    POSITIONS.update(deps.storage, (user_addr, position_index.to_string()), |_p| -> Result<Position, ContractError> {
+        Ok(market_position)
+    })?;
+
+    // Add to the order history account
     let order_history_info_length = 
     ORDER_HISTORY_INFO.load(deps.storage)?
     .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
@@ -211,9 +262,9 @@ pub fn place_order(
         i.len = order_history_info_length;
         Ok(i)
     })?;
-    ORDER_HISTORY.save(deps.storage, order_history_info_length.to_string(), &OrderRecord {
+    ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
         ts: now,
-        order: new_order,
+        order: new_order.clone(),
         user: user_addr.clone(),
         action: OrderAction::Place,
         filler: Addr::unchecked(""),
@@ -222,27 +273,30 @@ pub fn place_order(
         quote_asset_amount_filled: Uint128::zero(),
         filler_reward: Uint128::zero(),
         fee: Uint128::zero(),
+        maker_fee: Number128::zero(),
         quote_asset_amount_surplus: Uint128::zero(),
         position_index,
     })?;
 
-    Ok(true)
+    Ok(new_order)
 }
 
 pub fn cancel_order(
     deps: &mut DepsMut,
     user_addr: &Addr,
     position_index: u64,
-    order_index: u64,
+    order_id: u64,
     oracle: &Addr,
     now: u64
 ) -> Result<bool, ContractError> {
 
-    let mut market_position = POSITIONS.load(deps.storage, (user_addr, position_index.to_string()))?;
     let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
 
-    let order = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()))?;
-    let market = MARKETS.load(deps.storage, position_index.to_string())?;
+    let order = ORDERS.may_load(deps.storage, (user_addr, U64Key::new(order_id)))?
+        .ok_or(ContractError::OrderDoesNotExist)?;
+    if order.market_index != position_index {
+        return Err(ContractError::OrderDoesNotExist);
+    }
 
     settle_funding_payment(
         deps,
@@ -250,9 +304,185 @@ pub fn cancel_order(
         now
     )?;
 
+    remove_canceled_order(deps, user_addr, order, oracle, &oracle_guard_rails, now)?;
+
+    Ok(true)
+}
+
+pub fn cancel_order_by_user_order_id(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    user_order_id: u64,
+    oracle: &Addr,
+    now: u64
+) -> Result<bool, ContractError> {
+    let order_id = get_order_id_for_user_order_id(deps.storage, user_addr, user_order_id)?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
+
+    cancel_order(
+        deps,
+        user_addr,
+        order.market_index,
+        order_id,
+        oracle,
+        now,
+    )
+}
+
+/// Cancels every open order of the user, optionally restricted to one market and/or direction.
+/// Orders that can't be canceled right now (e.g. a post only order the AMM would fill) are left open.
+/// Returns the ids of the canceled and the skipped orders.
+pub fn cancel_all_orders(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    market_index: Option<u64>,
+    direction: Option<PositionDirection>,
+    oracle: &Addr,
+    now: u64
+) -> Result<(Vec<u64>, Vec<u64>), ContractError> {
+
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+
+    settle_funding_payment(
+        deps,
+        user_addr, 
+        now
+    )?;
+
+    let orders_to_cancel: Vec<Order> = ORDERS
+        .prefix(user_addr)
+        .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+        .filter_map(|o| o.ok().map(|order| order.1))
+        .filter(|order| market_index.map_or(true, |m| order.market_index == m))
+        .filter(|order| direction.map_or(true, |d| order.direction == d))
+        .collect();
+
+    let mut canceled_orders: Vec<u64> = vec![];
+    let mut skipped_orders: Vec<u64> = vec![];
+    for order in orders_to_cancel {
+        let order_id = order.order_id;
+        if !can_cancel_order(deps, &order, oracle, &oracle_guard_rails, now)? {
+            skipped_orders.push(order_id);
+            continue;
+        }
+        remove_canceled_order(deps, user_addr, order, oracle, &oracle_guard_rails, now)?;
+        canceled_orders.push(order_id);
+    }
+
+    Ok((canceled_orders, skipped_orders))
+}
+
+/// Cancels the given orders and then places the new ones in a single pass.
+/// Funding is settled once up front and margin is checked once after every order is placed.
+pub fn batch_orders(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    place: Vec<OrderParams>,
+    cancel: Vec<u64>,
+    oracle: &Addr,
+    now: u64
+) -> Result<bool, ContractError> {
+
+    let order_state = ORDERSTATE.load(deps.storage)?;
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+
+    settle_funding_payment(
+        deps,
+        user_addr, 
+        now
+    )?;
+
+    // cancels go first so a requoted ladder can reuse the freed user_order_ids
+    for order_id in cancel {
+        let order = ORDERS.may_load(deps.storage, (user_addr, U64Key::new(order_id)))?
+            .ok_or(ContractError::OrderDoesNotExist)?;
+        remove_canceled_order(deps, user_addr, order, oracle, &oracle_guard_rails, now)?;
+    }
+
+    if place.is_empty() {
+        return Ok(true);
+    }
+
+    let mut user = USERS.load(deps.storage, user_addr)?;
+    let mut risk_increasing = false;
+    for params in place {
+        let new_order = insert_order(
+            deps,
+            user_addr,
+            &mut user,
+            now,
+            params,
+            oracle,
+            &order_state,
+            &oracle_guard_rails,
+        )?;
+        risk_increasing = risk_increasing || !new_order.reduce_only;
+    }
+
+    if risk_increasing && !meets_initial_margin_requirement(deps, user_addr)? {
+        return Err(ContractError::InsufficientCollateral);
+    }
+
+    USERS.update(deps.storage, user_addr, |_u| -> Result<User, ContractError> {
+        Ok(user)
+    })?;
+
+    Ok(true)
+}
+
+/// Amends the price, size or trigger price of an open order in place.
+/// The order keeps its id, and keeps its timestamp (and so its queue position) when only the size shrinks.
+pub fn modify_order(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    order_id: u64,
+    new_price: Option<Uint128>,
+    new_base_asset_amount: Option<Uint128>,
+    new_trigger_price: Option<Uint128>,
+    oracle: &Addr,
+    now: u64
+) -> Result<bool, ContractError> {
+
+    let order_state = ORDERSTATE.load(deps.storage)?;
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+
+    let mut order = ORDERS.may_load(deps.storage, (user_addr, U64Key::new(order_id)))?
+        .ok_or(ContractError::OrderDoesNotExist)?;
     if order.status != OrderStatus::Open {
         return Err(ContractError::OrderNotOpen);
     }
+    let market = MARKETS.load(deps.storage, order.market_index.to_string())?;
+
+    let price_changed = new_price.map_or(false, |p| p != order.price);
+    let trigger_price_changed = new_trigger_price.map_or(false, |p| p != order.trigger_price);
+    let size_changed = new_base_asset_amount.map_or(false, |b| b != order.base_asset_amount);
+    if !price_changed && !trigger_price_changed && !size_changed {
+        return Err(ContractError::InvalidOrderModification);
+    }
+
+    let only_size_shrinks = !price_changed
+        && !trigger_price_changed
+        && new_base_asset_amount.map_or(false, |b| b < order.base_asset_amount);
+    let size_grows = new_base_asset_amount.map_or(false, |b| b > order.base_asset_amount);
+
+    if let Some(price) = new_price {
+        order.price = price;
+    }
+    if let Some(trigger_price) = new_trigger_price {
+        order.trigger_price = trigger_price;
+    }
+    if let Some(base_asset_amount) = new_base_asset_amount {
+        if base_asset_amount <= order.base_asset_amount_filled {
+            return Err(ContractError::InvalidOrderModification);
+        }
+        order.base_asset_amount = base_asset_amount;
+        order.discount_tier = get_user_fee_tier(deps.storage, &deps.querier, user_addr, now)?;
+    }
+
+    // anything other than shrinking the size loses the order's place in the queue
+    if !only_size_shrinks {
+        order.ts = now;
+    }
 
     let valid_oracle_price = get_valid_oracle_price(
         Some(oracle),
@@ -262,12 +492,105 @@ pub fn cancel_order(
         now
     )?;
 
+    validate_order(
+        &order,
+        &market,
+        &order_state,
+        valid_oracle_price
+    )?;
+
+    // a larger order takes on more risk, so it needs the same margin as placing it would
+    if size_grows && !order.reduce_only && !meets_initial_margin_requirement(deps, user_addr)? {
+        return Err(ContractError::InsufficientCollateral);
+    }
+
+    ORDERS.save(deps.storage, (user_addr, U64Key::new(order_id)), &order)?;
+
+    // Add to the order history account
+    let order_history_info_length = 
+    ORDER_HISTORY_INFO.load(deps.storage)?
+    .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
+    ORDER_HISTORY_INFO.update(deps.storage, |mut i|-> Result<OrderHisInfo, ContractError> {
+        i.len = order_history_info_length;
+        Ok(i)
+    })?;
+    let position_index = order.market_index;
+    ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
+        ts: now,
+        user: user_addr.clone(),
+        order: order,
+        action: OrderAction::Modify,
+        filler: Addr::unchecked(""),
+        trade_record_id: 0,
+        base_asset_amount_filled: Uint128::zero(),
+        quote_asset_amount_filled: Uint128::zero(),
+        fee: Uint128::zero(),
+        maker_fee: Number128::zero(),
+        filler_reward: Uint128::zero(),
+        quote_asset_amount_surplus: Uint128::zero(),
+        position_index,
+    })?;
+
+    Ok(true)
+}
+
+/// Whether an open order could be canceled right now, without canceling it
+fn can_cancel_order(
+    deps: &DepsMut,
+    order: &Order,
+    oracle: &Addr,
+    oracle_guard_rails: &OracleGuardRails,
+    now: u64
+) -> Result<bool, ContractError> {
+    if order.status != OrderStatus::Open {
+        return Ok(false);
+    }
+
+    let market = MARKETS.load(deps.storage, order.market_index.to_string())?;
+    let valid_oracle_price = get_valid_oracle_price(
+        Some(oracle),
+        &market,
+        order,
+        oracle_guard_rails,
+        now
+    )?;
+
+    Ok(validate_order_can_be_canceled(order, &market, valid_oracle_price).is_ok())
+}
+
+/// Validates that an open order can be canceled, removes it and writes its Cancel record
+fn remove_canceled_order(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    order: Order,
+    oracle: &Addr,
+    oracle_guard_rails: &OracleGuardRails,
+    now: u64
+) -> Result<(), ContractError> {
+
+    if order.status != OrderStatus::Open {
+        return Err(ContractError::OrderNotOpen);
+    }
+
+    let position_index = order.market_index;
+    let market = MARKETS.load(deps.storage, position_index.to_string())?;
+
+    let valid_oracle_price = get_valid_oracle_price(
+        Some(oracle),
+        &market,
+        &order,
+        oracle_guard_rails,
+        now
+    )?;
+
     validate_order_can_be_canceled(
         &order,
         &market,
         valid_oracle_price,
     )?;
 
+    remove_order(deps, user_addr, &order)?;
+
     // Add to the order history account
     let order_history_info_length = 
     ORDER_HISTORY_INFO.load(deps.storage)?
@@ -276,7 +599,7 @@ pub fn cancel_order(
         i.len = order_history_info_length;
         Ok(i)
     })?;
-    ORDER_HISTORY.save(deps.storage, order_history_info_length.to_string(), &OrderRecord {
+    ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
         ts: now,
         user: user_addr.clone(),
         order: order,
@@ -286,37 +609,42 @@ pub fn cancel_order(
         base_asset_amount_filled: Uint128::zero(),
         quote_asset_amount_filled: Uint128::zero(),
         fee: Uint128::zero(),
+        maker_fee: Number128::zero(),
         filler_reward: Uint128::zero(),
         quote_asset_amount_surplus: Uint128::zero(),
         position_index,
     })?;
 
-    if order_index != market_position.order_length {
-        let order_to_replace = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), market_position.order_length.to_string()))?;
-        ORDERS.update(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()), |_p| -> Result<Order, ContractError> {
-            Ok(order_to_replace)
-        })?;
+    Ok(())
+}
+
+/// Deletes the order and its user_order_id mapping, and decrements the open order count of the position
+pub fn remove_order(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    order: &Order,
+) -> Result<(), ContractError> {
+    ORDERS.remove(deps.storage, (user_addr, U64Key::new(order.order_id)));
+    if let Some(user_order_id) = order.user_order_id {
+        USER_ORDER_IDS.remove(deps.storage, (user_addr, user_order_id.to_string()));
     }
-    
-    ORDERS.remove(deps.storage, ((user_addr, position_index.to_string()), market_position.order_length.to_string()));
 
     // Decrement open orders for existing position
-    market_position.order_length -= 1;
-    POSITIONS.update(deps.storage, (user_addr, position_index.to_string()), |_p| -> Result<Position, ContractError> {
+    POSITIONS.update(deps.storage, (user_addr, order.market_index.to_string()), |p| -> Result<Position, ContractError> {
+        let mut market_position = p.ok_or(ContractError::UserHasNoPositionInMarket)?;
+        market_position.order_length = market_position.order_length.saturating_sub(1);
         Ok(market_position)
     })?;
 
-    Ok(true)
+    Ok(())
 }
 
-
 pub fn expire_orders(
     deps: &mut DepsMut,
     user_addr: &Addr,
     now: u64,
     filler_addr: &Addr,
 ) -> Result<bool, ContractError> {
-    let state = STATE.load(deps.storage)?;
     let mut user = USERS.load(deps.storage, user_addr)?;
     let mut filler = USERS.load(deps.storage, filler_addr)?;
     
@@ -330,92 +658,63 @@ pub fn expire_orders(
     let max_filler_reward = QUOTE_PRECISION.u128() / 100; // .01 quote asset
     let filler_reward = min(user.collateral.u128(), max_filler_reward);
 
+    let open_orders: Vec<Order> = ORDERS
+        .prefix(user_addr)
+        .range(deps.storage, None, None, cosmwasm_std::Order::Ascending)
+        .filter_map(|o| o.ok().map(|order| order.1))
+        .filter(|order| order.status == OrderStatus::Open)
+        .collect();
+
+    if open_orders.is_empty() {
+        return Err(ContractError::UserHasNoOrder);
+    }
+
+    let filler_reward_per_order: u128 = filler_reward / (open_orders.len() as u128);
+
     user.collateral = calculate_updated_collateral(user.collateral, -(filler_reward as i128))?;
     filler.collateral = calculate_updated_collateral(filler.collateral, filler_reward as i128)?;
 
-    let mut expired_order_len: u64 = 0;
-    if state.markets_length > 0 {
-        for i in 1..state.markets_length {
-            let market_position = POSITIONS.load(deps.storage, (user_addr,i.to_string()));
-            match market_position {
-                Ok(p) => {
-                    if p.order_length > 0 {
-                        for j in 1..p.order_length {
-                            let order = ORDERS.load(deps.storage, ((user_addr, i.to_string()), j.to_string()))?;
-                            if order.status == OrderStatus::Open {
-                                expired_order_len += 1;
-                            }
-                        }
-                    };
-                },
-                Err(_) => (),
-            };
-        }
-    }
-    let filler_reward_per_order: u128 = filler_reward / (expired_order_len as u128);
-
-    if state.markets_length > 0 {
-        for i in 1..state.markets_length {
-            let market_position = POSITIONS.load(deps.storage, (user_addr,i.to_string()));
-            match market_position {
-                Ok(mut p) => {
-                    if p.clone().order_length > 0 {
-                        let mut j = 1;
-                        while j <= p.order_length {
-                            let mut order = ORDERS.load(deps.storage, ((user_addr, i.to_string()), j.to_string()))?;
-                            if order.status == OrderStatus::Init {
-                                j += 1;
-                                continue;
-                            }
-                            order.fee = order
-                            .fee
-                            .checked_add(Uint128::from(filler_reward_per_order))?;
-
-                            // Add to the order history account
-                            let order_history_info_length = 
-                            ORDER_HISTORY_INFO.load(deps.storage)?
-                            .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
-                            ORDER_HISTORY_INFO.update(deps.storage, |mut k|-> Result<OrderHisInfo, ContractError> {
-                                k.len = order_history_info_length;
-                                Ok(k)
-                            })?;
-                            ORDER_HISTORY.save(deps.storage, order_history_info_length.to_string(), &OrderRecord {
-                                ts: now,
-                                order: order,
-                                user: user_addr.clone(),
-                                action: OrderAction::Expire,
-                                filler: filler_addr.clone(),
-                                trade_record_id: 0,
-                                base_asset_amount_filled: Uint128::zero(),
-                                quote_asset_amount_filled: Uint128::zero(),
-                                filler_reward: Uint128::from(filler_reward_per_order),
-                                fee: Uint128::from(filler_reward_per_order),
-                                quote_asset_amount_surplus: Uint128::zero(),
-                                position_index : i,
-                            })?;
-
-                            if j != p.clone().order_length {
-                                let order_to_replace = ORDERS.load(deps.storage, ((user_addr, i.to_string()), p.clone().order_length.to_string()))?;
-                                ORDERS.update(deps.storage, ((user_addr, i.to_string()), j.to_string()), |_p| -> Result<Order, ContractError> {
-                                    Ok(order_to_replace)
-                                })?;
-                            }
-                            
-                            ORDERS.remove(deps.storage, ((user_addr, i.to_string()), p.clone().order_length.to_string()));
-
-                            p.order_length -= 1;
-                            // Decrement open orders for existing position
-                            POSITIONS.update(deps.storage, (user_addr, i.to_string()), |_position| -> Result<Position, ContractError> {
-                                Ok(p.clone())
-                            })?;
-                        }
-                    };
-                },
-                Err(_e) => (),
-            };
-        }
+    for mut order in open_orders {
+        order.fee = order
+        .fee
+        .checked_add(Uint128::from(filler_reward_per_order))?;
+
+        remove_order(deps, user_addr, &order)?;
+
+        // Add to the order history account
+        let order_history_info_length = 
+        ORDER_HISTORY_INFO.load(deps.storage)?
+        .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
+        ORDER_HISTORY_INFO.update(deps.storage, |mut k|-> Result<OrderHisInfo, ContractError> {
+            k.len = order_history_info_length;
+            Ok(k)
+        })?;
+        let position_index = order.market_index;
+        ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
+            ts: now,
+            order: order,
+            user: user_addr.clone(),
+            action: OrderAction::Expire,
+            filler: filler_addr.clone(),
+            trade_record_id: 0,
+            base_asset_amount_filled: Uint128::zero(),
+            quote_asset_amount_filled: Uint128::zero(),
+            filler_reward: Uint128::from(filler_reward_per_order),
+            fee: Uint128::from(filler_reward_per_order),
+            maker_fee: Number128::zero(),
+            quote_asset_amount_surplus: Uint128::zero(),
+            position_index,
+        })?;
     }
 
+    USERS.update(deps.storage, user_addr, |_u|-> Result<User, ContractError> {
+        Ok(user)
+    })?;
+
+    USERS.update(deps.storage, filler_addr, |_u|-> Result<User, ContractError> {
+        Ok(filler)
+    })?;
+
     Ok(true)
 }
  
@@ -424,25 +723,21 @@ pub fn fill_order(
     user_addr: &Addr,
     filler_addr: &Addr,
     position_index: u64,
-    order_index: u64,
+    order_id: u64,
+    maker_orders: Vec<MakerOrderId>,
     now: u64,
 ) -> Result<Uint128, ContractError> {
     let state = STATE.load(deps.storage)?;
     let order_state = ORDERSTATE.load(deps.storage)?;
-    let mut user = USERS.load(deps.storage, user_addr)?;
-    let mut filler = USERS.load(deps.storage, filler_addr)?;
-    let mut market_position = POSITIONS.load(deps.storage, (user_addr, position_index.to_string()))?;
-    let order = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.may_load(deps.storage, (user_addr, U64Key::new(order_id)))?
+        .ok_or(ContractError::OrderDoesNotExist)?;
+    if order.market_index != position_index {
+        return Err(ContractError::OrderDoesNotExist);
+    }
     let market_index = position_index;
     let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
     let fee_structure = FEESTRUCTURE.load(deps.storage)?;
-    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
-    let mut referrer : Option<User> = None;
-
-    if let Some(s) = user.referrer.clone() {
-        referrer = Some(USERS.load(deps.storage, &s)?)
-    }
-    
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
     
     {
         settle_funding_payment(
@@ -494,6 +789,39 @@ pub fn fill_order(
         None
     };
 
+    // match against resting orders of other users first, only the remainder goes to the amm
+    let base_asset_amount_matched = if maker_orders.is_empty() {
+        Uint128::zero()
+    } else {
+        fill_against_maker_orders(
+            deps,
+            user_addr,
+            filler_addr,
+            order_id,
+            maker_orders,
+            mark_price_before,
+            valid_oracle_price,
+            oracle_price,
+            now,
+        )?
+    };
+
+    // the maker matches have added to the order's fills, so carry on from the stored order
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
+    if !base_asset_amount_matched.is_zero() {
+        if order.base_asset_amount_filled >= order.base_asset_amount {
+            remove_order(deps, user_addr, &order)?;
+            update_funding_rate(
+                deps,
+                market_index,
+                now,
+                state.funding_paused,
+                Some(mark_price_before),
+            )?;
+            return Ok(base_asset_amount_matched);
+        }
+    }
+
     let (
         base_asset_amount,
         quote_asset_amount,
@@ -502,7 +830,7 @@ pub fn fill_order(
     ) = execute_order(
         deps,
         user_addr,
-        order_index,
+        order_id,
         market_index,
         mark_price_before,
         now,
@@ -510,9 +838,25 @@ pub fn fill_order(
     )?;
 
     if base_asset_amount.is_zero() {
-        return Ok(Uint128::zero());
+        if !base_asset_amount_matched.is_zero() {
+            // the unmatched remainder keeps resting on the book
+            update_funding_rate(
+                deps,
+                market_index,
+                now,
+                state.funding_paused,
+                Some(mark_price_before),
+            )?;
+        }
+        return Ok(base_asset_amount_matched);
     }
 
+    // reload what the position updates and maker matches above have written
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let mut user = USERS.load(deps.storage, user_addr)?;
+    let mut filler = USERS.load(deps.storage, filler_addr)?;
+    let fee_structure = get_referral_fee_structure(deps.storage, &fee_structure, &user.referrer)?;
+
     let mark_price_after: Uint128;
     let oracle_price_after: i128;
     let oracle_mark_spread_pct_after: i128;
@@ -571,18 +915,32 @@ pub fn fill_order(
     }
 
     let discount_tier = order.discount_tier.clone();
+    let is_post_only = order.post_only;
+    // post only orders provide liquidity and only pay (or are rebated) the maker fee below,
+    // any price improvement still goes to the market
     let (user_fee, fee_to_market, token_discount, filler_reward, referrer_reward, referee_discount) =
-        calculate_fee_for_order(
-            quote_asset_amount,
-            &fee_structure,
-            &order_state,
-            &discount_tier,
-            order.ts,
-            now,
-            &user.referrer,
-            filler_addr == user_addr,
-            quote_asset_amount_surplus,
-        )?;
+        if is_post_only {
+            (
+                Uint128::zero(),
+                quote_asset_amount_surplus,
+                Uint128::zero(),
+                Uint128::zero(),
+                Uint128::zero(),
+                Uint128::zero(),
+            )
+        } else {
+            calculate_fee_for_order(
+                quote_asset_amount,
+                &fee_structure,
+                &order_state,
+                &discount_tier,
+                order.ts,
+                now,
+                &user.referrer,
+                filler_addr == user_addr,
+                quote_asset_amount_surplus,
+            )?
+        };
 
     // Increment the clearing house's total fee variables
     {
@@ -614,25 +972,27 @@ pub fn fill_order(
         .collateral
         .checked_add(filler_reward)?;
 
-    // Update the referrer's collateral with their reward
-    if let Some(mut r) = referrer.clone() {
-        r.total_referral_reward = r
-            .total_referral_reward
-            .checked_add(referrer_reward)?;
-    }
+    record_user_volume(deps, user_addr, quote_asset_amount, now)?;
+
+    let maker_fee = if is_post_only {
+        let maker_fee = calculate_maker_fee(quote_asset_amount, &fee_structure)?;
+        apply_maker_fee(&mut market, &mut user, maker_fee)?
+    } else {
+        0
+    };
 
     {
         update_order_after_trade(
             deps,
             user_addr,
-            position_index,
-            order_index,
+            order_id,
             market.amm.minimum_base_asset_trade_size,
             base_asset_amount,
             quote_asset_amount,
-            user_fee,
+            user_fee.checked_add(Uint128::from(max(maker_fee, 0) as u128))?,
         )?;
     }
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
 
     // Insert trade history
     let trade_history_info_length = 
@@ -651,6 +1011,7 @@ pub fn fill_order(
         mark_price_before,
         mark_price_after,
         fee: user_fee,
+        maker_fee: Number128::new(maker_fee),
         token_discount,
         referrer_reward,
         referee_discount,
@@ -660,6 +1021,9 @@ pub fn fill_order(
     })?;
     
 
+    // delete order
+    remove_order(deps, user_addr, &order)?;
+
     // Insert Order history
     let order_history_info_length = 
         ORDER_HISTORY_INFO.load(deps.storage)?
@@ -668,7 +1032,7 @@ pub fn fill_order(
         k.len = order_history_info_length;
         Ok(k)
     })?;
-    ORDER_HISTORY.save(deps.storage, order_history_info_length.to_string(), &OrderRecord {
+    ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
         ts: now,
         user: user_addr.clone(),
         order: order,
@@ -678,28 +1042,12 @@ pub fn fill_order(
         base_asset_amount_filled: base_asset_amount,
         quote_asset_amount_filled: quote_asset_amount,
         fee: user_fee,
+        maker_fee: Number128::new(maker_fee),
         filler_reward,
         quote_asset_amount_surplus,
         position_index,
     })?;
 
-    // delete order
-    if order_index != market_position.order_length {
-        let order_to_replace = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), market_position.order_length.to_string()))?;
-        ORDERS.update(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()), |_p| -> Result<Order, ContractError> {
-            Ok(order_to_replace)
-        })?;
-    }
-    
-    ORDERS.remove(deps.storage, ((user_addr, position_index.to_string()), market_position.order_length.to_string()));
-
-    // Decrement open orders for existing position
-    market_position.order_length -= 1;
-    POSITIONS.update(deps.storage, (user_addr, position_index.to_string()), |_p| -> Result<Position, ContractError> {
-        Ok(market_position)
-    })?;
-
-
     // save user, filler, referrer, market
     USERS.update(deps.storage, user_addr, |_u|-> Result<User, ContractError> {
         Ok(user.clone())
@@ -709,11 +1057,17 @@ pub fn fill_order(
         Ok(filler)
     })?;
 
-    if let Some(s) = user.referrer {
-        if let Some(r) = referrer {
-            USERS.update(deps.storage, &s, |_u|-> Result<User, ContractError> {
-                Ok(r)
-            })?;    
+    // post only fills pay no taker fee, so there is nothing to share with the referrer
+    if !is_post_only {
+        if let Some(referrer_addr) = &user.referrer {
+            accrue_referral_reward(
+                deps.storage,
+                &mut market,
+                market_index,
+                referrer_addr,
+                user_addr,
+                referrer_reward,
+            )?;
         }
     }
 
@@ -732,25 +1086,385 @@ pub fn fill_order(
         )?;
     }
 
-    Ok(base_asset_amount)
+    Ok(base_asset_amount.checked_add(base_asset_amount_matched)?)
+}
+
+/// Matches the taker order against resting limit orders of other users at the maker's price.
+/// Both sides settle through controller::position without touching the amm.
+/// Returns the base asset amount matched.
+fn fill_against_maker_orders(
+    deps: &mut DepsMut,
+    taker_addr: &Addr,
+    filler_addr: &Addr,
+    taker_order_id: u64,
+    maker_orders: Vec<MakerOrderId>,
+    mark_price: Uint128,
+    valid_oracle_price: Option<i128>,
+    oracle_price: i128,
+    now: u64,
+) -> Result<Uint128, ContractError> {
+    let taker_order = ORDERS.load(deps.storage, (taker_addr, U64Key::new(taker_order_id)))?;
+
+    // post only orders can only ever be the maker side
+    if taker_order.post_only {
+        return Ok(Uint128::zero());
+    }
+
+    let order_state = ORDERSTATE.load(deps.storage)?;
+    let fee_structure = get_referral_fee_structure(
+        deps.storage,
+        &FEESTRUCTURE.load(deps.storage)?,
+        &USERS.load(deps.storage, taker_addr)?.referrer,
+    )?;
+    let market_index = taker_order.market_index;
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let minimum_base_asset_trade_size = market.amm.minimum_base_asset_trade_size;
+
+    // trigger orders only take liquidity once triggered, trigger market orders without a price take any maker price
+    let is_trigger_order = matches!(taker_order.order_type, OrderType::TriggerMarket | OrderType::TriggerLimit);
+    if is_trigger_order
+        && !is_trigger_condition_satisfied(&taker_order, &market, Some(mark_price), valid_oracle_price)?
+    {
+        return Ok(Uint128::zero());
+    }
+    let taker_limit_price = match taker_order.order_type {
+        OrderType::Limit | OrderType::TriggerLimit => Some(get_limit_price(&taker_order, valid_oracle_price)?),
+        OrderType::Market | OrderType::TriggerMarket => {
+            if taker_order.price.is_zero() {
+                None
+            } else {
+                Some(taker_order.price)
+            }
+        }
+    };
+    let maker_direction = match taker_order.direction {
+        PositionDirection::Long => PositionDirection::Short,
+        PositionDirection::Short => PositionDirection::Long,
+    };
+
+    let mut taker_base_asset_amount_remaining = taker_order
+        .base_asset_amount
+        .checked_sub(taker_order.base_asset_amount_filled)?;
+    let mut base_asset_amount_matched = Uint128::zero();
+    let mut taker_potentially_risk_increasing = false;
+
+    for maker in maker_orders {
+        let maker_addr = addr_validate_to_lower(deps.api, &maker.user_address)?;
+        if maker_addr == *taker_addr {
+            continue;
+        }
+
+        let maker_order = match ORDERS.may_load(deps.storage, (&maker_addr, U64Key::new(maker.order_id)))? {
+            Some(maker_order) => maker_order,
+            None => continue,
+        };
+        if maker_order.status != OrderStatus::Open
+            || maker_order.order_type != OrderType::Limit
+            || maker_order.market_index != market_index
+            || maker_order.direction != maker_direction
+        {
+            continue;
+        }
+
+        let maker_price = get_limit_price(&maker_order, valid_oracle_price)?;
+        let crosses = match (taker_limit_price, taker_order.direction) {
+            (None, _) => true,
+            (Some(taker_limit_price), PositionDirection::Long) => maker_price <= taker_limit_price,
+            (Some(taker_limit_price), PositionDirection::Short) => maker_price >= taker_limit_price,
+        };
+        if !crosses {
+            continue;
+        }
+
+        let maker_base_asset_amount_remaining = maker_order
+            .base_asset_amount
+            .checked_sub(maker_order.base_asset_amount_filled)?;
+        let base_asset_amount = min(taker_base_asset_amount_remaining, maker_base_asset_amount_remaining);
+        if base_asset_amount < minimum_base_asset_trade_size {
+            continue;
+        }
+
+        // skip matches that would leave either order with less than the minimum trade size
+        let taker_base_asset_amount_left = taker_base_asset_amount_remaining.checked_sub(base_asset_amount)?;
+        let maker_base_asset_amount_left = maker_base_asset_amount_remaining.checked_sub(base_asset_amount)?;
+        if (!taker_base_asset_amount_left.is_zero() && taker_base_asset_amount_left < minimum_base_asset_trade_size)
+            || (!maker_base_asset_amount_left.is_zero() && maker_base_asset_amount_left < minimum_base_asset_trade_size)
+        {
+            continue;
+        }
+
+        let quote_asset_amount = calculate_quote_asset_amount_for_maker_order(base_asset_amount, maker_price)?;
+        if quote_asset_amount.is_zero() {
+            continue;
+        }
+
+        settle_funding_payment(deps, &maker_addr, now)?;
+
+        let taker_risk_increasing = update_position_with_matched_base_asset_amount(
+            deps,
+            base_asset_amount,
+            quote_asset_amount,
+            taker_order.direction,
+            taker_addr,
+            market_index,
+        )?;
+        taker_potentially_risk_increasing = taker_potentially_risk_increasing || taker_risk_increasing;
+
+        let maker_risk_increasing = update_position_with_matched_base_asset_amount(
+            deps,
+            base_asset_amount,
+            quote_asset_amount,
+            maker_direction,
+            &maker_addr,
+            market_index,
+        )?;
+        // resting orders are allowed to fill up to the partial margin requirement, like post only fills against the amm
+        if maker_risk_increasing && !meets_partial_margin_requirement(deps, &maker_addr)? {
+            return Err(ContractError::InsufficientCollateral);
+        }
+
+        let mut taker = USERS.load(deps.storage, taker_addr)?;
+        let (taker_fee, maker_fee, fee_to_market, token_discount, filler_reward, referrer_reward, referee_discount) =
+            calculate_fee_for_matched_order(
+                quote_asset_amount,
+                &fee_structure,
+                &order_state,
+                &taker_order.discount_tier,
+                taker_order.ts,
+                now,
+                &taker.referrer,
+                filler_addr == taker_addr,
+            )?;
+
+        taker.collateral = Uint128::from(taker.collateral.u128().saturating_sub(taker_fee.u128()));
+        taker.total_fee_paid = taker.total_fee_paid.checked_add(taker_fee)?;
+        taker.total_token_discount = taker.total_token_discount.checked_add(token_discount)?;
+        taker.total_referee_discount = taker.total_referee_discount.checked_add(referee_discount)?;
+        let taker_referrer = taker.referrer.clone();
+        USERS.save(deps.storage, taker_addr, &taker)?;
+
+        USERS.update(deps.storage, filler_addr, |f| -> Result<User, ContractError> {
+            let mut filler = f.ok_or(ContractError::UserDoesNotExist)?;
+            filler.collateral = filler.collateral.checked_add(filler_reward)?;
+            Ok(filler)
+        })?;
+
+        let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+        market.amm.total_fee = market.amm.total_fee.checked_add(fee_to_market)?;
+        market.amm.total_fee_minus_distributions = market
+            .amm
+            .total_fee_minus_distributions
+            .checked_add(fee_to_market)?;
+        if let Some(referrer_addr) = &taker_referrer {
+            accrue_referral_reward(
+                deps.storage,
+                &mut market,
+                market_index,
+                referrer_addr,
+                taker_addr,
+                referrer_reward,
+            )?;
+        }
+
+        let mut maker_user = USERS.load(deps.storage, &maker_addr)?;
+        let maker_fee = apply_maker_fee(&mut market, &mut maker_user, maker_fee)?;
+        USERS.save(deps.storage, &maker_addr, &maker_user)?;
+        MARKETS.save(deps.storage, market_index.to_string(), &market)?;
+
+        record_user_volume(deps, taker_addr, quote_asset_amount, now)?;
+        record_user_volume(deps, &maker_addr, quote_asset_amount, now)?;
+
+        update_order_after_trade(
+            deps,
+            taker_addr,
+            taker_order_id,
+            minimum_base_asset_trade_size,
+            base_asset_amount,
+            quote_asset_amount,
+            taker_fee,
+        )?;
+        update_order_after_trade(
+            deps,
+            &maker_addr,
+            maker.order_id,
+            minimum_base_asset_trade_size,
+            base_asset_amount,
+            quote_asset_amount,
+            Uint128::from(max(maker_fee, 0) as u128),
+        )?;
+
+        record_matched_fill(
+            deps,
+            taker_addr,
+            filler_addr,
+            &taker_order,
+            base_asset_amount,
+            quote_asset_amount,
+            taker_fee,
+            0,
+            filler_reward,
+            token_discount,
+            referrer_reward,
+            referee_discount,
+            mark_price,
+            oracle_price,
+            now,
+        )?;
+        record_matched_fill(
+            deps,
+            &maker_addr,
+            filler_addr,
+            &maker_order,
+            base_asset_amount,
+            quote_asset_amount,
+            Uint128::zero(),
+            maker_fee,
+            Uint128::zero(),
+            Uint128::zero(),
+            Uint128::zero(),
+            Uint128::zero(),
+            mark_price,
+            oracle_price,
+            now,
+        )?;
+
+        if maker_base_asset_amount_left.is_zero() {
+            remove_order(deps, &maker_addr, &maker_order)?;
+        }
+
+        taker_base_asset_amount_remaining = taker_base_asset_amount_left;
+        base_asset_amount_matched = base_asset_amount_matched.checked_add(base_asset_amount)?;
+        if taker_base_asset_amount_remaining.is_zero() {
+            break;
+        }
+    }
+
+    if taker_potentially_risk_increasing && !meets_initial_margin_requirement(deps, taker_addr)? {
+        return Err(ContractError::InsufficientCollateral);
+    }
+
+    Ok(base_asset_amount_matched)
+}
+
+/// Charges a positive maker fee, or pays a rebate out of the market's fee pool.
+/// Rebates are capped by total_fee_minus_distributions, returns the maker fee actually applied.
+fn apply_maker_fee(
+    market: &mut Market,
+    maker: &mut User,
+    maker_fee: i128,
+) -> Result<i128, ContractError> {
+    if maker_fee >= 0 {
+        let fee = Uint128::from(maker_fee as u128);
+        maker.collateral = Uint128::from(maker.collateral.u128().saturating_sub(fee.u128()));
+        maker.total_fee_paid = maker.total_fee_paid.checked_add(fee)?;
+        market.amm.total_fee = market.amm.total_fee.checked_add(fee)?;
+        market.amm.total_fee_minus_distributions = market
+            .amm
+            .total_fee_minus_distributions
+            .checked_add(fee)?;
+        return Ok(maker_fee);
+    }
+
+    let rebate = min(
+        Uint128::from(maker_fee.unsigned_abs()),
+        market.amm.total_fee_minus_distributions,
+    );
+    maker.collateral = maker.collateral.checked_add(rebate)?;
+    market.amm.total_fee_minus_distributions = market
+        .amm
+        .total_fee_minus_distributions
+        .checked_sub(rebate)?;
+
+    Ok(-(rebate.u128() as i128))
+}
+
+// trade record and Fill order record for one side of a peer-to-peer match
+fn record_matched_fill(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    filler_addr: &Addr,
+    order: &Order,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
+    fee: Uint128,
+    maker_fee: i128,
+    filler_reward: Uint128,
+    token_discount: Uint128,
+    referrer_reward: Uint128,
+    referee_discount: Uint128,
+    mark_price: Uint128,
+    oracle_price: i128,
+    now: u64,
+) -> Result<(), ContractError> {
+    // Insert trade history
+    let trade_history_info_length = 
+        TRADE_HISTORY_INFO.load(deps.storage)?
+        .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
+    TRADE_HISTORY_INFO.update(deps.storage, |mut k|-> Result<TradeInfo, ContractError> {
+        k.len = trade_history_info_length;
+        Ok(k)
+    })?;
+    TRADE_HISTORY.save(deps.storage, (user_addr, trade_history_info_length.to_string()), &TradeRecord {
+        ts: now,
+        user: user_addr.clone(),
+        direction: order.direction,
+        base_asset_amount,
+        quote_asset_amount,
+        mark_price_before: mark_price,
+        mark_price_after: mark_price,
+        fee,
+        maker_fee: Number128::new(maker_fee),
+        token_discount,
+        referrer_reward,
+        referee_discount,
+        liquidation: false,
+        market_index: order.market_index,
+        oracle_price: Number128::new(oracle_price),
+    })?;
+
+    // Insert Order history
+    let order_history_info_length = 
+        ORDER_HISTORY_INFO.load(deps.storage)?
+        .len.checked_add(1).ok_or_else(|| (ContractError::MathError))?;
+    ORDER_HISTORY_INFO.update(deps.storage, |mut k|-> Result<OrderHisInfo, ContractError> {
+        k.len = order_history_info_length;
+        Ok(k)
+    })?;
+    ORDER_HISTORY.save(deps.storage, U64Key::new(order_history_info_length), &OrderRecord {
+        ts: now,
+        user: user_addr.clone(),
+        order: order.clone(),
+        action: OrderAction::Fill,
+        filler: filler_addr.clone(),
+        trade_record_id: trade_history_info_length,
+        base_asset_amount_filled: base_asset_amount,
+        quote_asset_amount_filled: quote_asset_amount,
+        fee,
+        maker_fee: Number128::new(maker_fee),
+        filler_reward,
+        quote_asset_amount_surplus: Uint128::zero(),
+        position_index: order.market_index,
+    })?;
+
+    Ok(())
 }
 
 pub fn execute_order(
     deps: &mut DepsMut,
     user_addr: &Addr,
-    order_index: u64,
+    order_id: u64,
     market_index: u64,
     mark_price_before: Uint128,
     now: u64,
     value_oracle_price: Option<i128>,
 ) -> Result<(Uint128, Uint128, bool, Uint128), ContractError> {
-    let order = ORDERS.load(deps.storage, ((user_addr, market_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
     
     match order.order_type {
         OrderType::Market => execute_market_order(
             deps,
             user_addr,
-            order_index,
+            order_id,
             market_index,
             mark_price_before,
             now,
@@ -758,7 +1472,7 @@ pub fn execute_order(
         _ => execute_non_market_order(
             deps,
             user_addr,
-            order_index,
+            order_id,
             market_index,
             mark_price_before,
             now,
@@ -770,12 +1484,12 @@ pub fn execute_order(
 pub fn execute_market_order(
     deps: &mut DepsMut,
     user_addr: &Addr,
-    order_index: u64,
+    order_id: u64,
     market_index: u64,
     mark_price_before: Uint128,
     now: u64,
 ) -> Result<(Uint128, Uint128, bool, Uint128), ContractError> {
-    let order = ORDERS.load(deps.storage, ((user_addr, market_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
     let market = MARKETS.load(deps.storage, market_index.to_string())?;
 
     let position_index = market_index;
@@ -835,7 +1549,7 @@ pub fn execute_market_order(
 pub fn execute_non_market_order(
     deps: &mut DepsMut,
     user_addr: &Addr,
-    order_index: u64,
+    order_id: u64,
     market_index: u64,
     mark_price_before: Uint128,
     now: u64,
@@ -845,7 +1559,7 @@ pub fn execute_non_market_order(
     let base_asset_amount_user_can_execute = calculate_base_asset_amount_user_can_execute(
         deps,
         user_addr,
-        order_index,
+        order_id,
         market_index
     )?;
 
@@ -854,7 +1568,7 @@ pub fn execute_non_market_order(
         return Ok((Uint128::zero(), Uint128::zero(), false, Uint128::zero()));
     }
 
-    let order = ORDERS.load(deps.storage, ((user_addr, market_index.to_string()), order_index.to_string()))?;
+    let order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
     let market = MARKETS.load(deps.storage, market_index.to_string())?;
 
     // Determine the base asset amount the market can fill
@@ -937,14 +1651,13 @@ pub fn execute_non_market_order(
 pub fn update_order_after_trade(
     deps: &mut DepsMut,
     user_addr: &Addr,
-    position_index: u64,
-    order_index: u64,
+    order_id: u64,
     minimum_base_asset_trade_size: Uint128,
     base_asset_amount: Uint128,
     quote_asset_amount: Uint128,
     fee: Uint128,
 ) -> Result<bool, ContractError>{
-    let mut order = ORDERS.load(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()))?;
+    let mut order = ORDERS.load(deps.storage, (user_addr, U64Key::new(order_id)))?;
     order.base_asset_amount_filled = order
         .base_asset_amount_filled
         .checked_add(base_asset_amount)?;
@@ -968,7 +1681,7 @@ pub fn update_order_after_trade(
 
     order.fee = order.fee.checked_add(fee)?;
 
-    ORDERS.update(deps.storage, ((user_addr, position_index.to_string()), order_index.to_string()), |_o| -> Result<Order, ContractError> {
+    ORDERS.update(deps.storage, (user_addr, U64Key::new(order_id)), |_o| -> Result<Order, ContractError> {
         Ok(order)
     })?;
 
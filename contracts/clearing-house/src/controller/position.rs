@@ -1,3 +1,5 @@
+use std::cmp::min;
+
 use ariel::number::Number128;
 use cosmwasm_std::{Addr, DepsMut, Uint128};
 
@@ -11,9 +13,10 @@ use crate::helpers::position::calculate_base_asset_value_and_pnl;
 use crate::states::market::{Market, MARKETS};
 use crate::states::user::{Position, User, POSITIONS, USERS};
 
-use crate::helpers::position::{calculate_pnl, calculate_updated_collateral};
+use crate::helpers::position::{calculate_collateral_shortfall, calculate_pnl, calculate_updated_collateral};
 
 use crate::controller::amm;
+use crate::controller::bankruptcy;
 
 pub fn increase(
     deps: &mut DepsMut,
@@ -50,14 +53,16 @@ pub fn increase(
         PositionDirection::Short => SwapDirection::Remove,
     };
 
-    let base_asset_acquired = amm::swap_quote_asset(
+    let (base_asset_acquired, spread_revenue) = amm::swap_quote_asset(
         deps,
         market_index,
         quote_asset_amount,
         swap_direction,
         now,
         precomputed_mark_price,
+        false,
     )?;
+    amm::credit_spread_revenue(&mut market, spread_revenue)?;
 
     // update the position size on market and user
     market_position.base_asset_amount = Number128::new(
@@ -126,14 +131,16 @@ pub fn reduce(
         PositionDirection::Short => SwapDirection::Remove,
     };
 
-    let base_asset_swapped = amm::swap_quote_asset(
+    let (base_asset_swapped, spread_revenue) = amm::swap_quote_asset(
         deps,
         market_index,
         quote_asset_swap_amount,
         swap_direction,
         now,
         precomputed_mark_price,
+        true,
     )?;
+    amm::credit_spread_revenue(&mut market, spread_revenue)?;
 
     let base_asset_amount_before = market_position.base_asset_amount;
     market_position.base_asset_amount = Number128::new(
@@ -199,6 +206,12 @@ pub fn reduce(
         (initial_quote_asset_amount_closed.checked_sub(quote_asset_swap_amount)?).u128() as i128
     };
 
+    bankruptcy::record_bankruptcy_loss(
+        deps,
+        user_addr,
+        market_index,
+        calculate_collateral_shortfall(user.collateral, pnl),
+    )?;
     user.collateral = calculate_updated_collateral(user.collateral, pnl)?;
 
     MARKETS.update(
@@ -245,14 +258,16 @@ pub fn close(
         SwapDirection::Remove
     };
 
-    let quote_asset_swapped = amm::swap_base_asset(
+    let (quote_asset_swapped, spread_revenue) = amm::swap_base_asset(
         deps,
         market_index,
         Uint128::from(market_position.base_asset_amount.i128().unsigned_abs()),
         swap_direction,
         now,
         precomputed_mark_price,
+        true,
     )?;
+    amm::credit_spread_revenue(&mut market, spread_revenue)?;
 
     let (quote_asset_amount, quote_asset_amount_surplus) = match maker_limit_price {
         Some(limit_price) => calculate_quote_asset_amount_surplus(
@@ -270,6 +285,12 @@ pub fn close(
         swap_direction,
     )?;
 
+    bankruptcy::record_bankruptcy_loss(
+        deps,
+        user_addr,
+        market_index,
+        calculate_collateral_shortfall(user.collateral, pnl),
+    )?;
     user.collateral = calculate_updated_collateral(user.collateral, pnl)?;
     market_position.last_cumulative_funding_rate = Number128::zero();
     market_position.last_funding_rate_ts = 0;
@@ -394,14 +415,16 @@ pub fn increase_with_base_asset_amount(
         PositionDirection::Short => SwapDirection::Add,
     };
 
-    let quote_asset_swapped = amm::swap_base_asset(
+    let (quote_asset_swapped, spread_revenue) = amm::swap_base_asset(
         deps,
         market_index,
         base_asset_amount,
         swap_direction,
         now,
         precomputed_mark_price,
+        false,
     )?;
+    amm::credit_spread_revenue(&mut market, spread_revenue)?;
 
     let (quote_asset_amount, quote_asset_amount_surplus) = match maker_limit_price {
         Some(limit_price) => calculate_quote_asset_amount_surplus(
@@ -497,14 +520,16 @@ pub fn reduce_with_base_asset_amount(
         PositionDirection::Short => SwapDirection::Add,
     };
 
-    let quote_asset_swapped = amm::swap_base_asset(
+    let (quote_asset_swapped, spread_revenue) = amm::swap_base_asset(
         deps,
         market_index,
         base_asset_amount,
         swap_direction,
         now,
         precomputed_mark_price,
+        true,
     )?;
+    amm::credit_spread_revenue(&mut market, spread_revenue)?;
 
     let (quote_asset_amount, quote_asset_amount_surplus) = match maker_limit_price {
         Some(limit_price) => calculate_quote_asset_amount_surplus(
@@ -584,6 +609,12 @@ pub fn reduce_with_base_asset_amount(
             .ok_or_else(|| (ContractError::MathError))?
     };
 
+    bankruptcy::record_bankruptcy_loss(
+        deps,
+        user_addr,
+        market_index,
+        calculate_collateral_shortfall(user.collateral, pnl),
+    )?;
     user.collateral = calculate_updated_collateral(user.collateral, pnl)?;
 
     MARKETS.update(
@@ -721,6 +752,214 @@ pub fn update_position_with_base_asset_amount(
     ))
 }
 
+/// Settles one side of a peer-to-peer match at the agreed quote asset amount, without trading against the amm.
+/// Returns whether the trade is potentially risk increasing for the user.
+pub fn update_position_with_matched_base_asset_amount(
+    deps: &mut DepsMut,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
+    direction: PositionDirection,
+    user_addr: &Addr,
+    position_index: u64,
+) -> Result<bool, ContractError> {
+    if base_asset_amount.is_zero() {
+        return Ok(false);
+    }
+
+    let mut user = USERS.load(deps.storage, user_addr)?;
+    let mut market_position = POSITIONS.load(deps.storage, (user_addr, position_index.to_string()))?;
+
+    let market_index = position_index;
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+
+    let existing_base_asset_amount = market_position.base_asset_amount.i128();
+    let increase_position = existing_base_asset_amount == 0
+        || existing_base_asset_amount > 0 && direction == PositionDirection::Long
+        || existing_base_asset_amount < 0 && direction == PositionDirection::Short;
+
+    let potentially_risk_increasing;
+    if increase_position {
+        increase_matched_position(
+            &mut market,
+            &mut market_position,
+            direction,
+            base_asset_amount,
+            quote_asset_amount,
+        )?;
+        potentially_risk_increasing = true;
+    } else {
+        let base_asset_amount_closed = Uint128::from(min(
+            existing_base_asset_amount.unsigned_abs(),
+            base_asset_amount.u128(),
+        ));
+        let quote_asset_amount_closed = quote_asset_amount
+            .checked_mul(base_asset_amount_closed)?
+            .checked_div(base_asset_amount)?;
+        let initial_quote_asset_amount_closed = market_position
+            .quote_asset_amount
+            .checked_mul(base_asset_amount_closed)?
+            .checked_div(Uint128::from(existing_base_asset_amount.unsigned_abs()))?;
+
+        let pnl = if existing_base_asset_amount > 0 {
+            (quote_asset_amount_closed.u128() as i128)
+                .checked_sub(initial_quote_asset_amount_closed.u128() as i128)
+                .ok_or_else(|| (ContractError::MathError))?
+        } else {
+            (initial_quote_asset_amount_closed.u128() as i128)
+                .checked_sub(quote_asset_amount_closed.u128() as i128)
+                .ok_or_else(|| (ContractError::MathError))?
+        };
+        bankruptcy::record_bankruptcy_loss(
+            deps,
+            user_addr,
+            market_index,
+            calculate_collateral_shortfall(user.collateral, pnl),
+        )?;
+        user.collateral = calculate_updated_collateral(user.collateral, pnl)?;
+
+        market_position.quote_asset_amount = market_position
+            .quote_asset_amount
+            .checked_sub(initial_quote_asset_amount_closed)?;
+
+        let base_asset_amount_change = match direction {
+            PositionDirection::Long => (base_asset_amount_closed.u128() as i128),
+            PositionDirection::Short => -(base_asset_amount_closed.u128() as i128),
+        };
+        market_position.base_asset_amount = Number128::new(
+            existing_base_asset_amount
+                .checked_add(base_asset_amount_change)
+                .ok_or_else(|| (ContractError::MathError))?,
+        );
+        market.base_asset_amount = Number128::new(
+            market
+                .base_asset_amount
+                .i128()
+                .checked_add(base_asset_amount_change)
+                .ok_or_else(|| (ContractError::MathError))?,
+        );
+        if existing_base_asset_amount > 0 {
+            market.base_asset_amount_long = Number128::new(
+                market
+                    .base_asset_amount_long
+                    .i128()
+                    .checked_add(base_asset_amount_change)
+                    .ok_or_else(|| (ContractError::MathError))?,
+            );
+        } else {
+            market.base_asset_amount_short = Number128::new(
+                market
+                    .base_asset_amount_short
+                    .i128()
+                    .checked_add(base_asset_amount_change)
+                    .ok_or_else(|| (ContractError::MathError))?,
+            );
+        }
+
+        if market_position.base_asset_amount.i128() == 0 {
+            market_position.last_cumulative_funding_rate = Number128::zero();
+            market_position.last_funding_rate_ts = 0;
+            market.open_interest = market.open_interest.checked_sub(Uint128::from(1 as u128))?;
+        }
+
+        // whatever is left after closing the existing position opens one in the other direction
+        let base_asset_amount_after_close = base_asset_amount.checked_sub(base_asset_amount_closed)?;
+        if !base_asset_amount_after_close.is_zero() {
+            increase_matched_position(
+                &mut market,
+                &mut market_position,
+                direction,
+                base_asset_amount_after_close,
+                quote_asset_amount.checked_sub(quote_asset_amount_closed)?,
+            )?;
+        }
+
+        // If the value of the new position is less than value of the old position, consider it risk decreasing
+        potentially_risk_increasing = base_asset_amount_after_close >= base_asset_amount_closed;
+    }
+
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> { Ok(market) },
+    )?;
+
+    POSITIONS.update(
+        deps.storage,
+        (user_addr, position_index.to_string()),
+        |_p| -> Result<Position, ContractError> { Ok(market_position) },
+    )?;
+
+    USERS.update(
+        deps.storage,
+        user_addr,
+        |_u| -> Result<User, ContractError> { Ok(user) },
+    )?;
+
+    Ok(potentially_risk_increasing)
+}
+
+fn increase_matched_position(
+    market: &mut Market,
+    market_position: &mut Position,
+    direction: PositionDirection,
+    base_asset_amount: Uint128,
+    quote_asset_amount: Uint128,
+) -> Result<(), ContractError> {
+    // Update funding rate if this is a new position
+    if market_position.base_asset_amount.i128() == 0 {
+        market_position.last_cumulative_funding_rate = match direction {
+            PositionDirection::Long => market.amm.cumulative_funding_rate_long,
+            PositionDirection::Short => market.amm.cumulative_funding_rate_short,
+        };
+
+        market.open_interest = market.open_interest.checked_add(Uint128::from(1 as u64))?;
+    }
+
+    market_position.quote_asset_amount = market_position
+        .quote_asset_amount
+        .checked_add(quote_asset_amount)?;
+
+    let base_asset_amount = match direction {
+        PositionDirection::Long => (base_asset_amount.u128() as i128),
+        PositionDirection::Short => -(base_asset_amount.u128() as i128),
+    };
+
+    market_position.base_asset_amount = Number128::new(
+        market_position
+            .base_asset_amount
+            .i128()
+            .checked_add(base_asset_amount)
+            .ok_or_else(|| (ContractError::MathError))?,
+    );
+    market.base_asset_amount = Number128::new(
+        market
+            .base_asset_amount
+            .i128()
+            .checked_add(base_asset_amount)
+            .ok_or_else(|| (ContractError::MathError))?,
+    );
+
+    if market_position.base_asset_amount.i128() > 0 {
+        market.base_asset_amount_long = Number128::new(
+            market
+                .base_asset_amount_long
+                .i128()
+                .checked_add(base_asset_amount)
+                .ok_or_else(|| (ContractError::MathError))?,
+        );
+    } else {
+        market.base_asset_amount_short = Number128::new(
+            market
+                .base_asset_amount_short
+                .i128()
+                .checked_add(base_asset_amount)
+                .ok_or_else(|| (ContractError::MathError))?,
+        );
+    }
+
+    Ok(())
+}
+
 pub fn update_position_with_quote_asset_amount(
     deps: &mut DepsMut,
     quote_asset_amount: Uint128,
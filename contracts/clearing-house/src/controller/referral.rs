@@ -0,0 +1,157 @@
+use std::cmp::min;
+
+use cosmwasm_std::{Addr, DepsMut, Order, StdError, Storage, Uint128};
+
+use ariel::helper::addr_validate_to_lower;
+use ariel::types::FeeStructure;
+
+use crate::error::ContractError;
+use crate::states::market::{Market, MARKETS};
+use crate::states::referral::{
+    Referrer, REFEREES, REFERRAL_CODES, REFERRERS, UNCLAIMED_REFERRAL_REWARDS,
+};
+use crate::states::user::{User, USERS};
+
+/// Resolves a referral code, or failing that a plain address, to an existing user.
+pub fn resolve_referrer(
+    deps: &DepsMut,
+    user_addr: &Addr,
+    referrer: &str,
+) -> Result<Addr, ContractError> {
+    let referrer_addr = match REFERRAL_CODES.may_load(deps.storage, referrer)? {
+        Some(addr) => addr,
+        None => addr_validate_to_lower(deps.api, referrer)
+            .map_err(|_| ContractError::InvalidReferrer)?,
+    };
+    if referrer_addr == *user_addr {
+        return Err(ContractError::UserCantReferThemselves);
+    }
+    if USERS.may_load(deps.storage, &referrer_addr)?.is_none() {
+        return Err(ContractError::ReferrerNotFound);
+    }
+    Ok(referrer_addr)
+}
+
+pub fn register_referral_code(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    code: String,
+) -> Result<(), ContractError> {
+    if code.len() < 3
+        || code.len() > 32
+        || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ContractError::InvalidReferralCode);
+    }
+    if USERS.may_load(deps.storage, user_addr)?.is_none() {
+        return Err(ContractError::UserDoesNotExist);
+    }
+    if REFERRAL_CODES.may_load(deps.storage, &code)?.is_some() {
+        return Err(ContractError::ReferralCodeAlreadyRegistered);
+    }
+
+    REFERRAL_CODES.save(deps.storage, &code, user_addr)?;
+    let mut referrer = REFERRERS.may_load(deps.storage, user_addr)?.unwrap_or_default();
+    referrer.codes.push(code);
+    REFERRERS.save(deps.storage, user_addr, &referrer)?;
+    Ok(())
+}
+
+/// Fee structure with the referrer's custom reward rate applied, if the admin set one.
+pub fn get_referral_fee_structure(
+    storage: &dyn Storage,
+    fee_structure: &FeeStructure,
+    referrer: &Option<Addr>,
+) -> Result<FeeStructure, ContractError> {
+    let mut fee_structure = fee_structure.clone();
+    if let Some(referrer_addr) = referrer {
+        if let Some(rate) = REFERRERS
+            .may_load(storage, referrer_addr)?
+            .and_then(|r: Referrer| r.reward_rate)
+        {
+            fee_structure.referrer_reward = rate.referrer_reward;
+            fee_structure.referee_discount = rate.referee_discount;
+        }
+    }
+    Ok(fee_structure)
+}
+
+/// Credits the referrer with a reward from the referee's fee. The reward stays in the
+/// market's total_fee_minus_distributions until it is claimed.
+pub fn accrue_referral_reward(
+    storage: &mut dyn Storage,
+    market: &mut Market,
+    market_index: u64,
+    referrer_addr: &Addr,
+    referee_addr: &Addr,
+    referrer_reward: Uint128,
+) -> Result<(), ContractError> {
+    if referrer_reward.is_zero() {
+        return Ok(());
+    }
+
+    USERS.update(storage, referrer_addr, |r| -> Result<User, ContractError> {
+        let mut referrer = r.ok_or(ContractError::ReferrerNotFound)?;
+        referrer.total_referral_reward = referrer.total_referral_reward.checked_add(referrer_reward)?;
+        Ok(referrer)
+    })?;
+    REFEREES.update(storage, (referrer_addr, referee_addr), |r| -> Result<Uint128, ContractError> {
+        Ok(r.unwrap_or_default().checked_add(referrer_reward)?)
+    })?;
+    UNCLAIMED_REFERRAL_REWARDS.update(
+        storage,
+        (referrer_addr, market_index.to_string()),
+        |r| -> Result<Uint128, ContractError> {
+            Ok(r.unwrap_or_default().checked_add(referrer_reward)?)
+        },
+    )?;
+
+    market.amm.total_fee_minus_distributions = market
+        .amm
+        .total_fee_minus_distributions
+        .checked_add(referrer_reward)?;
+    Ok(())
+}
+
+/// Pays unclaimed rewards into the referrer's collateral, limited by each market's
+/// total_fee_minus_distributions. Whatever can't be paid stays claimable.
+pub fn claim_referral_rewards(
+    deps: &mut DepsMut,
+    referrer_addr: &Addr,
+) -> Result<Uint128, ContractError> {
+    let unclaimed = UNCLAIMED_REFERRAL_REWARDS
+        .prefix(referrer_addr)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<Result<Vec<(Vec<u8>, Uint128)>, StdError>>()?;
+
+    let mut total_claimed = Uint128::zero();
+    for (key, amount) in unclaimed {
+        let market_index = String::from_utf8(key).map_err(StdError::invalid_utf8)?;
+        let mut market = MARKETS.load(deps.storage, market_index.clone())?;
+        let claimed = min(amount, market.amm.total_fee_minus_distributions);
+        market.amm.total_fee_minus_distributions = market
+            .amm
+            .total_fee_minus_distributions
+            .checked_sub(claimed)?;
+        MARKETS.save(deps.storage, market_index.clone(), &market)?;
+
+        let remaining = amount.checked_sub(claimed)?;
+        if remaining.is_zero() {
+            UNCLAIMED_REFERRAL_REWARDS.remove(deps.storage, (referrer_addr, market_index));
+        } else {
+            UNCLAIMED_REFERRAL_REWARDS.save(deps.storage, (referrer_addr, market_index), &remaining)?;
+        }
+        total_claimed = total_claimed.checked_add(claimed)?;
+    }
+
+    USERS.update(deps.storage, referrer_addr, |u| -> Result<User, ContractError> {
+        let mut user = u.ok_or(ContractError::UserDoesNotExist)?;
+        user.collateral = user.collateral.checked_add(total_claimed)?;
+        Ok(user)
+    })?;
+    let mut referrer = REFERRERS.may_load(deps.storage, referrer_addr)?.unwrap_or_default();
+    referrer.total_claimed = referrer.total_claimed.checked_add(total_claimed)?;
+    REFERRERS.save(deps.storage, referrer_addr, &referrer)?;
+
+    Ok(total_claimed)
+}
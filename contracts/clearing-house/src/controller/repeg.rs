@@ -1,3 +1,5 @@
+use std::cmp::min;
+
 use cosmwasm_std::{DepsMut, Uint128};
 
 use crate::error::ContractError;
@@ -10,6 +12,8 @@ use crate::states::constants::{
     SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR,SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR
 };
 use crate::helpers::position::_calculate_base_asset_value_and_pnl;
+use crate::states::history::Type;
+use crate::controller::amm::record_curve_history;
 
 pub fn repeg(
     deps: &mut DepsMut,
@@ -41,10 +45,10 @@ pub fn repeg(
     if oracle_is_valid {
         let terminal_price_after = amm::calculate_terminal_price(&mut market)?;
 
-        let mark_price_after = amm::calculate_price(
+        let mark_price_after = amm::calculate_curve_price(
+            &market.amm,
             market.amm.quote_asset_reserve,
             market.amm.base_asset_reserve,
-            market.amm.peg_multiplier,
         )?;
 
         let oracle_conf_band_top = Uint128::from(oracle_price.unsigned_abs())
@@ -136,3 +140,90 @@ pub fn adjust_peg_cost(market: &mut Market, new_peg: Uint128) -> Result<i128, Co
 
     Ok(cost)
 }
+
+/// Moves the peg toward the oracle twap, spending at most the market's repeg budget from the
+/// fee pool. Returns the adjustment cost when the peg was moved.
+pub fn formulaic_repeg(
+    deps: &mut DepsMut,
+    market_index: u64,
+    now: u64,
+) -> Result<Option<i128>, ContractError> {
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let oracle_price_twap = market.amm.last_oracle_price_twap.i128();
+    if market.amm.repeg_budget.is_zero() || oracle_price_twap <= 0 {
+        return Ok(None);
+    }
+
+    // the clearing house's share of fees is never spent on repegs
+    let protected_fees = market
+        .amm
+        .total_fee
+        .checked_mul(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR)?
+        .checked_div(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR)?;
+    let budget = min(
+        market.amm.repeg_budget,
+        market.amm.total_fee_minus_distributions.saturating_sub(protected_fees),
+    );
+
+    let peg_multiplier = market.amm.peg_multiplier;
+    let optimal_peg = amm::calculate_optimal_peg(
+        &market.amm,
+        Uint128::from(oracle_price_twap.unsigned_abs()),
+    )?;
+    if optimal_peg.is_zero() || optimal_peg == peg_multiplier {
+        return Ok(None);
+    }
+
+    let mut market_after = market.clone();
+    let mut adjustment_cost = adjust_peg_cost(&mut market_after, optimal_peg)?;
+    if adjustment_cost > 0 && adjustment_cost.unsigned_abs() > budget.u128() {
+        // the cost is linear in the peg change, so only move as far as the budget pays for
+        let peg_change = if optimal_peg > peg_multiplier {
+            optimal_peg.checked_sub(peg_multiplier)?
+        } else {
+            peg_multiplier.checked_sub(optimal_peg)?
+        };
+        let affordable_peg_change = peg_change
+            .checked_mul(budget)?
+            .checked_div(Uint128::from(adjustment_cost.unsigned_abs()))?;
+        if affordable_peg_change.is_zero() {
+            return Ok(None);
+        }
+        let new_peg = if optimal_peg > peg_multiplier {
+            peg_multiplier.checked_add(affordable_peg_change)?
+        } else {
+            peg_multiplier.checked_sub(affordable_peg_change)?
+        };
+
+        market_after = market.clone();
+        adjustment_cost = adjust_peg_cost(&mut market_after, new_peg)?;
+        if adjustment_cost > 0 && adjustment_cost.unsigned_abs() > budget.u128() {
+            return Ok(None);
+        }
+    }
+
+    market_after.amm.total_fee_minus_distributions = if adjustment_cost > 0 {
+        market_after
+            .amm
+            .total_fee_minus_distributions
+            .checked_sub(Uint128::from(adjustment_cost.unsigned_abs()))?
+    } else {
+        market_after
+            .amm
+            .total_fee_minus_distributions
+            .checked_add(Uint128::from(adjustment_cost.unsigned_abs()))?
+    };
+
+    MARKETS.save(deps.storage, market_index.to_string(), &market_after)?;
+    record_curve_history(
+        deps,
+        now,
+        market_index,
+        Type::Repeg,
+        &market,
+        &market_after,
+        adjustment_cost,
+    )?;
+
+    Ok(Some(adjustment_cost))
+}
@@ -0,0 +1,77 @@
+use cosmwasm_std::{Addr, DepsMut, QuerierWrapper, Storage, Uint128};
+
+use ariel::helper::{query_staked_balance, query_token_balance};
+use ariel::types::OrderDiscountTier;
+
+use crate::error::ContractError;
+use crate::helpers::fees::{best_discount_tier, calculate_order_fee_tier, calculate_volume_fee_tier};
+use crate::states::state::{DISCOUNT_TOKEN, FEESTRUCTURE, FEE_TIER_VOLUMES};
+use crate::states::user::USER_VOLUMES;
+
+pub fn record_user_volume(
+    deps: &mut DepsMut,
+    user_addr: &Addr,
+    quote_asset_amount: Uint128,
+    now: u64,
+) -> Result<(), ContractError> {
+    let mut user_volume = USER_VOLUMES
+        .may_load(deps.storage, user_addr)?
+        .unwrap_or_default();
+    user_volume.roll(now);
+    user_volume.daily_volume[0] = user_volume.daily_volume[0].checked_add(quote_asset_amount)?;
+    USER_VOLUMES.save(deps.storage, user_addr, &user_volume)?;
+    Ok(())
+}
+
+pub fn get_rolling_volume(
+    storage: &dyn Storage,
+    user_addr: &Addr,
+    now: u64,
+) -> Result<Uint128, ContractError> {
+    match USER_VOLUMES.may_load(storage, user_addr)? {
+        Some(user_volume) => user_volume.rolling_volume(now),
+        None => Ok(Uint128::zero()),
+    }
+}
+
+pub fn get_volume_fee_tier(
+    storage: &dyn Storage,
+    user_addr: &Addr,
+    now: u64,
+) -> Result<OrderDiscountTier, ContractError> {
+    let fee_tier_volumes = FEE_TIER_VOLUMES.load(storage)?;
+    let volume = get_rolling_volume(storage, user_addr, now)?;
+    Ok(calculate_volume_fee_tier(&fee_tier_volumes, volume))
+}
+
+/// Held plus staked balance of the discount token, zero if no token is configured.
+pub fn get_discount_token_balance(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    user_addr: &Addr,
+) -> Result<Uint128, ContractError> {
+    let config = match DISCOUNT_TOKEN.may_load(storage)? {
+        Some(config) => config,
+        None => return Ok(Uint128::zero()),
+    };
+
+    let mut balance = query_token_balance(querier, &config.token, user_addr)?;
+    if let Some(staking_contract) = config.staking_contract {
+        balance = balance.checked_add(query_staked_balance(querier, &staking_contract, user_addr)?)?;
+    }
+    Ok(balance)
+}
+
+/// The better of the user's discount token tier and trading volume tier.
+pub fn get_user_fee_tier(
+    storage: &dyn Storage,
+    querier: &QuerierWrapper,
+    user_addr: &Addr,
+    now: u64,
+) -> Result<OrderDiscountTier, ContractError> {
+    let fee_structure = FEESTRUCTURE.load(storage)?;
+    let discount_token_amt = get_discount_token_balance(storage, querier, user_addr)?;
+    let token_tier = calculate_order_fee_tier(&fee_structure, discount_token_amt)?;
+    let volume_tier = get_volume_fee_tier(storage, user_addr, now)?;
+    Ok(best_discount_tier(token_tier, volume_tier))
+}
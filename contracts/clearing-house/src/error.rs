@@ -50,6 +50,18 @@ pub enum ContractError {
     TradeSizeTooSmall,
     #[error("Price change too large when updating K")]
     InvalidUpdateK,
+    #[error("Invalid dynamic k bounds")]
+    InvalidDynamicKBounds,
+    #[error("Invalid spread")]
+    InvalidSpread,
+    #[error("Invalid funding parameters")]
+    InvalidFundingParameters,
+    #[error("Invalid oracle twap parameters")]
+    InvalidOracleTwapParameters,
+    #[error("Invalid curve parameters")]
+    InvalidCurveParameters,
+    #[error("Trade would move the price outside of the curve's range")]
+    PriceOutsideCurveRange,
     #[error("Admin tried to withdraw amount larger than fees collected")]
     AdminWithdrawTooLarge,
     #[error("Math Error")]
@@ -138,6 +150,32 @@ pub enum ContractError {
     CantCancelPostOnlyOrder,
     #[error("CantExpireOrders")]
     CantExpireOrders,
+    #[error("Invalid order modification")]
+    InvalidOrderModification,
+    #[error("Invalid maker fee")]
+    InvalidMakerFee,
+    #[error("Invalid fee tier volumes")]
+    InvalidFeeTierVolumes,
+    #[error("Invalid referral code")]
+    InvalidReferralCode,
+    #[error("Referral code already registered")]
+    ReferralCodeAlreadyRegistered,
+    #[error("Invalid referral reward rate")]
+    InvalidReferralRewardRate,
+    #[error("Liquidation auctions are disabled")]
+    LiquidationAuctionsDisabled,
+    #[error("Liquidation auction in progress")]
+    LiquidationAuctionInProgress,
+    #[error("Liquidation auction not found")]
+    LiquidationAuctionNotFound,
+    #[error("Liquidation auction expired")]
+    LiquidationAuctionExpired,
+    #[error("Liquidation auction required before liquidating into the amm")]
+    LiquidationAuctionRequired,
+    #[error("Invalid liquidation auction config")]
+    InvalidLiquidationAuctionConfig,
+    #[error("User cant liquidate themselves")]
+    UserCantLiquidateThemselves,
     #[error("Helpers Error")]
     HelpersError,
 }
@@ -1,16 +1,17 @@
 use std::cmp::{max, min};
 
-use num::integer::Roots;
 use std::ops::Mul;
 
 use crate::error::ContractError;
 
-use ariel::types::{OracleGuardRails, SwapDirection, PositionDirection, OraclePriceData};
-use cosmwasm_std::{Fraction, Uint128};
+use std::convert::TryFrom;
+
+use ariel::types::{CurveType, OracleGuardRails, SwapDirection, PositionDirection, OraclePriceData};
+use cosmwasm_std::{Fraction, Uint128, Uint256};
 
 use crate::states::market::{Market, Amm};
 
-use crate::states::constants::{PEG_PRECISION, PRICE_TO_PEG_PRECISION_RATIO,MARK_PRICE_PRECISION, PRICE_SPREAD_PRECISION, PRICE_SPREAD_PRECISION_U128};
+use crate::states::constants::{PEG_PRECISION, PRICE_TO_PEG_PRECISION_RATIO,MARK_PRICE_PRECISION, PRICE_SPREAD_PRECISION, PRICE_SPREAD_PRECISION_U128, MAX_STABLESWAP_AMPLIFICATION, STABLESWAP_MAX_ITERATIONS};
 use crate::helpers::position::{reserve_to_asset_amount, asset_to_reserve_amount};
 
 pub fn calculate_price(
@@ -26,6 +27,14 @@ pub fn calculate_price(
     Ok(res)
 }
 
+/// Peg multiplier at which the current reserves price the market at `target_price`.
+pub fn calculate_optimal_peg(a: &Amm, target_price: Uint128) -> Result<Uint128, ContractError> {
+    let peg = target_price
+        .checked_mul(a.base_asset_reserve)?
+        .checked_div(a.quote_asset_reserve.checked_mul(PRICE_TO_PEG_PRECISION_RATIO)?)?;
+    Ok(peg)
+}
+
 pub fn calculate_terminal_price(market: &mut Market) -> Result<Uint128, ContractError> {
     let swap_direction = if market.base_asset_amount.i128() > 0 {
         SwapDirection::Add
@@ -33,16 +42,16 @@ pub fn calculate_terminal_price(market: &mut Market) -> Result<Uint128, Contract
         SwapDirection::Remove
     };
     let (new_quote_asset_amount, new_base_asset_amount) = calculate_swap_output(
+        &market.amm,
         Uint128::from(market.base_asset_amount.i128().unsigned_abs()),
         market.amm.base_asset_reserve,
         swap_direction,
-        market.amm.sqrt_k,
     )?;
 
-    let terminal_price = calculate_price(
+    let terminal_price = calculate_curve_price(
+        &market.amm,
         new_quote_asset_amount,
         new_base_asset_amount,
-        market.amm.peg_multiplier,
     )?;
 
     Ok(terminal_price)
@@ -90,12 +99,7 @@ pub fn calculate_new_oracle_price_twap(
             .ok_or_else(|| (ContractError::MathError))?,
     );
 
-    let from_start = max(
-        1 as u64,
-        a.funding_period
-            .checked_sub(since_last)
-            .ok_or_else(|| (ContractError::MathError))?,
-        );
+    let from_start = max(1 as u64, a.oracle_twap_period.saturating_sub(since_last));
 
     // ensure amm.last_oracle_price is proper
     // let capped_last_oracle_price = if a.last_oracle_price > 0 {
@@ -150,15 +154,14 @@ pub fn calculate_twap(
     return new_twap;
 }
 
+/// Reserves after swapping `swap_amount` into (or out of) the reserve holding `input_asset_amount`,
+/// returned as (output reserve, input reserve) along the market's curve.
 pub fn calculate_swap_output(
+    a: &Amm,
     swap_amount: Uint128,
     input_asset_amount: Uint128,
     direction: SwapDirection,
-    invariant_sqrt: Uint128,
 ) -> Result<(Uint128, Uint128), ContractError> {
-    let invariant = invariant_sqrt
-        .checked_mul(invariant_sqrt)?;
-
     if direction == SwapDirection::Remove && swap_amount > input_asset_amount {
         return Err(ContractError::TradeSizeTooLarge);
     }
@@ -171,12 +174,284 @@ pub fn calculate_swap_output(
             .checked_sub(swap_amount)?
     };
 
-    let new_output_amount = invariant
-        .checked_div(new_input_amount)?;
+    let new_output_amount = match a.curve_type {
+        CurveType::StableSwap { amplification } => {
+            let invariant = calculate_stableswap_invariant(
+                a.base_asset_reserve,
+                a.quote_asset_reserve,
+                amplification,
+            )?;
+            calculate_stableswap_reserve(new_input_amount, invariant, amplification)?
+        }
+        CurveType::ConstantProduct | CurveType::Concentrated { .. } => {
+            let invariant = a.sqrt_k.checked_mul(a.sqrt_k)?;
+            invariant.checked_div(new_input_amount)?
+        }
+    };
 
     return Ok((new_output_amount, new_input_amount));
 }
 
+/// Marginal price of the market's curve at the given reserves
+pub fn calculate_curve_price(
+    a: &Amm,
+    quote_asset_reserve: Uint128,
+    base_asset_reserve: Uint128,
+) -> Result<Uint128, ContractError> {
+    match a.curve_type {
+        CurveType::StableSwap { amplification } => {
+            let invariant = calculate_stableswap_invariant(
+                base_asset_reserve,
+                quote_asset_reserve,
+                amplification,
+            )?;
+            calculate_stableswap_price(
+                quote_asset_reserve,
+                base_asset_reserve,
+                a.peg_multiplier,
+                invariant,
+                amplification,
+            )
+        }
+        CurveType::ConstantProduct | CurveType::Concentrated { .. } => {
+            calculate_price(quote_asset_reserve, base_asset_reserve, a.peg_multiplier)
+        }
+    }
+}
+
+pub fn validate_curve_type(curve_type: &CurveType, mark_price: Uint128) -> Result<(), ContractError> {
+    match *curve_type {
+        CurveType::ConstantProduct => {}
+        CurveType::StableSwap { amplification } => {
+            if amplification == 0 || amplification > MAX_STABLESWAP_AMPLIFICATION {
+                return Err(ContractError::InvalidCurveParameters);
+            }
+        }
+        CurveType::Concentrated { min_price, max_price } => {
+            if min_price.is_zero() || min_price >= mark_price || max_price <= mark_price {
+                return Err(ContractError::InvalidCurveParameters);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A concentrated curve has no liquidity outside of its price range, so a swap may only end up
+/// outside of it when it reduces a position or moves the price back toward the range
+pub fn validate_curve_price_range(
+    a: &Amm,
+    mark_price_before: Uint128,
+    reduces_position: bool,
+) -> Result<(), ContractError> {
+    if reduces_position {
+        return Ok(());
+    }
+    if let CurveType::Concentrated { min_price, max_price } = a.curve_type {
+        let mark_price = get_mark_price(a)?;
+        if (mark_price > max_price && mark_price > mark_price_before)
+            || (mark_price < min_price && mark_price < mark_price_before)
+        {
+            return Err(ContractError::PriceOutsideCurveRange);
+        }
+    }
+    Ok(())
+}
+
+/// Virtual reserves of a concentrated range holding the same real reserves as the given constant
+/// product reserves. The liquidity spread over every price is packed into the range, so the
+/// curve is deeper the narrower the range, and the real reserves run out at its bounds.
+pub fn calculate_concentrated_reserves(
+    base_asset_reserve: Uint128,
+    quote_asset_reserve: Uint128,
+    mark_price: Uint128,
+    min_price: Uint128,
+    max_price: Uint128,
+) -> Result<(Uint128, Uint128), ContractError> {
+    // the real base runs out at the max price and the real quote at the min price, the liquidity
+    // grows by 1 / (1 - sqrt(p / p_max)) and 1 / (1 - sqrt(p_min / p)) and the smaller one fits both
+    let precision_squared = Uint256::from(MARK_PRICE_PRECISION)
+        .checked_mul(Uint256::from(MARK_PRICE_PRECISION))?;
+    let sqrt_ratio_to_max = sqrt_u256(
+        precision_squared
+            .checked_mul(Uint256::from(mark_price))?
+            .checked_div(Uint256::from(max_price))?,
+    )?;
+    let sqrt_ratio_from_min = sqrt_u256(
+        precision_squared
+            .checked_mul(Uint256::from(min_price))?
+            .checked_div(Uint256::from(mark_price))?,
+    )?;
+    let sqrt_ratio = Uint128::try_from(min(sqrt_ratio_to_max, sqrt_ratio_from_min))
+        .map_err(|_| ContractError::MathError)?;
+    let scale_denominator = MARK_PRICE_PRECISION.checked_sub(sqrt_ratio)?;
+
+    Ok((
+        base_asset_reserve
+            .checked_mul(MARK_PRICE_PRECISION)?
+            .checked_div(scale_denominator)?,
+        quote_asset_reserve
+            .checked_mul(MARK_PRICE_PRECISION)?
+            .checked_div(scale_denominator)?,
+    ))
+}
+
+/// Solves 4A(x + y) + D = 4AD + D^3 / 4xy for D with newton's method
+fn calculate_stableswap_invariant(
+    base_asset_reserve: Uint128,
+    quote_asset_reserve: Uint128,
+    amplification: u64,
+) -> Result<Uint256, ContractError> {
+    let x = Uint256::from(base_asset_reserve);
+    let y = Uint256::from(quote_asset_reserve);
+    let sum = x.checked_add(y)?;
+    if x.is_zero() || y.is_zero() {
+        return Err(ContractError::MathError);
+    }
+
+    let ann = Uint256::from(amplification as u128 * 4);
+    let two = Uint256::from(2u128);
+    let mut d = sum;
+    for _ in 0..STABLESWAP_MAX_ITERATIONS {
+        let d_p = d
+            .checked_mul(d)?
+            .checked_div(x.checked_mul(two)?)?
+            .checked_mul(d)?
+            .checked_div(y.checked_mul(two)?)?;
+        let d_prev = d;
+        let numerator = ann
+            .checked_mul(sum)?
+            .checked_add(d_p.checked_mul(two)?)?
+            .checked_mul(d)?;
+        let denominator = ann
+            .checked_sub(Uint256::from(1u128))?
+            .checked_mul(d)?
+            .checked_add(d_p.checked_mul(Uint256::from(3u128))?)?;
+        d = numerator.checked_div(denominator)?;
+        if is_converged(d, d_prev) {
+            return Ok(d);
+        }
+    }
+    Err(ContractError::MathError)
+}
+
+/// The other reserve on the stableswap curve with invariant `d` once one reserve is `reserve`
+fn calculate_stableswap_reserve(
+    reserve: Uint128,
+    d: Uint256,
+    amplification: u64,
+) -> Result<Uint128, ContractError> {
+    let x = Uint256::from(reserve);
+    let ann = Uint256::from(amplification as u128 * 4);
+    let two = Uint256::from(2u128);
+
+    let c = d
+        .checked_mul(d)?
+        .checked_div(x.checked_mul(two)?)?
+        .checked_mul(d)?
+        .checked_div(ann.checked_mul(two)?)?;
+    let b = x.checked_add(d.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..STABLESWAP_MAX_ITERATIONS {
+        let y_prev = y;
+        y = y
+            .checked_mul(y)?
+            .checked_add(c)?
+            .checked_div(y.checked_mul(two)?.checked_add(b)?.checked_sub(d)?)?;
+        if is_converged(y, y_prev) {
+            return Uint128::try_from(y).map_err(|_| ContractError::MathError);
+        }
+    }
+    Err(ContractError::MathError)
+}
+
+/// -dy/dx of the stableswap invariant: the constant product price scaled by
+/// (16A·x·xy + D^3) / (16A·y·xy + D^3), which flattens towards the peg as A grows
+fn calculate_stableswap_price(
+    quote_asset_reserve: Uint128,
+    base_asset_reserve: Uint128,
+    peg_multiplier: Uint128,
+    d: Uint256,
+    amplification: u64,
+) -> Result<Uint128, ContractError> {
+    let price = calculate_price(quote_asset_reserve, base_asset_reserve, peg_multiplier)?;
+
+    let x = Uint256::from(base_asset_reserve);
+    let y = Uint256::from(quote_asset_reserve);
+    // n^n · Ann, with Ann = 4A as in the invariant
+    let ann_xy = Uint256::from(amplification as u128 * 16)
+        .checked_mul(x)?
+        .checked_mul(y)?;
+    let d_squared = d.checked_mul(d)?;
+
+    // both terms divided through by D to stay within 256 bits
+    let numerator = ann_xy.checked_mul(x)?.checked_div(d)?.checked_add(d_squared)?;
+    let denominator = ann_xy.checked_mul(y)?.checked_div(d)?.checked_add(d_squared)?;
+
+    let price = Uint256::from(price)
+        .checked_mul(numerator)?
+        .checked_div(denominator)?;
+    Uint128::try_from(price).map_err(|_| ContractError::MathError)
+}
+
+fn is_converged(value: Uint256, previous: Uint256) -> bool {
+    let one = Uint256::from(1u128);
+    if value > previous {
+        value - previous <= one
+    } else {
+        previous - value <= one
+    }
+}
+
+/// Spread charged to a trade in `direction`: half the base spread, widened by the mark/oracle
+/// twap divergence and skewed against trades that add to the AMM's inventory. expo = -4
+pub fn calculate_spread(
+    a: &Amm,
+    base_asset_amount: i128,
+    direction: PositionDirection,
+) -> Result<u128, ContractError> {
+    let mut spread = (a.base_spread / 2) as u128;
+
+    let oracle_price_twap = a.last_oracle_price_twap.i128();
+    if oracle_price_twap > 0 {
+        let volatility_spread = (a.last_mark_price_twap.u128() as i128)
+            .checked_sub(oracle_price_twap)
+            .ok_or(ContractError::MathError)?
+            .unsigned_abs()
+            .checked_mul(PRICE_SPREAD_PRECISION as u128)
+            .ok_or(ContractError::MathError)?
+            .checked_div(oracle_price_twap.unsigned_abs())
+            .ok_or(ContractError::MathError)?;
+        spread = spread
+            .checked_add(volatility_spread)
+            .ok_or(ContractError::MathError)?;
+    }
+
+    let adds_to_inventory = match direction {
+        PositionDirection::Long => base_asset_amount > 0,
+        PositionDirection::Short => base_asset_amount < 0,
+    };
+    if adds_to_inventory && !a.base_asset_reserve.is_zero() {
+        let inventory_ratio = base_asset_amount
+            .unsigned_abs()
+            .checked_mul(PRICE_SPREAD_PRECISION as u128)
+            .ok_or(ContractError::MathError)?
+            .checked_div(a.base_asset_reserve.u128())
+            .ok_or(ContractError::MathError)?;
+        spread = spread
+            .checked_mul(
+                (PRICE_SPREAD_PRECISION as u128)
+                    .checked_add(inventory_ratio)
+                    .ok_or(ContractError::MathError)?,
+            )
+            .ok_or(ContractError::MathError)?
+            .checked_div(PRICE_SPREAD_PRECISION as u128)
+            .ok_or(ContractError::MathError)?;
+    }
+
+    Ok(min(spread, a.max_spread as u128))
+}
+
 pub fn calculate_quote_asset_amount_swapped(
     quote_asset_reserve_before: Uint128,
     quote_asset_reserve_after: Uint128,
@@ -384,16 +659,24 @@ pub fn calculate_max_base_asset_amount_to_trade(
     amm: &Amm,
     limit_price: Uint128,
 ) -> Result<(Uint128, PositionDirection), ContractError> {
-    let invariant = amm.sqrt_k
-        .checked_mul(amm.sqrt_k)?;
-
-    let new_base_asset_reserve_squared = invariant
-        .checked_mul(MARK_PRICE_PRECISION)?
-        .checked_div(limit_price)?
-        .checked_mul(amm.peg_multiplier)?
-        .checked_div(PEG_PRECISION)?;
-
-    let new_base_asset_reserve = new_base_asset_reserve_squared.u128().sqrt();
+    let new_base_asset_reserve = match amm.curve_type {
+        CurveType::ConstantProduct => {
+            calculate_constant_product_base_asset_reserve(amm, limit_price)?
+        }
+        // liquidity ends at the range bounds, so trades can't go past them, or further past them
+        // when the price is already outside of the range
+        CurveType::Concentrated { min_price, max_price } => {
+            let mark_price = get_mark_price(amm)?;
+            let limit_price = max(
+                min(limit_price, max(max_price, mark_price)),
+                min(min_price, mark_price),
+            );
+            calculate_constant_product_base_asset_reserve(amm, limit_price)?
+        }
+        CurveType::StableSwap { amplification } => {
+            calculate_stableswap_base_asset_reserve(amm, limit_price, amplification)?
+        }
+    };
 
     if new_base_asset_reserve > amm.base_asset_reserve.u128() {
         let max_trade_amount = Uint128::from(new_base_asset_reserve)
@@ -407,6 +690,84 @@ pub fn calculate_max_base_asset_amount_to_trade(
     }
 }
 
+fn calculate_constant_product_base_asset_reserve(
+    amm: &Amm,
+    limit_price: Uint128,
+) -> Result<u128, ContractError> {
+    let sqrt_k = Uint256::from(amm.sqrt_k);
+    let invariant = sqrt_k.checked_mul(sqrt_k)?;
+
+    // in 256 bits, the invariant times the price precision overflows u128 for deep markets
+    let new_base_asset_reserve_squared = invariant
+        .checked_mul(Uint256::from(MARK_PRICE_PRECISION))?
+        .checked_div(Uint256::from(limit_price))?
+        .checked_mul(Uint256::from(amm.peg_multiplier))?
+        .checked_div(Uint256::from(PEG_PRECISION))?;
+
+    let new_base_asset_reserve = Uint128::try_from(sqrt_u256(new_base_asset_reserve_squared)?)
+        .map_err(|_| ContractError::MathError)?;
+    Ok(new_base_asset_reserve.u128())
+}
+
+// floor of the square root, by newton's method
+fn sqrt_u256(value: Uint256) -> Result<Uint256, ContractError> {
+    if value.is_zero() {
+        return Ok(value);
+    }
+    let two = Uint256::from(2u128);
+    let mut x = value;
+    let mut y = x.checked_add(Uint256::from(1u128))?.checked_div(two)?;
+    while y < x {
+        x = y;
+        y = x.checked_add(value.checked_div(x)?)?.checked_div(two)?;
+    }
+    Ok(x)
+}
+
+/// The stableswap price has no closed form inverse, so bisect the base asset reserve (the price
+/// falls as it grows) and stop on the side that doesn't cross the limit price
+fn calculate_stableswap_base_asset_reserve(
+    amm: &Amm,
+    limit_price: Uint128,
+    amplification: u64,
+) -> Result<u128, ContractError> {
+    let invariant = calculate_stableswap_invariant(
+        amm.base_asset_reserve,
+        amm.quote_asset_reserve,
+        amplification,
+    )?;
+    let price_at = |base_asset_reserve: Uint128| -> Result<Uint128, ContractError> {
+        let quote_asset_reserve =
+            calculate_stableswap_reserve(base_asset_reserve, invariant, amplification)?;
+        calculate_stableswap_price(
+            quote_asset_reserve,
+            base_asset_reserve,
+            amm.peg_multiplier,
+            invariant,
+            amplification,
+        )
+    };
+
+    let base_asset_reserve = amm.base_asset_reserve;
+    let is_long = limit_price > get_mark_price(amm)?;
+    let (mut low, mut high) = if is_long {
+        (max(base_asset_reserve / Uint128::from(16u128), Uint128::from(1u128)), base_asset_reserve)
+    } else {
+        (base_asset_reserve, base_asset_reserve.checked_mul(Uint128::from(16u128))?)
+    };
+
+    while high.checked_sub(low)? > Uint128::from(1u128) {
+        let mid = low.checked_add(high.checked_sub(low)? / Uint128::from(2u128))?;
+        if price_at(mid)? > limit_price {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    Ok(if is_long { high.u128() } else { low.u128() })
+}
+
 pub fn should_round_trade(
     a: &Amm,
     quote_asset_amount: Uint128,
@@ -426,9 +787,5 @@ pub fn should_round_trade(
 }
 
 pub fn get_mark_price(a: &Amm) -> Result<Uint128, ContractError> {
-    calculate_price(
-        a.quote_asset_reserve,
-        a.base_asset_reserve,
-        a.peg_multiplier,
-    )
+    calculate_curve_price(a, a.quote_asset_reserve, a.base_asset_reserve)
 }
@@ -2,7 +2,9 @@ use std::cmp::{max, min};
 
 use cosmwasm_std::{Addr, Uint128, Fraction, Decimal};
 
+use crate::states::constants::MAKER_FEE_PRECISION;
 use crate::states::order::OrderState;
+use crate::states::state::FeeTierVolumes;
 use crate::{error::ContractError};
 
 use num::integer::Roots;
@@ -13,13 +15,18 @@ pub fn calculate_fee_for_trade(
     quote_asset_amount: Uint128,
     fee_structure: &FeeStructure,
     discount_token_amt: Uint128,
+    volume_fee_tier: &OrderDiscountTier,
     referrer: &Option<Addr>,
 ) -> Result<(Uint128, Uint128, Uint128, Uint128, Uint128), ContractError> {
     let fee = quote_asset_amount
         .checked_mul(Uint128::from(fee_structure.fee.numerator()))?
         .checked_div(Uint128::from(fee_structure.fee.denominator()))?;
 
-    let token_discount = calculate_token_discount(fee, fee_structure, discount_token_amt)?;
+    // the better of the token balance discount and the trading volume discount
+    let token_discount = max(
+        calculate_token_discount(fee, fee_structure, discount_token_amt)?,
+        calculate_token_discount_for_limit_order(fee, fee_structure, volume_fee_tier)?,
+    );
 
     let (referrer_reward, referee_discount) =
         calculate_referral_reward_and_referee_discount(fee, fee_structure, referrer)?;
@@ -216,6 +223,103 @@ pub fn calculate_fee_for_order(
     }
 }
 
+// signed fee at the maker rate, negative values are rebates owed to the maker
+pub fn calculate_maker_fee(
+    quote_asset_amount: Uint128,
+    fee_structure: &FeeStructure,
+) -> Result<i128, ContractError> {
+    let maker_fee = (quote_asset_amount.u128() as i128)
+        .checked_mul(fee_structure.maker_fee.i128())
+        .ok_or_else(|| (ContractError::MathError))?
+        .checked_div(MAKER_FEE_PRECISION.u128() as i128)
+        .ok_or_else(|| (ContractError::MathError))?;
+
+    Ok(maker_fee)
+}
+
+// fees for a peer-to-peer match, the taker pays the order fee and the maker the signed maker fee
+pub fn calculate_fee_for_matched_order(
+    quote_asset_amount: Uint128,
+    fee_structure: &FeeStructure,
+    filler_reward_structure: &OrderState,
+    taker_order_fee_tier: &OrderDiscountTier,
+    taker_order_ts: u64,
+    now: u64,
+    taker_referrer: &Option<Addr>,
+    filler_is_taker: bool,
+) -> Result<(Uint128, i128, Uint128, Uint128, Uint128, Uint128, Uint128), ContractError> {
+    let (taker_fee, fee_to_market, token_discount, filler_reward, referrer_reward, referee_discount) =
+        calculate_fee_for_order(
+            quote_asset_amount,
+            fee_structure,
+            filler_reward_structure,
+            taker_order_fee_tier,
+            taker_order_ts,
+            now,
+            taker_referrer,
+            filler_is_taker,
+            Uint128::zero(),
+        )?;
+
+    let maker_fee = calculate_maker_fee(quote_asset_amount, fee_structure)?;
+
+    Ok((
+        taker_fee,
+        maker_fee,
+        fee_to_market,
+        token_discount,
+        filler_reward,
+        referrer_reward,
+        referee_discount,
+    ))
+}
+
+// tiers are ordered from First (largest discount) to Fourth
+fn tier_rank(tier: &OrderDiscountTier) -> u8 {
+    match tier {
+        OrderDiscountTier::First => 4,
+        OrderDiscountTier::Second => 3,
+        OrderDiscountTier::Third => 2,
+        OrderDiscountTier::Fourth => 1,
+        OrderDiscountTier::None => 0,
+    }
+}
+
+pub fn best_discount_tier(a: OrderDiscountTier, b: OrderDiscountTier) -> OrderDiscountTier {
+    if tier_rank(&a) >= tier_rank(&b) {
+        a
+    } else {
+        b
+    }
+}
+
+pub fn calculate_volume_fee_tier(
+    fee_tier_volumes: &FeeTierVolumes,
+    volume: Uint128,
+) -> OrderDiscountTier {
+    if volume.is_zero() {
+        return OrderDiscountTier::None;
+    }
+
+    if belongs_to_tier(fee_tier_volumes.first_tier_minimum_volume, volume) {
+        return OrderDiscountTier::First;
+    }
+
+    if belongs_to_tier(fee_tier_volumes.second_tier_minimum_volume, volume) {
+        return OrderDiscountTier::Second;
+    }
+
+    if belongs_to_tier(fee_tier_volumes.third_tier_minimum_volume, volume) {
+        return OrderDiscountTier::Third;
+    }
+
+    if belongs_to_tier(fee_tier_volumes.fourth_tier_minimum_volume, volume) {
+        return OrderDiscountTier::Fourth;
+    }
+
+    OrderDiscountTier::None
+}
+
 fn calculate_token_discount_for_limit_order(
     fee: Uint128,
     fee_structure: &FeeStructure,
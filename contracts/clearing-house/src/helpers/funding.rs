@@ -1,107 +1,247 @@
-use std::cmp::max;
+use std::cmp::{max, min};
 
+use ariel::number::Number128;
 use cosmwasm_std::{Uint128};
 
 use crate::error::ContractError;
 
-use crate::states::market::Market;
+use crate::states::market::{Amm, Market};
 use crate::states::user::Position;
 
 use crate::states::constants::{
-    AMM_TO_QUOTE_PRECISION_RATIO, FUNDING_PAYMENT_PRECISION, MARK_PRICE_PRECISION,
+    AMM_TO_QUOTE_PRECISION_RATIO, FUNDING_PAYMENT_PRECISION, MARK_PRICE_PRECISION, ONE_HOUR, PRICE_SPREAD_PRECISION,
     QUOTE_TO_BASE_AMT_FUNDING_PRECISION, SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR,SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR
 };
 
+/// Seconds after `last_funding_rate_ts` until the next funding update, rounded so updates land on the hour.
+pub fn calculate_next_update_wait(a: &Amm) -> Result<u64, ContractError> {
+    let mut next_update_wait = a.funding_period;
+    if a.funding_period > 1 {
+        let last_update_delay = a.last_funding_rate_ts.rem_euclid(a.funding_period);
+        if last_update_delay != 0 {
+            let max_delay_for_next_period = a
+                .funding_period
+                .checked_div(3)
+                .ok_or(ContractError::MathError)?;
+            if last_update_delay > max_delay_for_next_period {
+                // too late for on the hour next period, delay to following period
+                next_update_wait = a
+                    .funding_period
+                    .checked_mul(2)
+                    .ok_or(ContractError::MathError)?
+                    .checked_sub(last_update_delay)
+                    .ok_or(ContractError::MathError)?;
+            } else {
+                // allow update on the hour
+                next_update_wait = a
+                    .funding_period
+                    .checked_sub(last_update_delay)
+                    .ok_or(ContractError::MathError)?;
+            }
+        }
+    }
+    Ok(next_update_wait)
+}
+
+/// Funding periods per day, used to spread the daily premium over each period.
+pub fn calculate_period_adjustment(funding_period: u64) -> Result<i64, ContractError> {
+    let one_hour_i64 = ONE_HOUR.u128() as i64;
+    (24_i64)
+        .checked_mul(one_hour_i64)
+        .ok_or(ContractError::MathError)?
+        .checked_div(max(one_hour_i64, funding_period as i64))
+        .ok_or(ContractError::MathError)
+}
+
+/// Adds the premium of the previous sample to the premium index, weighted by the time it held, and
+/// keeps the current mark/oracle premium for the next sample.
+pub fn sample_premium_index(
+    a: &mut Amm,
+    mark_price: Uint128,
+    oracle_price: i128,
+    now: u64,
+) -> Result<(), ContractError> {
+    let premium = (mark_price.u128() as i128)
+        .checked_sub(oracle_price)
+        .ok_or(ContractError::MathError)?;
+    let since_last_sample = now.saturating_sub(a.premium_index_ts);
+    a.premium_index_sum = Number128::new(
+        a.premium_index_sum
+            .i128()
+            .checked_add(
+                a.last_premium
+                    .i128()
+                    .checked_mul(since_last_sample as i128)
+                    .ok_or(ContractError::MathError)?,
+            )
+            .ok_or(ContractError::MathError)?,
+    );
+    a.premium_index_duration = a
+        .premium_index_duration
+        .checked_add(since_last_sample)
+        .ok_or(ContractError::MathError)?;
+    a.last_premium = Number128::new(premium);
+    a.premium_index_ts = now;
+    Ok(())
+}
+
+/// Average premium over the period, or the twap spread when nothing has been sampled yet.
+pub fn calculate_premium(
+    a: &Amm,
+    mark_price_twap: Uint128,
+    oracle_price_twap: i128,
+) -> Result<i128, ContractError> {
+    if a.premium_index_duration > 0 {
+        a.premium_index_sum
+            .i128()
+            .checked_div(a.premium_index_duration as i128)
+            .ok_or(ContractError::MathError)
+    } else {
+        (mark_price_twap.u128() as i128)
+            .checked_sub(oracle_price_twap)
+            .ok_or(ContractError::MathError)
+    }
+}
+
+/// Funding rate for a period from the premium index (daily mark/oracle spread), an interest rate baseline
+/// optionally clamped around the premium, and the market's per period cap.
+pub fn calculate_funding_rate(
+    a: &Amm,
+    premium: i128,
+    oracle_price_twap: i128,
+    period_adjustment: i64,
+) -> Result<i128, ContractError> {
+    let fraction_of_oracle = |value: u32| -> Result<i128, ContractError> {
+        oracle_price_twap
+            .checked_mul(value as i128)
+            .ok_or(ContractError::MathError)?
+            .checked_div(PRICE_SPREAD_PRECISION)
+            .ok_or(ContractError::MathError)
+    };
+
+    let interest = fraction_of_oracle(a.interest_rate)?;
+    let mut interest_adjustment = interest
+        .checked_sub(premium)
+        .ok_or(ContractError::MathError)?;
+    if a.funding_clamp > 0 {
+        let clamp = fraction_of_oracle(a.funding_clamp)?;
+        interest_adjustment = max(-clamp, min(clamp, interest_adjustment));
+    } else {
+        interest_adjustment = interest;
+    }
+    let price_spread = premium
+        .checked_add(interest_adjustment)
+        .ok_or(ContractError::MathError)?;
+
+    let mut funding_rate = price_spread
+        .checked_mul(FUNDING_PAYMENT_PRECISION.u128() as i128)
+        .ok_or(ContractError::MathError)?
+        .checked_div(period_adjustment as i128)
+        .ok_or(ContractError::MathError)?;
+
+    if a.max_funding_rate > 0 {
+        let max_funding_rate = fraction_of_oracle(a.max_funding_rate)?
+            .checked_mul(FUNDING_PAYMENT_PRECISION.u128() as i128)
+            .ok_or(ContractError::MathError)?;
+        funding_rate = max(-max_funding_rate, min(max_funding_rate, funding_rate));
+    }
+
+    Ok(funding_rate)
+}
+
+pub struct FundingPoolUpdate {
+    pub total_fee_minus_distributions: Uint128,
+    pub funding_pool: Uint128,
+    pub funding_shortfall: Number128,
+    pub capped_funding_amount: Uint128,
+}
+
 /// With a virtual AMM, there can be an imbalance between longs and shorts and thus funding can be asymmetric.
 /// To account for this, amm keeps track of the cumulative funding rate for both longs and shorts.
-/// When there is a period with asymmetric funding, the clearing house will pay/receive funding from/to it's collected fees.
+/// Net funding paid by users accrues to the market's funding pool. When the imbalanced side is owed funding,
+/// it is paid at most the pool plus `funding_fee_share` of the distributable fees and the rest carries forward.
 pub fn calculate_funding_rate_long_short(
     market: &Market,
     funding_rate: i128,
-) -> Result<(i128, i128, Uint128), ContractError> {
+) -> Result<(i128, i128, FundingPoolUpdate), ContractError> {
     // Calculate the funding payment owed by the net_market_position if funding is not capped
-    // If the net market position owes funding payment, the clearing house receives payment
+    // If the net market position owes funding payment, the funding pool receives payment
     let net_market_position = market.base_asset_amount.i128().clone();
     let net_market_position_funding_payment =
         calculate_funding_payment_in_quote_precision(funding_rate, net_market_position)?;
     let uncapped_funding_pnl = -net_market_position_funding_payment;
 
-    // If the uncapped_funding_pnl is positive, the clearing house receives money.
     if uncapped_funding_pnl >= 0 {
-        let new_total_fee_minus_distributions = market
+        let funding_pool = market
             .amm
-            .total_fee_minus_distributions
+            .funding_pool
             .checked_add(Uint128::from(uncapped_funding_pnl.unsigned_abs()))?;
-        return Ok((funding_rate, funding_rate, new_total_fee_minus_distributions));
+        return Ok((
+            funding_rate,
+            funding_rate,
+            FundingPoolUpdate {
+                total_fee_minus_distributions: market.amm.total_fee_minus_distributions,
+                funding_pool,
+                funding_shortfall: market.amm.funding_shortfall,
+                capped_funding_amount: Uint128::zero(),
+            },
+        ));
     }
 
-    let (capped_funding_rate, capped_funding_pnl) =
-        calculate_capped_funding_rate(&market, uncapped_funding_pnl, funding_rate)?;
-
-    let new_total_fee_minus_distributions = market
-        .amm
-        .total_fee_minus_distributions
-        .checked_sub(Uint128::from(capped_funding_pnl.unsigned_abs()))?;
-
-    // clearing house is paying part of funding imbalance
-    if capped_funding_pnl != 0 {
-        let total_fee_minus_distributions_lower_bound = market
-            .amm
-            .total_fee
-            .checked_mul(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR)?
-            .checked_div(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR)?;
-
-        // makes sure the clearing house doesn't pay more than the share of fees allocated to `distributions`
-        if new_total_fee_minus_distributions.lt(&total_fee_minus_distributions_lower_bound) {
-            return Err(ContractError::InvalidFundingProfitability.into());
-        }
-    }
-    
-    let funding_rate_long = if funding_rate < 0 {
-        capped_funding_rate
+    // a shortfall only carries forward while the same side keeps receiving funding
+    let longs_receive = funding_rate < 0;
+    let funding_shortfall = market.amm.funding_shortfall.i128();
+    let carried_shortfall = if (longs_receive && funding_shortfall > 0)
+        || (!longs_receive && funding_shortfall < 0)
+    {
+        funding_shortfall.unsigned_abs()
     } else {
-        funding_rate
+        0
     };
+    let funding_owed = uncapped_funding_pnl
+        .unsigned_abs()
+        .checked_add(carried_shortfall)
+        .ok_or(ContractError::MathError)?;
 
-    let funding_rate_short = if funding_rate > 0 {
-        capped_funding_rate
-    } else {
-        funding_rate
-    };
-
-    return Ok((funding_rate_long, funding_rate_short, new_total_fee_minus_distributions));
-}
-
-fn calculate_capped_funding_rate(
-    market: &Market,
-    uncapped_funding_pnl: i128, // if negative, users would net recieve from clearinghouse
-    funding_rate: i128,
-) -> Result<(i128, i128), ContractError> {
-    // The funding_rate_pnl_limit is the amount of fees the clearing house can use before it hits it's lower bound
     let total_fee_minus_distributions_lower_bound = market
         .amm
         .total_fee
         .checked_mul(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR)?
         .checked_div(SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR)?;
+    let fee_budget = market
+        .amm
+        .total_fee_minus_distributions
+        .saturating_sub(total_fee_minus_distributions_lower_bound)
+        .checked_mul(Uint128::from(market.amm.funding_fee_share))?
+        .checked_div(Uint128::from(PRICE_SPREAD_PRECISION as u128))?;
+    let funding_budget = market.amm.funding_pool.checked_add(fee_budget)?;
+
+    let funding_paid = min(funding_owed, funding_budget.u128());
+    let capped_funding_amount = funding_owed
+        .checked_sub(funding_paid)
+        .ok_or(ContractError::MathError)?;
+
+    // the pool pays first, fees cover the rest
+    let paid_from_pool = min(funding_paid, market.amm.funding_pool.u128());
+    let funding_pool = market
+        .amm
+        .funding_pool
+        .checked_sub(Uint128::from(paid_from_pool))?;
+    let new_total_fee_minus_distributions = market
+        .amm
+        .total_fee_minus_distributions
+        .checked_sub(Uint128::from(funding_paid - paid_from_pool))?;
 
-    // limit to 2/3 of current fee pool per funding period
-    let funding_rate_pnl_limit =
-        if market.amm.total_fee_minus_distributions > total_fee_minus_distributions_lower_bound {
-            -(market
-                    .amm
-                    .total_fee_minus_distributions
-                    .checked_sub(total_fee_minus_distributions_lower_bound)?
-                    .checked_mul(Uint128::from(2 as u32))?
-                    .checked_div(Uint128::from(3 as u32))?
-                    .u128() as i128)
-        } else {
-            0
-        };
+    // makes sure the clearing house doesn't pay more than the share of fees allocated to `distributions`
+    if funding_paid > paid_from_pool
+        && new_total_fee_minus_distributions.lt(&total_fee_minus_distributions_lower_bound)
+    {
+        return Err(ContractError::InvalidFundingProfitability.into());
+    }
 
-    // if theres enough in fees, give user's uncapped funding
-    // if theres a little/nothing in fees, give the user's capped outflow funding
-    let capped_funding_pnl = max(uncapped_funding_pnl, funding_rate_pnl_limit);
-    let capped_funding_rate = if uncapped_funding_pnl < funding_rate_pnl_limit {
+    let capped_funding_rate = if funding_paid == uncapped_funding_pnl.unsigned_abs() {
+        funding_rate
+    } else {
         // Calculate how much funding payment is already available from users
         let funding_payment_from_users = if funding_rate > 0 {
             calculate_funding_payment_in_quote_precision(
@@ -115,30 +255,52 @@ fn calculate_capped_funding_rate(
             )
         }?;
 
-        // increase the funding_rate_pnl_limit by accounting for the funding payment already being made by users
-        // this makes it so that the capped rate includes funding payments from users and clearing house collected fees
-        let funding_rate_pnl_limit = funding_rate_pnl_limit
-            .checked_sub(funding_payment_from_users.abs())
-            .ok_or_else(|| (ContractError::MathError))?;
+        // the receiving side gets what users pay plus what the pool and fees can cover
+        let funding_rate_pnl_limit = -(funding_paid as i128)
+            .checked_add(funding_payment_from_users.abs())
+            .ok_or(ContractError::MathError)?;
 
-        if funding_rate < 0 {
-            // longs receive
+        if longs_receive {
             calculate_funding_rate_from_pnl_limit(
                 funding_rate_pnl_limit,
                 market.base_asset_amount_long.i128(),
             )?
         } else {
-            // shorts receive
             calculate_funding_rate_from_pnl_limit(
                 funding_rate_pnl_limit,
                 market.base_asset_amount_short.i128(),
             )?
         }
+    };
+
+    let funding_rate_long = if funding_rate < 0 {
+        capped_funding_rate
     } else {
         funding_rate
     };
 
-    return Ok((capped_funding_rate, capped_funding_pnl));
+    let funding_rate_short = if funding_rate > 0 {
+        capped_funding_rate
+    } else {
+        funding_rate
+    };
+
+    let funding_shortfall = if longs_receive {
+        capped_funding_amount as i128
+    } else {
+        -(capped_funding_amount as i128)
+    };
+
+    return Ok((
+        funding_rate_long,
+        funding_rate_short,
+        FundingPoolUpdate {
+            total_fee_minus_distributions: new_total_fee_minus_distributions,
+            funding_pool,
+            funding_shortfall: Number128::new(funding_shortfall),
+            capped_funding_amount: Uint128::from(capped_funding_amount),
+        },
+    ));
 }
 
 pub fn calculate_funding_payment(
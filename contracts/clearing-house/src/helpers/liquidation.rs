@@ -0,0 +1,126 @@
+use std::cmp::min;
+
+use cosmwasm_std::{Decimal, Fraction, Uint128};
+
+use ariel::types::PositionDirection;
+
+use crate::error::ContractError;
+use crate::states::constants::{
+    MAX_LIQUIDATION_SLIPPAGE, PRICE_SPREAD_PRECISION_U128, QUOTE_TO_BASE_AMT_FUNDING_PRECISION,
+};
+use crate::states::liquidation::LiquidationAuctionConfig;
+use crate::states::market::LiquidationType;
+use crate::states::state::State;
+
+pub fn calculate_auction_discount(
+    config: &LiquidationAuctionConfig,
+    start_ts: u64,
+    now: u64,
+) -> Result<Decimal, ContractError> {
+    if config.duration == 0 {
+        return Ok(config.max_discount);
+    }
+    let elapsed = min(now.saturating_sub(start_ts), config.duration);
+
+    // both discounts share the same denominator
+    let start = Uint128::from(config.start_discount.numerator());
+    let max = Uint128::from(config.max_discount.numerator());
+    let discount = start.checked_add(
+        max.checked_sub(start)?
+            .checked_mul(Uint128::from(elapsed))?
+            .checked_div(Uint128::from(config.duration))?,
+    )?;
+
+    Ok(Decimal::from_ratio(
+        discount,
+        config.start_discount.denominator(),
+    ))
+}
+
+/// Price a liquidator takes over the position at: below mark for longs, above mark for shorts.
+pub fn calculate_auction_price(
+    mark_price: Uint128,
+    discount: Decimal,
+    direction: PositionDirection,
+) -> Result<Uint128, ContractError> {
+    let price_discount = mark_price
+        .checked_mul(Uint128::from(discount.numerator()))?
+        .checked_div(Uint128::from(discount.denominator()))?;
+
+    let price = match direction {
+        PositionDirection::Long => mark_price.checked_sub(price_discount)?,
+        PositionDirection::Short => mark_price.checked_add(price_discount)?,
+    };
+    Ok(price)
+}
+
+/// Base asset amount of a position that can be handed to liquidators: all of it for a full
+/// liquidation, the partial close percentage otherwise.
+pub fn calculate_liquidatable_base_asset_amount(
+    position_base_asset_amount: i128,
+    liquidation_type: &LiquidationType,
+    partial_liquidation_close_percentage: Decimal,
+) -> Result<Uint128, ContractError> {
+    let base_asset_amount = Uint128::from(position_base_asset_amount.unsigned_abs());
+    if *liquidation_type == LiquidationType::PARTIAL {
+        return Ok(base_asset_amount
+            .checked_mul(Uint128::from(partial_liquidation_close_percentage.numerator()))?
+            .checked_div(Uint128::from(partial_liquidation_close_percentage.denominator()))?);
+    }
+    Ok(base_asset_amount)
+}
+
+pub fn is_price_within_liquidation_slippage(
+    price: Uint128,
+    oracle_price: Uint128,
+) -> Result<bool, ContractError> {
+    let spread = if price > oracle_price {
+        price.checked_sub(oracle_price)?
+    } else {
+        oracle_price.checked_sub(price)?
+    };
+    let spread_pct = spread
+        .checked_mul(PRICE_SPREAD_PRECISION_U128)?
+        .checked_div(oracle_price)?;
+    Ok(spread_pct <= MAX_LIQUIDATION_SLIPPAGE)
+}
+
+/// Funding rate change that charges `loss` pro rata to every unit of open base asset,
+/// long or short. Longs pay as the long rate rises, shorts as the short rate falls.
+pub fn calculate_socialized_loss_funding_rate_delta(
+    loss: Uint128,
+    base_asset_amount_long: i128,
+    base_asset_amount_short: i128,
+) -> Result<Uint128, ContractError> {
+    let open_base_asset_amount = Uint128::from(base_asset_amount_long.unsigned_abs())
+        .checked_add(Uint128::from(base_asset_amount_short.unsigned_abs()))?;
+    if open_base_asset_amount.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    Ok(loss
+        .checked_mul(QUOTE_TO_BASE_AMT_FUNDING_PRECISION)?
+        .checked_div(open_base_asset_amount)?)
+}
+
+/// Liquidator's share of the liquidation penalty if the whole penalty can be paid out.
+pub fn calculate_estimated_liquidator_reward(
+    state: &State,
+    liquidation_type: &LiquidationType,
+    total_collateral: Uint128,
+) -> Result<Uint128, ContractError> {
+    let (penalty_percentage, liquidator_share_denominator) = match liquidation_type {
+        LiquidationType::NONE => return Ok(Uint128::zero()),
+        LiquidationType::PARTIAL => (
+            state.partial_liquidation_penalty_percentage,
+            state.partial_liquidation_liquidator_share_denominator,
+        ),
+        LiquidationType::FULL => (
+            state.full_liquidation_penalty_percentage,
+            state.full_liquidation_liquidator_share_denominator,
+        ),
+    };
+    Ok(total_collateral
+        .checked_mul(Uint128::from(penalty_percentage.numerator()))?
+        .checked_div(Uint128::from(penalty_percentage.denominator()))?
+        .checked_div(Uint128::from(liquidator_share_denominator))?)
+}
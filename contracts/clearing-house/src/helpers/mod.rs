@@ -1,6 +1,7 @@
 pub mod amm;
 pub mod fees;
 pub mod funding;
+pub mod liquidation;
 pub mod oracle;
 pub mod position;
 pub mod order;
\ No newline at end of file
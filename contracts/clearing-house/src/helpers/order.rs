@@ -43,6 +43,26 @@ pub fn calculate_base_asset_amount_market_can_execute(
     }
 }
 
+/// Whether a trigger order has triggered; a trigger limit order stays triggered once it starts filling
+pub fn is_trigger_condition_satisfied(
+    order: &Order,
+    market: &Market,
+    precomputed_mark_price: Option<Uint128>,
+    valid_oracle_price: Option<i128>,
+) -> Result<bool, ContractError> {
+    if order.order_type == OrderType::TriggerLimit && !order.base_asset_amount_filled.is_zero() {
+        return Ok(true);
+    }
+
+    let base_asset_amount = calculate_base_asset_amount_to_trade_for_trigger_market(
+        order,
+        market,
+        precomputed_mark_price,
+        valid_oracle_price,
+    )?;
+    Ok(!base_asset_amount.is_zero())
+}
+
 pub fn calculate_base_asset_amount_to_trade_for_limit(
     order: &Order,
     market: &Market,
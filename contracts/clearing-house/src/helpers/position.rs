@@ -43,6 +43,14 @@ pub fn calculate_updated_collateral(collateral: Uint128, pnl: i128) -> Result<Ui
     });
 }
 
+/// Part of a realized loss that the collateral can not cover.
+pub fn calculate_collateral_shortfall(collateral: Uint128, pnl: i128) -> Uint128 {
+    if pnl.is_negative() && pnl.unsigned_abs() > collateral.u128() {
+        Uint128::from(pnl.unsigned_abs() - collateral.u128())
+    } else {
+        Uint128::zero()
+    }
+}
 
 pub fn calculate_slippage(
     exit_value: Uint128,
@@ -112,10 +120,10 @@ pub fn _calculate_base_asset_value_and_pnl(
     let swap_direction = swap_direction_to_close_position(base_asset_amount);
 
     let (new_quote_asset_reserve, _new_base_asset_reserve) = amm::calculate_swap_output(
+        a,
         Uint128::from(base_asset_amount.unsigned_abs()),
         a.base_asset_reserve,
         swap_direction,
-        a.sqrt_k,
     )?;
 
     let base_asset_value = calculate_quote_asset_amount_swapped(
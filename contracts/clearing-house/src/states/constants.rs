@@ -25,8 +25,12 @@ pub const MARK_PRICE_TIMES_AMM_TO_QUOTE_PRECISION_RATIO: Uint128 =
 // FEE REBATES
 pub const UPDATE_K_ALLOWED_PRICE_CHANGE: Uint128 = Uint128::new(1_000_000_000); // MARK_PRICE_PRECISION / Uint128::new(10));
 
+pub const MAX_FUNDING_UPDATE_REWARD: Uint128 = Uint128::new(1_000_000); // $1 per funding period
+
 // TIME PERIODS
 pub const ONE_HOUR: Uint128 =  Uint128::new(3600);
+pub const ONE_DAY: u64 = 86_400;
+pub const VOLUME_WINDOW_DAYS: u64 = 30;
 
 // FEES
 pub const SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_NUMERATOR: Uint128 = Uint128::new(5);
@@ -35,6 +39,9 @@ pub const SHARE_OF_FEES_ALLOCATED_TO_CLEARING_HOUSE_DENOMINATOR: Uint128 = Uint1
 pub const DEFAULT_FEE_NUMERATOR: Uint128 = Uint128::new(1);
 pub const DEFAULT_FEE_DENOMINATOR: Uint128 = Uint128::new(1000);
 
+pub const MAKER_FEE_PRECISION: Uint128 = Uint128::new(1_000_000); // expo = -6
+pub const DEFAULT_MAKER_FEE: i128 = 0;
+
 pub const DEFAULT_DISCOUNT_TOKEN_FIRST_TIER_MINIMUM_BALANCE: Uint128 = Uint128::new(1_000_000_000_000); // 1000
 
 pub const DEFAULT_DISCOUNT_TOKEN_FIRST_TIER_DISCOUNT_NUMERATOR: Uint128 = Uint128::new(20);
@@ -55,6 +62,11 @@ pub const DEFAULT_DISCOUNT_TOKEN_FOURTH_TIER_MINIMUM_BALANCE: Uint128 = Uint128:
 pub const DEFAULT_DISCOUNT_TOKEN_FOURTH_TIER_DISCOUNT_NUMERATOR: Uint128 = Uint128::new(5);
 pub const DEFAULT_DISCOUNT_TOKEN_FOURTH_TIER_DISCOUNT_DENOMINATOR: Uint128 = Uint128::new(100);
 
+pub const DEFAULT_VOLUME_FIRST_TIER_MINIMUM: Uint128 = Uint128::new(10_000_000_000_000); // $10M
+pub const DEFAULT_VOLUME_SECOND_TIER_MINIMUM: Uint128 = Uint128::new(1_000_000_000_000); // $1M
+pub const DEFAULT_VOLUME_THIRD_TIER_MINIMUM: Uint128 = Uint128::new(100_000_000_000); // $100k
+pub const DEFAULT_VOLUME_FOURTH_TIER_MINIMUM: Uint128 = Uint128::new(10_000_000_000); // $10k
+
 pub const DEFAULT_REFERRER_REWARD_NUMERATOR: Uint128 = Uint128::new(5);
 pub const DEFAULT_REFERRER_REWARD_DENOMINATOR: Uint128 = Uint128::new(100);
 
@@ -64,7 +76,15 @@ pub const DEFAULT_REFEREE_DISCOUNT_DENOMINATOR: Uint128 = Uint128::new(100);
 // CONSTRAINTS
 pub const MAX_LIQUIDATION_SLIPPAGE: Uint128 = Uint128::new(100); // expo = -2
 pub const MAX_LIQUIDATION_SLIPPAGE_U128: Uint128 = Uint128::new(100); // expo = -2
+pub const DEFAULT_LIQUIDATION_AUCTION_DURATION: u64 = 300; // 5 minutes
+pub const DEFAULT_LIQUIDATION_AUCTION_MAX_DISCOUNT_PERCENT: u64 = 5;
+pub const DYNAMIC_K_STEP_NUMERATOR: Uint128 = Uint128::new(1); // sqrt_k moves at most 1% per funding update
+pub const DYNAMIC_K_STEP_DENOMINATOR: Uint128 = Uint128::new(100);
+pub const DEFAULT_FUNDING_FEE_SHARE: u32 = 6_667; // expo = -4
+pub const DEFAULT_ORACLE_TWAP_CAP_PERCENT: u32 = 33;
 pub const MAX_MARK_TWAP_DIVERGENCE: Uint128 = Uint128::new(5_000); // expo = -3
+pub const MAX_STABLESWAP_AMPLIFICATION: u64 = 1_000_000;
+pub const STABLESWAP_MAX_ITERATIONS: u32 = 255;
 pub const MAXIMUM_MARGIN_RATIO: Uint128 = MARGIN_PRECISION;
 pub const MINIMUM_MARGIN_RATIO: Uint128 =  Uint128::new(200);// MARGIN_PRECISION / Uint128::new(50);
 
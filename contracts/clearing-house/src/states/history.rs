@@ -2,27 +2,17 @@ use ariel::number::Number128;
 use cosmwasm_std::{Uint128, Addr};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use cw_storage_plus::{Map, Item};
-use ariel::types::{Order, PositionDirection, DepositDirection};
+use cw_storage_plus::{Map, Item, U64Key};
+use ariel::types::{Order, OrderAction, PositionDirection, DepositDirection, LiquidatedMarket};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub enum Type {
-    Repeg,
-    UpdateK,
-}
-
-impl Default for Type {
-    // UpOnly
-    fn default() -> Self {
-        Type::Repeg
-    }
-}
+pub use ariel::types::Type;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct CurveRecord {
     pub ts: u64,
     pub record_id: u64,
     pub market_index: u64,
+    pub record_type: Type,
     pub peg_multiplier_before: Uint128,
     pub peg_multiplier_after: Uint128,
     pub base_asset_reserve_before: Uint128,
@@ -99,6 +89,9 @@ pub struct FundingRateRecord {
     pub cumulative_funding_rate_short: Number128,
     pub oracle_price_twap: Number128,
     pub mark_price_twap: Uint128,
+    pub capped_funding_amount: Uint128,
+    pub funding_pool: Uint128,
+    pub funding_shortfall: Number128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -118,7 +111,9 @@ pub struct LiquidationRecord {
     pub ts: u64,
     pub record_id: u64,
     pub user: Addr,
+    pub market_index: u64, // market picked by the liquidator
     pub partial: bool,
+    pub markets: Vec<LiquidatedMarket>,
     pub base_asset_value: Uint128,
     pub base_asset_value_closed: Uint128,
     pub liquidation_fee: Uint128,
@@ -140,20 +135,25 @@ pub const LIQUIDATION_HISTORY: Map<(Addr, String),  LiquidationRecord> = Map::ne
 pub const LIQUIDATION_HISTORY_INFO: Item<LiquidationInfo> = Item::new("liquidation_history_info");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub enum OrderAction {
-    Place,
-    Cancel,
-    Fill,
-    Expire,
+pub struct BankruptcyRecord {
+    pub ts: u64,
+    pub record_id: u64,
+    pub user: Addr,
+    pub market_index: u64,
+    pub loss: Uint128,               // realized loss the user's collateral could not cover
+    pub insurance_fund_paid: Uint128,
+    pub socialized_loss: Uint128,    // remainder charged to open interest through funding
+    pub cumulative_funding_rate_delta: Uint128,
 }
 
-impl Default for OrderAction {
-    // UpOnly
-    fn default() -> Self {
-        OrderAction::Place
-    }
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BankruptcyInfo {
+    pub len: u64,
 }
 
+pub const BANKRUPTCY_HISTORY: Map<(&Addr, String), BankruptcyRecord> = Map::new("bankruptcy_history");
+pub const BANKRUPTCY_HISTORY_INFO: Item<BankruptcyInfo> = Item::new("bankruptcy_history_info");
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct OrderRecord {
     pub ts: u64,
@@ -165,6 +165,7 @@ pub struct OrderRecord {
     pub base_asset_amount_filled: Uint128,
     pub quote_asset_amount_filled: Uint128,
     pub fee: Uint128,
+    pub maker_fee: Number128,
     pub filler_reward: Uint128,
     pub quote_asset_amount_surplus: Uint128,
     pub position_index: u64,
@@ -175,7 +176,8 @@ pub struct OrderHisInfo {
     pub len: u64,
 }
 
-pub const ORDER_HISTORY: Map<String,  OrderRecord> = Map::new("order_history");
+// keyed by the numeric record id so pages come back in the order the records were written
+pub const ORDER_HISTORY: Map<U64Key, OrderRecord> = Map::new("order_history");
 pub const ORDER_HISTORY_INFO: Item<OrderHisInfo> = Item::new("order_history_info");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -188,6 +190,8 @@ pub struct TradeRecord {
     pub mark_price_before: Uint128,
     pub mark_price_after: Uint128,
     pub fee: Uint128,
+    // signed fee at the maker rate, negative when a rebate was paid
+    pub maker_fee: Number128,
     pub referrer_reward: Uint128,
     pub referee_discount: Uint128,
     pub token_discount: Uint128,
@@ -0,0 +1,35 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw_storage_plus::{Item, Map};
+
+use ariel::types::PositionDirection;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidationAuctionConfig {
+    // when enabled, positions are auctioned to liquidators before being closed into the amm
+    pub enabled: bool,
+    pub duration: u64,
+    // discount to mark offered to liquidators, grows linearly from start to max over the auction
+    pub start_discount: Decimal,
+    pub max_discount: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidationAuction {
+    pub direction: PositionDirection, // direction of the liquidated position
+    pub base_asset_amount: Uint128,   // remaining amount up for auction
+    pub partial: bool,
+    pub start_ts: u64,
+    pub end_ts: u64,
+}
+
+pub const LIQUIDATION_AUCTION_CONFIG: Item<LiquidationAuctionConfig> =
+    Item::new("liquidation_auction_config");
+// (user, market_index) -> running auction
+pub const LIQUIDATION_AUCTIONS: Map<(&Addr, String), LiquidationAuction> =
+    Map::new("liquidation_auctions");
+// (user, market_index) -> realized loss beyond the user's collateral, waiting to be resolved
+pub const PENDING_BANKRUPTCY_LOSSES: Map<(&Addr, String), Uint128> =
+    Map::new("pending_bankruptcy_losses");
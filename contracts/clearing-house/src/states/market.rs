@@ -6,7 +6,7 @@ use cosmwasm_std::{Addr, Uint128};
 
 use cw_storage_plus::Map;
 
-use ariel::types::{OracleSource, OracleStatus, OraclePriceData};
+use ariel::types::{CurveType, OracleSource, OracleStatus, OraclePriceData};
 
 use crate::error::ContractError;
 
@@ -51,17 +51,35 @@ pub struct Amm {
     pub last_oracle_price: Number128,
     pub last_oracle_price_twap: Number128,
     pub minimum_base_asset_trade_size: Uint128,
+    pub repeg_budget: Uint128, // most the fee pool spends on a single formulaic repeg
+    pub dynamic_k_enabled: bool,
+    pub minimum_sqrt_k: Uint128,
+    pub maximum_sqrt_k: Uint128,
+    pub last_k_update_open_interest: Uint128,
+    pub last_k_update_total_fee_minus_distributions: Uint128,
+    pub base_spread: u32, // expo = -4
+    pub max_spread: u32,  // expo = -4
+    pub total_spread_revenue: Uint128,
+    pub max_funding_rate: u32, // expo = -4, of the oracle twap per period, zero disables the cap
+    pub interest_rate: u32,    // expo = -4, of the oracle twap per day
+    pub funding_clamp: u32,    // expo = -4, of the oracle twap, zero disables the clamp
+    pub premium_index_sum: Number128, // mark/oracle premium weighted by seconds since the last funding update
+    pub premium_index_duration: u64,
+    pub premium_index_ts: u64,
+    pub last_premium: Number128, // mark/oracle premium at the last sample, held until the next one
+    pub funding_pool: Uint128,
+    pub funding_fee_share: u32, // expo = -4, of the distributable fees the funding pool may draw per period
+    pub funding_shortfall: Number128, // funding still owed, positive to longs and negative to shorts
+    pub oracle_twap_period: u64,
+    pub oracle_twap_cap_percent: u32, // largest move of a single oracle twap update, in percent of the price
+    pub curve_type: CurveType,
 }
 
 pub const MARKETS: Map<String, Market> = Map::new("markets");
 
 impl Amm {
     pub fn mark_price(&self) -> Result<Uint128, ContractError> {
-        amm::calculate_price(
-            self.quote_asset_reserve,
-            self.base_asset_reserve,
-            self.peg_multiplier,
-        )
+        amm::calculate_curve_price(self, self.quote_asset_reserve, self.base_asset_reserve)
     }
 
     pub fn get_oracle_price(
@@ -103,12 +121,7 @@ impl Amm {
     // }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub enum LiquidationType {
-    NONE,
-    PARTIAL,
-    FULL,
-}
+pub use ariel::types::LiquidationType;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct LiquidationStatus {
@@ -3,4 +3,6 @@ pub mod order;
 pub mod state;
 pub mod user;
 pub mod history;
-pub mod constants;
\ No newline at end of file
+pub mod constants;
+pub mod liquidation;
+pub mod referral;
\ No newline at end of file
@@ -4,8 +4,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::ContractError;
 
-use cosmwasm_std::{Addr, Decimal, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Decimal, Storage, Uint128};
+use cw_storage_plus::{Item, Map, U64Key};
 
 // use ariel::types::OracleSource;
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -22,8 +22,22 @@ pub struct OrderInfo {
     pub len: u64,
 }
 
-pub const ORDERS: Map<((&Addr, String), String), Order> = Map::new("orders");
+// orders are keyed by (user, order_id), order ids are assigned from User.next_order_id
+// and stored big-endian so a user's orders iterate in id order
+pub const ORDERS: Map<(&Addr, U64Key), Order> = Map::new("orders");
 pub const ORDERS_INFO: Item<OrderInfo> = Item::new("order_info");
+// client assigned user_order_id -> order_id, only kept while the order is open
+pub const USER_ORDER_IDS: Map<(&Addr, String), u64> = Map::new("user_order_ids");
+
+pub fn get_order_id_for_user_order_id(
+    storage: &dyn Storage,
+    user_addr: &Addr,
+    user_order_id: u64,
+) -> Result<u64, ContractError> {
+    USER_ORDER_IDS
+        .may_load(storage, (user_addr, user_order_id.to_string()))?
+        .ok_or(ContractError::OrderDoesNotExist)
+}
 
 pub fn has_oracle_price_offset(oo: &Order) -> bool {
     oo.oracle_price_offset.i128() != 0
@@ -0,0 +1,28 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw_storage_plus::Map;
+
+// admin set rates that replace the fee structure's referrer_reward and referee_discount
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferrerRewardRate {
+    pub referrer_reward: Decimal,
+    pub referee_discount: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Referrer {
+    pub codes: Vec<String>,
+    pub reward_rate: Option<ReferrerRewardRate>,
+    pub total_claimed: Uint128,
+}
+
+// referral code -> referrer
+pub const REFERRAL_CODES: Map<&str, Addr> = Map::new("referral_codes");
+pub const REFERRERS: Map<&Addr, Referrer> = Map::new("referrers");
+// (referrer, referee) -> reward earned from the referee's fees
+pub const REFEREES: Map<(&Addr, &Addr), Uint128> = Map::new("referees");
+// (referrer, market_index) -> reward not yet claimed, held in that market's total_fee_minus_distributions
+pub const UNCLAIMED_REFERRAL_REWARDS: Map<(&Addr, String), Uint128> =
+    Map::new("unclaimed_referral_rewards");
@@ -5,7 +5,7 @@ use cosmwasm_std::{Addr, Decimal, Uint128};
 use cw_controllers::Admin;
 use cw_storage_plus::Item;
 
-use ariel::types::{FeeStructure, OracleGuardRails};
+use ariel::types::{DiscountToken, FeeStructure, OracleGuardRails};
 
 use super::order::OrderState;
 
@@ -35,8 +35,26 @@ pub struct State {
     pub markets_length: u64,
 }
 
+// minimum rolling 30 day quote volume for each fee tier
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTierVolumes {
+    pub first_tier_minimum_volume: Uint128,
+    pub second_tier_minimum_volume: Uint128,
+    pub third_tier_minimum_volume: Uint128,
+    pub fourth_tier_minimum_volume: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DiscountTokenConfig {
+    pub token: DiscountToken,
+    // balances staked here count towards the discount tiers as well
+    pub staking_contract: Option<Addr>,
+}
+
 pub const STATE: Item<State> = Item::new("state");
 pub const ADMIN: Admin = Admin::new("admin");
 pub const FEESTRUCTURE: Item<FeeStructure> = Item::new("fee_structure");
 pub const ORACLEGUARDRAILS: Item<OracleGuardRails> = Item::new("oracle_guard_rails");
-pub const ORDERSTATE: Item<OrderState> = Item::new("order_state");
\ No newline at end of file
+pub const ORDERSTATE: Item<OrderState> = Item::new("order_state");
+pub const FEE_TIER_VOLUMES: Item<FeeTierVolumes> = Item::new("fee_tier_volumes");
+pub const DISCOUNT_TOKEN: Item<DiscountTokenConfig> = Item::new("discount_token");
\ No newline at end of file
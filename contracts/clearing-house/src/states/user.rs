@@ -2,9 +2,13 @@ use ariel::number::Number128;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Uint128};
 use cw_storage_plus::Map;
 
+use crate::error::ContractError;
+
+use super::constants::{ONE_DAY, VOLUME_WINDOW_DAYS};
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct User {
     pub collateral: Uint128,
@@ -14,6 +18,7 @@ pub struct User {
     pub total_referral_reward: Uint128,
     pub total_referee_discount: Uint128,
     pub referrer: Option<Addr>,
+    pub next_order_id: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -27,8 +32,29 @@ pub struct Position {
     pub order_length: u64,
 }
 
+// quote asset volume traded per day, newest day first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct UserVolume {
+    pub daily_volume: Vec<Uint128>,
+    pub last_day: u64,
+}
+
 pub const USERS: Map<&Addr, User> = Map::new("users");
 pub const POSITIONS: Map<(&Addr, String), Position> = Map::new("market_positions");
+pub const USER_VOLUMES: Map<&Addr, UserVolume> = Map::new("user_volumes");
+
+/// Positions the user holds base in, read through the user's prefix rather than every listed market.
+pub fn get_open_positions(storage: &dyn Storage, user_addr: &Addr) -> StdResult<Vec<Position>> {
+    POSITIONS
+        .prefix(user_addr)
+        .range(storage, None, None, Order::Ascending)
+        .filter_map(|item| match item {
+            Ok((_, position)) if position.is_open_position() => Some(Ok(position)),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect()
+}
 
 impl Position {
     pub fn is_for(&self, market_index: u64) -> bool {
@@ -47,3 +73,30 @@ impl Position {
         self.order_length != 0
     }
 }
+
+impl UserVolume {
+    // shift the buckets forward to the current day, dropping days outside the window
+    pub fn roll(&mut self, now: u64) {
+        let today = now / ONE_DAY;
+        let elapsed = today.saturating_sub(self.last_day).min(VOLUME_WINDOW_DAYS);
+        if self.daily_volume.is_empty() {
+            self.daily_volume.push(Uint128::zero());
+        } else {
+            for _ in 0..elapsed {
+                self.daily_volume.insert(0, Uint128::zero());
+            }
+        }
+        self.daily_volume.truncate(VOLUME_WINDOW_DAYS as usize);
+        self.last_day = self.last_day.max(today);
+    }
+
+    pub fn rolling_volume(&self, now: u64) -> Result<Uint128, ContractError> {
+        let mut volume = self.clone();
+        volume.roll(now);
+        let mut rolling_volume = Uint128::zero();
+        for daily_volume in volume.daily_volume.iter() {
+            rolling_volume = rolling_volume.checked_add(*daily_volume)?;
+        }
+        Ok(rolling_volume)
+    }
+}
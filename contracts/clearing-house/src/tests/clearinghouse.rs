@@ -1,12 +1,19 @@
 use std::ops::Mul;
 
 use crate::contract::{execute, instantiate, query};
-use crate::states::constants::{DEFAULT_FEE_DENOMINATOR, DEFAULT_FEE_NUMERATOR};
-use crate::states::market::Market;
+use crate::states::constants::{
+    DEFAULT_FEE_DENOMINATOR, DEFAULT_FEE_NUMERATOR, FUNDING_PAYMENT_PRECISION,
+};
+use crate::states::liquidation::{LiquidationAuction, LIQUIDATION_AUCTIONS};
+use crate::controller::amm::update_oracle_price_twap;
+use crate::helpers::amm::calculate_swap_output;
+use crate::states::market::{Market, MARKETS};
 use crate::views::execute_admin::{
     try_feeding_price, try_initialize_market, try_update_exchange_paused,
     try_update_market_minimum_base_asset_trade_size,
-    try_update_market_minimum_quote_asset_trade_size,
+    try_update_market_minimum_quote_asset_trade_size, try_update_repeg_budget,
+    try_update_dynamic_k, try_update_funding_fee_share, try_update_funding_parameters,
+    try_update_oracle_twap_parameters, try_update_spread, try_update_funding_rate,
 };
 use crate::views::execute_user::{
     try_close_position, try_deposit_collateral, try_open_position, try_settle_funding_payment,
@@ -14,17 +21,21 @@ use crate::views::execute_user::{
 };
 use crate::views::query;
 
-use ariel::execute::InstantiateMsg;
+use ariel::execute::{ExecuteMsg, InstantiateMsg};
 use ariel::number::Number128;
 use ariel::queries::QueryMsg;
 use ariel::response::*;
 
-use ariel::types::{DepositDirection, OracleSource, PositionDirection};
+use ariel::types::{
+    CurveType, DepositDirection, DiscountToken, MakerOrderId, OracleSource, OrderAction, OrderDiscountTier, OrderParams, OrderTriggerCondition, OrderType,
+    PositionDirection, SwapDirection,
+};
 use cosmwasm_std::testing::{
     mock_dependencies, mock_env, mock_info, MockQuerier, MOCK_CONTRACT_ADDR,
 };
 use cosmwasm_std::{
-    coins, from_binary, Addr, BalanceResponse, BankQuery, Decimal, QueryRequest, StdResult, Uint128,
+    coins, from_binary, Addr, BalanceResponse, BankQuery, Decimal, QueryRequest, StdResult, Timestamp,
+    Uint128,
 };
 
 const ADMIN_ACCOUNT: &str = "admin_account";
@@ -176,6 +187,7 @@ pub fn test_deposit_withdraw() {
         margin_ratio_initial,
         margin_ratio_partial,
         margin_ratio_maintenance,
+        None,
     )
     .unwrap();
 
@@ -313,6 +325,7 @@ pub fn test_open_position() {
         margin_ratio_initial,
         margin_ratio_partial,
         margin_ratio_maintenance,
+        None,
     )
     .unwrap();
 
@@ -521,6 +534,7 @@ pub fn test_short_position() {
         margin_ratio_initial,
         margin_ratio_partial,
         margin_ratio_maintenance,
+        None,
     )
     .unwrap();
 
@@ -614,6 +628,7 @@ pub fn user_functions_test() {
         margin_ratio_initial,
         margin_ratio_partial,
         margin_ratio_maintenance,
+        None,
     )
     .unwrap();
 
@@ -929,31 +944,2458 @@ pub fn user_functions_test() {
         Uint128::new(497450503674885u128),
         value[3].base_asset_amount
     );
+
+    // a spread makes the same notional buy less base than the curve would give
+    try_update_spread(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 200, 100).unwrap_err();
+    try_update_spread(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 20, 100).unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetMarketInfo { market_index: 1 },
+    )
+    .unwrap();
+    let before: MarketInfoResponse = from_binary(&res).unwrap();
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(5_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetMarketInfo { market_index: 1 },
+    )
+    .unwrap();
+    let value: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(20, value.base_spread);
+    // the twaps have diverged far enough to hit the max spread
+    assert_eq!(Uint128::from(50_000u128), value.total_spread_revenue);
+    assert!(value.base_asset_amount.i128() < 497450503674885);
+    // the spread is credited to the fee pool on top of the trading fee
+    assert!(value.total_fee > before.total_fee + Uint128::from(50_000u128));
+    assert!(
+        value.total_fee_minus_distributions
+            > before.total_fee_minus_distributions + Uint128::from(50_000u128)
+    );
 }
 
-pub fn calculate_trade_amount(amount_collateral: u128) -> StdResult<Uint128> {
-    let trade_amount = Uint128::from(amount_collateral)
-        .checked_mul(Uint128::from(5u128))?
-        .checked_mul(Uint128::from(100000u128).checked_sub(Uint128::from(500u128))?)?
-        .checked_div(Uint128::from(100000u128))?;
+#[test]
+pub fn order_functions_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
 
-    Ok(trade_amount)
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        92_450_000_000_0,
+    )
+    .unwrap();
+
+    // orders can only be placed in a market the user has a position in
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        calculate_trade_amount(10_000_000).unwrap(),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // long limit order far below the mark price so it rests on the book
+    let limit_order = |user_order_id: Option<u64>| OrderParams {
+        order_type: OrderType::Limit,
+        direction: PositionDirection::Long,
+        quote_asset_amount: Uint128::zero(),
+        base_asset_amount: Uint128::from(100_000_000u128),
+        price: Uint128::from(1_000_000u128),
+        market_index: 1,
+        reduce_only: false,
+        post_only: false,
+        immediate_or_cancel: false,
+        trigger_price: Uint128::zero(),
+        trigger_condition: OrderTriggerCondition::Above,
+        position_limit: Uint128::zero(),
+        oracle_price_offset: Number128::zero(),
+        user_order_id,
+    };
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder { order: limit_order(Some(7)) },
+    )
+    .unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderByUserId {
+            user: "geekybot".to_string(),
+            user_order_id: 7,
+        },
+    )
+    .unwrap();
+    let value: OrderResponse = from_binary(&res).unwrap();
+    assert_eq!(1, value.order_id);
+    assert_eq!(Some(7), value.user_order_id);
+    let placed_ts = value.ts;
+
+    // user_order_id can't be reused while the order is open
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder { order: limit_order(Some(7)) },
+    )
+    .unwrap_err();
+
+    // shrinking the size keeps the id and timestamp
+    let mut later_env = mock_env();
+    later_env.block.time = later_env.block.time.plus_seconds(60);
+    execute(
+        deps.as_mut(),
+        later_env,
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::ModifyOrder {
+            order_id: 1,
+            new_price: None,
+            new_base_asset_amount: Some(Uint128::from(50_000_000u128)),
+            new_trigger_price: None,
+        },
+    )
+    .unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder {
+            user: "geekybot".to_string(),
+            order_id: 1,
+        },
+    )
+    .unwrap();
+    let value: OrderResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(50_000_000u128), value.base_asset_amount);
+    assert_eq!(placed_ts, value.ts);
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::CancelOrderByUserId { user_order_id: 7 },
+    )
+    .unwrap();
+    query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder {
+            user: "geekybot".to_string(),
+            order_id: 1,
+        },
+    )
+    .unwrap_err();
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::BatchOrders {
+            place: vec![limit_order(Some(7)), limit_order(None)],
+            cancel: vec![],
+        },
+    )
+    .unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetUserOrders {
+            user: "geekybot".to_string(),
+            market_index: Some(1),
+            order_type: None,
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderResponse> = from_binary(&res).unwrap();
+    assert_eq!(2, value.len());
+    assert_eq!(2, value[0].order_id);
+    assert_eq!(3, value[1].order_id);
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetFillableOrders {
+            market_index: 1,
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: FillableOrdersResponse = from_binary(&res).unwrap();
+    assert_eq!(0, value.orders.len());
+    assert_eq!(Some("geekybot".to_string()), value.last_user);
+
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::CancelAllOrders {
+            market_index: None,
+            direction: Some(PositionDirection::Long),
+        },
+    )
+    .unwrap();
+    assert_eq!(res.attributes[1].value, "2");
+    assert_eq!(res.attributes[2].value, "");
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetOrderHistoryLength {}).unwrap();
+    let value: OrderHistoryLengthResponse = from_binary(&res).unwrap();
+    assert_eq!(7, value.length);
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderHistory {
+            user_address: Some("geekybot".to_string()),
+            market_index: None,
+            action: Some(OrderAction::Cancel),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+    assert_eq!(3, value.len());
+    assert_eq!(7, value[0].record_id);
+    assert_eq!(1, value[2].order.order_id);
+
+    // order ids page numerically, so ids past 9 aren't skipped
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::BatchOrders {
+            place: (0..8).map(|_| limit_order(None)).collect(),
+            cancel: vec![],
+        },
+    )
+    .unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetUserOrders {
+            user: "geekybot".to_string(),
+            market_index: None,
+            order_type: None,
+            start_after: Some(9),
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderResponse> = from_binary(&res).unwrap();
+    assert_eq!(2, value.len());
+    assert_eq!(10, value[0].order_id);
+    assert_eq!(11, value[1].order_id);
 }
 
-// pub fn calculate_trade_slippage(
-//     direction: PositionDirection,
-//     trade_amount: Uint128,
-//     base: Number128,
-//     quote: Uint128,
-//     peg_multiplier: Uint128,
-// ) -> StdResult<(Uint128, Uint128)> {
-//     let oldPrice = quote
-//         .checked_mul(Uint128::from(10_000_000_000u128))?
-//         .checked_mul(peg_multiplier)?
-//         .checked_div(Uint128::from(1000u128))?
-//         .checked_div(Uint128::from(base.amount))?;
-//     if !base.is_positive {
-//         return Ok((oldPrice, Uint128::zero()));
-//     }
+#[test]
+pub fn order_history_paging_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
 
-// }
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(1_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // six resting orders placed and canceled write twelve records
+    for order_id in 1..=6 {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("geekybot", &coins(0, "denom")),
+            ExecuteMsg::PlaceOrder {
+                order: OrderParams {
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Long,
+                    quote_asset_amount: Uint128::zero(),
+                    base_asset_amount: Uint128::from(100_000_000u128),
+                    price: Uint128::from(1_000_000u128),
+                    market_index: 1,
+                    reduce_only: false,
+                    post_only: false,
+                    immediate_or_cancel: false,
+                    trigger_price: Uint128::zero(),
+                    trigger_condition: OrderTriggerCondition::Above,
+                    position_limit: Uint128::zero(),
+                    oracle_price_offset: Number128::zero(),
+                    user_order_id: None,
+                },
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("geekybot", &coins(0, "denom")),
+            ExecuteMsg::CancelOrder { market_index: 1, order_id },
+        )
+        .unwrap();
+    }
+
+    // paging past the single digit ids keeps the newest first without skipping or repeating
+    let mut record_ids = vec![];
+    let mut start_after = None;
+    loop {
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetOrderHistory {
+                user_address: None,
+                market_index: None,
+                action: None,
+                start_after,
+                limit: Some(5),
+            },
+        )
+        .unwrap();
+        let page: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+        match page.last() {
+            Some(record) => start_after = Some(record.record_id),
+            None => break,
+        }
+        record_ids.extend(page.iter().map(|record| record.record_id));
+    }
+    assert_eq!((1..=12).rev().collect::<Vec<u64>>(), record_ids);
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderHistory {
+            user_address: None,
+            market_index: None,
+            action: Some(OrderAction::Cancel),
+            start_after: Some(10),
+            limit: Some(1),
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+    assert_eq!(8, value[0].record_id);
+    assert_eq!(4, value[0].order.order_id);
+}
+
+#[test]
+pub fn modify_order_margin_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    // the margin checks cover the markets below the newest one, so the trades go in market 1 of 2
+    for market_index in 1..=2 {
+        try_initialize_market(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+            market_index,
+            "LUNA-UST".to_string(),
+            Uint128::from(5_000_000_000_000_000_000u128),
+            Uint128::from(5_000_000_000_000_000_000u128),
+            3600,
+            Uint128::from(1000u128),
+            OracleSource::Oracle,
+            2000,
+            625,
+            500,
+            None,
+        )
+        .unwrap();
+        try_feeding_price(
+            deps.as_mut(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+            market_index,
+            10_000_000_000,
+        )
+        .unwrap();
+    }
+
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(40_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: OrderParams {
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                quote_asset_amount: Uint128::zero(),
+                base_asset_amount: Uint128::from(100_000_000u128),
+                price: Uint128::from(1_000_000u128),
+                market_index: 1,
+                reduce_only: false,
+                post_only: false,
+                immediate_or_cancel: false,
+                trigger_price: Uint128::zero(),
+                trigger_condition: OrderTriggerCondition::Above,
+                position_limit: Uint128::zero(),
+                oracle_price_offset: Number128::zero(),
+                user_order_id: None,
+            },
+        },
+    )
+    .unwrap();
+
+    // the long loses a fifth of its value and the account falls below the initial margin
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let market: MarketInfoResponse = from_binary(&res).unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: market.base_asset_reserve,
+            quote_asset_reserve: market.quote_asset_reserve.multiply_ratio(80u128, 100u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+
+    let modify = |base_asset_amount: u128| ExecuteMsg::ModifyOrder {
+        order_id: 1,
+        new_price: None,
+        new_base_asset_amount: Some(Uint128::from(base_asset_amount)),
+        new_trigger_price: None,
+    };
+
+    // growing the order takes on more risk than the account can carry, shrinking it doesn't
+    let err = execute(deps.as_mut(), mock_env(), mock_info("geekybot", &coins(0, "denom")), modify(200_000_000))
+        .unwrap_err();
+    assert_eq!("Insufficient collateral", err.to_string());
+    execute(deps.as_mut(), mock_env(), mock_info("geekybot", &coins(0, "denom")), modify(50_000_000))
+        .unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder {
+            user: "geekybot".to_string(),
+            order_id: 1,
+        },
+    )
+    .unwrap();
+    let value: OrderResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(50_000_000u128), value.base_asset_amount);
+}
+
+#[test]
+pub fn order_matching_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        92_450_000_000_0,
+    )
+    .unwrap();
+
+    for user in ["taker", "maker", "filler"] {
+        try_deposit_collateral(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(user, &coins(10_000_000, "uusd")),
+            10_000_000,
+            None,
+        )
+        .unwrap();
+    }
+    for user in ["taker", "maker"] {
+        try_open_position(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(user, &coins(0, "denom")),
+            PositionDirection::Long,
+            Uint128::from(1_000_000u128),
+            1,
+            None,
+        )
+        .unwrap();
+    }
+
+    let limit_order = |direction: PositionDirection| OrderParams {
+        order_type: OrderType::Limit,
+        direction,
+        quote_asset_amount: Uint128::zero(),
+        base_asset_amount: Uint128::from(1_000_000_000_000u128),
+        price: Uint128::from(20_000_000_000u128),
+        market_index: 1,
+        reduce_only: false,
+        post_only: false,
+        immediate_or_cancel: false,
+        trigger_price: Uint128::zero(),
+        trigger_condition: OrderTriggerCondition::Above,
+        position_limit: Uint128::zero(),
+        oracle_price_offset: Number128::zero(),
+        user_order_id: None,
+    };
+
+    // maker asks $2, well above the mark price, so it rests on the book
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder { order: limit_order(PositionDirection::Short) },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("taker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder { order: limit_order(PositionDirection::Long) },
+    )
+    .unwrap();
+
+    let position = |deps: &cosmwasm_std::OwnedDeps<_, _, _>, user: &str| -> Number128 {
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetUserMarketPosition {
+                user_address: user.to_string(),
+                index: 1,
+            },
+        )
+        .unwrap();
+        let value: UserPositionResponse = from_binary(&res).unwrap();
+        value.base_asset_amount
+    };
+    let taker_before = position(&deps, "taker");
+    let maker_before = position(&deps, "maker");
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let market_before: MarketInfoResponse = from_binary(&res).unwrap();
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("filler", &coins(0, "denom")),
+        ExecuteMsg::FillOrder {
+            order_id: 1,
+            user_address: "taker".to_string(),
+            market_index: 1,
+            maker_orders: Some(vec![MakerOrderId {
+                user_address: "maker".to_string(),
+                order_id: 1,
+            }]),
+        },
+    )
+    .unwrap();
+
+    // the whole order was matched at the maker's price without touching the amm
+    assert_eq!(
+        taker_before.i128() + 1_000_000_000_000,
+        position(&deps, "taker").i128()
+    );
+    assert_eq!(
+        maker_before.i128() - 1_000_000_000_000,
+        position(&deps, "maker").i128()
+    );
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let market_after: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(market_before.base_asset_reserve, market_after.base_asset_reserve);
+    assert_eq!(market_before.base_asset_amount, market_after.base_asset_amount);
+    // taker fee on $0.20 of notional, no volume discount on $1 of traded volume
+    assert_eq!(
+        market_before.total_fee + Uint128::from(200u128),
+        market_after.total_fee
+    );
+
+    for user in ["taker", "maker"] {
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetUserOrders {
+                user: user.to_string(),
+                market_index: None,
+                order_type: None,
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let value: Vec<OrderResponse> = from_binary(&res).unwrap();
+        assert_eq!(0, value.len());
+    }
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderHistory {
+            user_address: None,
+            market_index: Some(1),
+            action: Some(OrderAction::Fill),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+    assert_eq!(2, value.len());
+    assert_eq!(Uint128::from(200_000u128), value[0].quote_asset_amount_filled);
+
+    // both sides of the match count towards the rolling volume
+    for user in ["taker", "maker"] {
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::GetUserFeeTier { user_address: user.to_string() },
+        )
+        .unwrap();
+        let value: UserFeeTierResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(1_200_000u128), value.rolling_volume);
+        assert_eq!(OrderDiscountTier::None, value.tier);
+    }
+
+    // holding the discount token lifts the taker into the first tier
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::UpdateDiscountToken {
+            token: Some(DiscountToken::Native { denom: "uaril".to_string() }),
+            staking_contract: None,
+        },
+    )
+    .unwrap();
+    deps.querier
+        .update_balance("taker", coins(1_000_000_000_000, "uaril"));
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetUserFeeTier { user_address: "taker".to_string() },
+    )
+    .unwrap();
+    let value: UserFeeTierResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(1_000_000_000_000u128), value.discount_token_balance);
+    assert_eq!(OrderDiscountTier::First, value.tier);
+
+    // a partial match the amm can't add to leaves the remainder resting
+    let priced_order = |order_type: OrderType, direction: PositionDirection, base: u128, price: u128| OrderParams {
+        order_type,
+        direction,
+        base_asset_amount: Uint128::from(base),
+        price: Uint128::from(price),
+        ..limit_order(direction)
+    };
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: priced_order(OrderType::Limit, PositionDirection::Short, 1_000_000_000_000, 5_000_000_000),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("taker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: priced_order(OrderType::Limit, PositionDirection::Long, 2_000_000_000_000, 5_000_000_000),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("filler", &coins(0, "denom")),
+        ExecuteMsg::FillOrder {
+            order_id: 2,
+            user_address: "taker".to_string(),
+            market_index: 1,
+            maker_orders: Some(vec![MakerOrderId {
+                user_address: "maker".to_string(),
+                order_id: 2,
+            }]),
+        },
+    )
+    .unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder { user: "taker".to_string(), order_id: 2 },
+    )
+    .unwrap();
+    let value: OrderResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(1_000_000_000_000u128), value.base_asset_amount_filled);
+
+    // triggered orders take resting liquidity too
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: priced_order(OrderType::Limit, PositionDirection::Short, 1_000_000_000_000, 15_000_000_000),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("taker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: OrderParams {
+                trigger_price: Uint128::from(5_000_000_000u128),
+                ..priced_order(OrderType::TriggerMarket, PositionDirection::Long, 1_000_000_000_000, 0)
+            },
+        },
+    )
+    .unwrap();
+    let taker_before = position(&deps, "taker");
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("filler", &coins(0, "denom")),
+        ExecuteMsg::FillOrder {
+            order_id: 3,
+            user_address: "taker".to_string(),
+            market_index: 1,
+            maker_orders: Some(vec![MakerOrderId {
+                user_address: "maker".to_string(),
+                order_id: 3,
+            }]),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        taker_before.i128() + 1_000_000_000_000,
+        position(&deps, "taker").i128()
+    );
+    query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder { user: "maker".to_string(), order_id: 3 },
+    )
+    .unwrap_err();
+
+    // the amm fills what the maker couldn't, and its fill record counts the maker's part of the order
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: priced_order(OrderType::Limit, PositionDirection::Short, 1_000_000_000_000, 15_000_000_000),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("taker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: priced_order(OrderType::Limit, PositionDirection::Long, 2_000_000_000_000, 20_000_000_000),
+        },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("filler", &coins(0, "denom")),
+        ExecuteMsg::FillOrder {
+            order_id: 4,
+            user_address: "taker".to_string(),
+            market_index: 1,
+            maker_orders: Some(vec![MakerOrderId {
+                user_address: "maker".to_string(),
+                order_id: 4,
+            }]),
+        },
+    )
+    .unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderHistory {
+            user_address: Some("taker".to_string()),
+            market_index: Some(1),
+            action: Some(OrderAction::Fill),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+    let amm_fill = value
+        .iter()
+        .filter(|record| record.order.order_id == 4)
+        .max_by_key(|record| record.record_id)
+        .unwrap();
+    assert_eq!(Uint128::from(1_000_000_000_000u128), amm_fill.base_asset_amount_filled);
+    assert_eq!(Uint128::from(2_000_000_000_000u128), amm_fill.order.base_asset_amount_filled);
+    query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrder { user: "taker".to_string(), order_id: 4 },
+    )
+    .unwrap_err();
+}
+
+#[test]
+pub fn referral_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        Uint128::from(5_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        92_450_000_000_0,
+    )
+    .unwrap();
+
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referrer", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referrer", &coins(0, "denom")),
+        ExecuteMsg::RegisterReferralCode { code: "alpha".to_string() },
+    )
+    .unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referee", &coins(0, "denom")),
+        ExecuteMsg::RegisterReferralCode { code: "alpha".to_string() },
+    )
+    .unwrap_err();
+
+    // the referee signs up with the code rather than the referrer's address
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referee", &coins(10_000_000, "uusd")),
+        10_000_000,
+        Some("alpha".to_string()),
+    )
+    .unwrap();
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referee", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(1_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // 5% of the $0.001 fee
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetReferralStats {
+            referrer: "referrer".to_string(),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: ReferralStatsResponse = from_binary(&res).unwrap();
+    assert_eq!(vec!["alpha".to_string()], value.codes);
+    assert_eq!(Uint128::from(50u128), value.unclaimed_referral_reward);
+    assert_eq!(1, value.referees.len());
+    assert_eq!("referee", value.referees[0].referee);
+    assert_eq!(Uint128::from(50u128), value.referees[0].referrer_reward);
+
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("referrer", &coins(0, "denom")),
+        ExecuteMsg::ClaimReferralRewards {},
+    )
+    .unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetUser { user_address: "referrer".to_string() }).unwrap();
+    let value: UserResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(10_000_050u128), value.collateral);
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetReferralStats {
+            referrer: "referrer".to_string(),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: ReferralStatsResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::zero(), value.unclaimed_referral_reward);
+    assert_eq!(Uint128::from(50u128), value.total_claimed);
+
+    // custom rates replace the fee structure's for this referrer only
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::UpdateReferrerRewardRate {
+            referrer: "referrer".to_string(),
+            referrer_reward: Decimal::percent(10),
+            referee_discount: Decimal::percent(10),
+        },
+    )
+    .unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetReferralStats {
+            referrer: "referrer".to_string(),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: ReferralStatsResponse = from_binary(&res).unwrap();
+    assert_eq!(Decimal::percent(10), value.referrer_reward);
+}
+
+pub fn calculate_trade_amount(amount_collateral: u128) -> StdResult<Uint128> {
+    let trade_amount = Uint128::from(amount_collateral)
+        .checked_mul(Uint128::from(5u128))?
+        .checked_mul(Uint128::from(100000u128).checked_sub(Uint128::from(500u128))?)?
+        .checked_div(Uint128::from(100000u128))?;
+
+    Ok(trade_amount)
+}
+
+// pub fn calculate_trade_slippage(
+//     direction: PositionDirection,
+//     trade_amount: Uint128,
+//     base: Number128,
+//     quote: Uint128,
+//     peg_multiplier: Uint128,
+// ) -> StdResult<(Uint128, Uint128)> {
+//     let oldPrice = quote
+//         .checked_mul(Uint128::from(10_000_000_000u128))?
+//         .checked_mul(peg_multiplier)?
+//         .checked_div(Uint128::from(1000u128))?
+//         .checked_div(Uint128::from(base.amount))?;
+//     if !base.is_positive {
+//         return Ok((oldPrice, Uint128::zero()));
+//     }
+
+// }
+
+#[test]
+pub fn liquidation_auction_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetLiquidationAuctionConfig {}).unwrap();
+    let config: LiquidationAuctionConfigResponse = from_binary(&res).unwrap();
+    assert!(!config.enabled);
+    assert_eq!(config.max_discount, Decimal::percent(5));
+
+    // auctions need a non-zero duration and a discount below 100%
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::UpdateLiquidationAuctionConfig {
+            enabled: true,
+            duration: 0,
+            start_discount: Decimal::zero(),
+            max_discount: Decimal::percent(5),
+        },
+    )
+    .unwrap_err();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::UpdateLiquidationAuctionConfig {
+            enabled: true,
+            duration: 600,
+            start_discount: Decimal::percent(10),
+            max_discount: Decimal::percent(5),
+        },
+    )
+    .unwrap_err();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("user", &coins(0, "denom")),
+        ExecuteMsg::UpdateLiquidationAuctionConfig {
+            enabled: true,
+            duration: 600,
+            start_discount: Decimal::percent(1),
+            max_discount: Decimal::percent(5),
+        },
+    )
+    .unwrap_err();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::UpdateLiquidationAuctionConfig {
+            enabled: true,
+            duration: 600,
+            start_discount: Decimal::percent(1),
+            max_discount: Decimal::percent(5),
+        },
+    )
+    .unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetLiquidationAuctionConfig {}).unwrap();
+    let config: LiquidationAuctionConfigResponse = from_binary(&res).unwrap();
+    assert!(config.enabled);
+    assert_eq!(config.duration, 600);
+
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("user", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+
+    // a healthy user can not be put up for auction
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("liquidator", &coins(0, "denom")),
+        ExecuteMsg::StartLiquidationAuction {
+            user: "user".to_string(),
+            market_index: 1,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Sufficient collateral");
+
+    // nor taken over directly
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("liquidator", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("user", &coins(0, "denom")),
+        ExecuteMsg::LiquidatePerp {
+            user: "user".to_string(),
+            market_index: 1,
+            max_base_asset_amount: Uint128::from(1_000u128),
+            limit_price: Uint128::from(92_450_000_000_0u128),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "User cant liquidate themselves");
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("liquidator", &coins(0, "denom")),
+        ExecuteMsg::LiquidatePerp {
+            user: "user".to_string(),
+            market_index: 1,
+            max_base_asset_amount: Uint128::from(1_000u128),
+            limit_price: Uint128::from(92_450_000_000_0u128),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Sufficient collateral");
+
+    query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetLiquidationAuction {
+            user_address: "user".to_string(),
+            market_index: 1,
+        },
+    )
+    .unwrap_err();
+
+    // a solvent user has nothing to resolve
+    let res = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("liquidator", &coins(0, "denom")),
+        ExecuteMsg::ResolveBankruptcy { user: "user".to_string() },
+    )
+    .unwrap();
+    assert!(res.messages.is_empty());
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetBankruptcyHistoryLength {}).unwrap();
+    let length: BankruptcyHistoryLengthResponse = from_binary(&res).unwrap();
+    assert_eq!(length.length, 0);
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetLiquidatableUsers { start_after: None, limit: Some(1) },
+    )
+    .unwrap();
+    let liquidatable: LiquidatableUsersResponse = from_binary(&res).unwrap();
+    assert!(liquidatable.users.is_empty());
+    assert_eq!(liquidatable.last_user, Some("liquidator".to_string()));
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetLiquidatableUsers { start_after: liquidatable.last_user, limit: None },
+    )
+    .unwrap();
+    let liquidatable: LiquidatableUsersResponse = from_binary(&res).unwrap();
+    assert_eq!(liquidatable.last_user, Some("user".to_string()));
+
+    // a running auction can only be bid on, not taken over directly
+    let now = mock_env().block.time.seconds();
+    LIQUIDATION_AUCTIONS
+        .save(
+            deps.as_mut().storage,
+            (&Addr::unchecked("user"), 1.to_string()),
+            &LiquidationAuction {
+                direction: PositionDirection::Long,
+                base_asset_amount: Uint128::from(1_000u128),
+                partial: false,
+                start_ts: now,
+                end_ts: now + 600,
+            },
+        )
+        .unwrap();
+    let err = execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("liquidator", &coins(0, "denom")),
+        ExecuteMsg::LiquidatePerp {
+            user: "user".to_string(),
+            market_index: 1,
+            max_base_asset_amount: Uint128::from(1_000u128),
+            limit_price: Uint128::from(92_450_000_000_0u128),
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Liquidation auction in progress");
+}
+
+#[test]
+pub fn curve_type_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    let curve_types = vec![
+        (1, None),
+        (2, Some(CurveType::StableSwap { amplification: 100 })),
+        (
+            3,
+            Some(CurveType::Concentrated {
+                min_price: Uint128::from(9_500_000_000u128),
+                max_price: Uint128::from(10_500_000_000u128),
+            }),
+        ),
+    ];
+    for (market_index, curve_type) in curve_types {
+        try_initialize_market(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+            market_index,
+            "UST-USDC".to_string(),
+            Uint128::from(10_000_000_000_000_000u128),
+            Uint128::from(10_000_000_000_000_000u128),
+            3600,
+            Uint128::from(1000u128),
+            OracleSource::Oracle,
+            2000,
+            625,
+            500,
+            curve_type,
+        )
+        .unwrap();
+        try_feeding_price(
+            deps.as_mut(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+            market_index,
+            10_000_000_000,
+        )
+        .unwrap();
+    }
+
+    // a stableswap curve needs a positive amplification
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        4,
+        "UST-USDC".to_string(),
+        Uint128::from(10_000_000_000_000_000u128),
+        Uint128::from(10_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        Some(CurveType::StableSwap { amplification: 0 }),
+    )
+    .unwrap_err();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 2 }).unwrap();
+    let value: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(CurveType::StableSwap { amplification: 100 }, value.curve_type);
+
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(1_000_000_000, "uusd")),
+        1_000_000_000,
+        None,
+    )
+    .unwrap();
+
+    for market_index in 1..=3 {
+        try_open_position(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("geekybot", &coins(0, "denom")),
+            PositionDirection::Long,
+            Uint128::from(10_000_000u128),
+            market_index,
+            None,
+        )
+        .unwrap();
+    }
+
+    // the same quote buys more base on the flatter stableswap curve and inside the concentrated range
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let constant_product: MarketInfoResponse = from_binary(&res).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 2 }).unwrap();
+    let stableswap: MarketInfoResponse = from_binary(&res).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetMarketInfo { market_index: 3 }).unwrap();
+    let concentrated: MarketInfoResponse = from_binary(&res).unwrap();
+    assert!(stableswap.base_asset_amount_long.i128() > constant_product.base_asset_amount_long.i128());
+    assert!(concentrated.base_asset_amount_long.i128() > constant_product.base_asset_amount_long.i128());
+    assert!(concentrated.sqrt_k > constant_product.sqrt_k);
+
+    // off the peg, the stableswap mark price is what a tiny swap executes at
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(10_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(13_000_000_000_000_000u128),
+            market_index: 2,
+        },
+    )
+    .unwrap();
+    let amm = MARKETS.load(&deps.storage, "2".to_string()).unwrap().amm;
+    let mark_price = amm.mark_price().unwrap();
+    let quote_asset_swapped = Uint128::from(10_000_000_000u128);
+    let (base_asset_reserve_after, _) =
+        calculate_swap_output(&amm, quote_asset_swapped, amm.quote_asset_reserve, SwapDirection::Add)
+            .unwrap();
+    let execution_price = quote_asset_swapped
+        .multiply_ratio(10_000_000_000u128, amm.base_asset_reserve - base_asset_reserve_after);
+    assert!(mark_price > Uint128::from(10_000_000_000u128));
+    assert!(
+        (mark_price.u128() as i128 - execution_price.u128() as i128).unsigned_abs() * 10_000
+            < mark_price.u128()
+    );
+
+    // the concentrated market has no liquidity above its max price
+    let err = try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(2_000_000_000u128),
+        3,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!("Trade would move the price outside of the curve's range", err.to_string());
+
+    // once the price is above the range, trades back toward it and trades that reduce a position
+    // still go through, but new risk can't push it further out
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: concentrated.base_asset_reserve,
+            quote_asset_reserve: concentrated.base_asset_reserve.multiply_ratio(106u128, 100u128),
+            market_index: 3,
+        },
+    )
+    .unwrap();
+    try_deposit_collateral(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &coins(1_000_000_000, "uusd")),
+        1_000_000_000,
+        None,
+    )
+    .unwrap();
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("alice", &coins(0, "denom")),
+        PositionDirection::Short,
+        Uint128::from(10_000_000u128),
+        3,
+        None,
+    )
+    .unwrap();
+    try_close_position(deps.as_mut(), mock_env(), mock_info("alice", &coins(0, "denom")), 3)
+        .unwrap();
+    let err = try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(10_000_000u128),
+        3,
+        None,
+    )
+    .unwrap_err();
+    assert_eq!("Trade would move the price outside of the curve's range", err.to_string());
+}
+
+#[test]
+pub fn post_only_fill_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), mock_env(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(1_000_000_000_000_000u128),
+        Uint128::from(1_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+
+    for user in ["maker", "filler"] {
+        try_deposit_collateral(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(user, &coins(10_000_000, "uusd")),
+            10_000_000,
+            None,
+        )
+        .unwrap();
+    }
+    try_open_position(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(1_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+
+    // post only bid at $0.90 rests below the $1 mark price
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("maker", &coins(0, "denom")),
+        ExecuteMsg::PlaceOrder {
+            order: OrderParams {
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                quote_asset_amount: Uint128::zero(),
+                base_asset_amount: Uint128::from(10_000_000_000_000u128),
+                price: Uint128::from(9_000_000_000u128),
+                market_index: 1,
+                reduce_only: false,
+                post_only: true,
+                immediate_or_cancel: false,
+                trigger_price: Uint128::zero(),
+                trigger_condition: OrderTriggerCondition::Above,
+                position_limit: Uint128::zero(),
+                oracle_price_offset: Number128::zero(),
+                user_order_id: None,
+            },
+        },
+    )
+    .unwrap();
+
+    // the amm and oracle move through the bid, which can now be filled against the amm
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_100_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(909_090_909_090_909u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        8_500_000_000,
+    )
+    .unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetUser { user_address: "filler".to_string() }).unwrap();
+    let filler_before: UserResponse = from_binary(&res).unwrap();
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("filler", &coins(0, "denom")),
+        ExecuteMsg::FillOrder {
+            order_id: 1,
+            user_address: "maker".to_string(),
+            market_index: 1,
+            maker_orders: None,
+        },
+    )
+    .unwrap();
+
+    // only the (zero) maker fee is charged, no taker fee and so no filler reward
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetOrderHistory {
+            user_address: Some("maker".to_string()),
+            market_index: Some(1),
+            action: Some(OrderAction::Fill),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<OrderHistoryResponse> = from_binary(&res).unwrap();
+    assert_eq!(1, value.len());
+    assert!(!value[0].base_asset_amount_filled.is_zero());
+    assert_eq!(Uint128::zero(), value[0].fee);
+    assert_eq!(0, value[0].maker_fee.i128());
+    assert_eq!(Uint128::zero(), value[0].filler_reward);
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::GetUser { user_address: "filler".to_string() }).unwrap();
+    let filler_after: UserResponse = from_binary(&res).unwrap();
+    assert_eq!(filler_before.collateral, filler_after.collateral);
+}
+
+#[test]
+pub fn formulaic_repeg_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    try_deposit_collateral(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(100_000_000_000, "uusd")),
+        100_000_000_000,
+        None,
+    )
+    .unwrap();
+
+    // round trips fill the fee pool without leaving a position behind
+    for _ in 0..3 {
+        try_open_position(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("geekybot", &coins(0, "denom")),
+            PositionDirection::Long,
+            Uint128::from(50_000_000_000u128),
+            1,
+            None,
+        )
+        .unwrap();
+        try_close_position(deps.as_mut(), env.clone(), mock_info("geekybot", &coins(0, "denom")), 1)
+            .unwrap();
+    }
+    try_open_position(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(10_000_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+    try_update_funding_fee_share(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 0).unwrap();
+    try_update_repeg_budget(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &[]),
+        1,
+        Uint128::from(30_000_000u128),
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_700_000_000,
+    )
+    .unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let before: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(1000u128), before.peg_multiplier);
+
+    // the oracle twap sits 7% above mark, but the budget only pays for part of the move
+    env.block.time = env.block.time.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let after: MarketInfoResponse = from_binary(&res).unwrap();
+    assert!(after.peg_multiplier > before.peg_multiplier);
+    assert!(after.peg_multiplier < Uint128::from(1070u128));
+    let repeg_cost = before
+        .total_fee_minus_distributions
+        .checked_sub(after.total_fee_minus_distributions)
+        .unwrap();
+    assert!(!repeg_cost.is_zero());
+    assert!(repeg_cost <= Uint128::from(30_000_000u128));
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetCurveHistoryLength {}).unwrap();
+    let length: CurveHistoryLengthResponse = from_binary(&res).unwrap();
+    assert_eq!(1, length.length);
+}
+
+#[test]
+pub fn dynamic_k_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    try_deposit_collateral(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(100_000_000_000, "uusd")),
+        100_000_000_000,
+        None,
+    )
+    .unwrap();
+
+    let sqrt_k = Uint128::from(10_000_000_000_000_000_000u128);
+    try_update_dynamic_k(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &[]),
+        1,
+        true,
+        sqrt_k,
+        sqrt_k.checked_mul(Uint128::from(2u128)).unwrap(),
+    )
+    .unwrap();
+
+    // round trips fill the fee pool without leaving a position behind
+    for _ in 0..3 {
+        try_open_position(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("geekybot", &coins(0, "denom")),
+            PositionDirection::Long,
+            Uint128::from(50_000_000_000u128),
+            1,
+            None,
+        )
+        .unwrap();
+        try_close_position(deps.as_mut(), env.clone(), mock_info("geekybot", &coins(0, "denom")), 1)
+            .unwrap();
+    }
+    try_open_position(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(10_000_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+    try_update_funding_fee_share(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 0).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let before: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(sqrt_k, before.sqrt_k);
+    assert!(before.total_fee_minus_distributions > before.last_k_update_total_fee_minus_distributions);
+
+    // fees and open interest grew since k was last set, so k steps up by 1%
+    env.block.time = env.block.time.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let grown: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::from(10_100_000_000_000_000_000u128), grown.sqrt_k);
+    assert!(grown.total_fee_minus_distributions < before.total_fee_minus_distributions);
+    assert_eq!(
+        grown.total_fee_minus_distributions,
+        grown.last_k_update_total_fee_minus_distributions
+    );
+
+    // funding paid out of the fees draws the pool down, so k steps back but not below its minimum
+    try_update_funding_fee_share(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 10_000).unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_700_000_000,
+    )
+    .unwrap();
+    // a keeper samples the new premium right away, so it holds for the whole period
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    env.block.time = env.block.time.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let shrunk: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(sqrt_k, shrunk.sqrt_k);
+    assert!(shrunk.total_fee_minus_distributions < grown.total_fee_minus_distributions);
+}
+
+#[test]
+pub fn funding_rate_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(1_000_000_000_000_000u128),
+        Uint128::from(1_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+
+    // mark sits on the oracle for most of the period, a keeper samples the premium halfway
+    env.block.time = start.plus_seconds(1800);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+
+    // mark moves 5% above the oracle a minute before funding is due
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(1_050_000_000_000_000u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+    env.block.time = start.plus_seconds(3540);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+
+    env.block.time = start.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let value: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(env.block.time.seconds(), value.last_funding_rate_ts);
+
+    // each premium counts for the time it held, so the late move is only paid for its last minute
+    let twap_premium =
+        value.last_mark_price_twap.u128() as i128 - value.last_oracle_price_twap.i128();
+    let twap_funding_rate = twap_premium * FUNDING_PAYMENT_PRECISION.u128() as i128 / 24;
+    assert!(value.last_funding_rate.i128() > 0);
+    assert!(value.last_funding_rate.i128() * 50 < twap_funding_rate);
+
+    // held for a full period the premium is well past the 0.1% cap
+    try_update_funding_parameters(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 10_001, 0, 0)
+        .unwrap_err();
+    try_update_funding_parameters(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 10, 0, 0).unwrap();
+    env.block.time = start.plus_seconds(7200);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let value: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(10, value.max_funding_rate);
+    assert_eq!(
+        value.last_oracle_price_twap.i128() * 10 / 10_000 * FUNDING_PAYMENT_PRECISION.u128() as i128,
+        value.last_funding_rate.i128()
+    );
+}
+
+#[test]
+pub fn funding_pool_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(1_000_000_000_000_000u128),
+        Uint128::from(1_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    try_deposit_collateral(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+    try_open_position(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(1_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+    // only the funding pool pays funding, the fees are kept out of it
+    try_update_funding_fee_share(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 1, 0).unwrap();
+
+    // the net long pays into the pool while mark trades above the oracle
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(1_050_000_000_000_000u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    env.block.time = start.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let paid: MarketInfoResponse = from_binary(&res).unwrap();
+    assert!(paid.last_funding_rate.i128() > 0);
+    assert!(!paid.funding_pool.is_zero());
+    assert_eq!(0, paid.funding_shortfall.i128());
+
+    // once mark drops further below the oracle, the longs are owed more than the pool holds
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(920_000_000_000_000u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    env.block.time = start.plus_seconds(7200);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let drained: MarketInfoResponse = from_binary(&res).unwrap();
+    assert!(drained.last_funding_rate.i128() < 0);
+    assert_eq!(Uint128::zero(), drained.funding_pool);
+    assert!(drained.funding_shortfall.i128() > 0);
+    assert_eq!(paid.total_fee_minus_distributions, drained.total_fee_minus_distributions);
+
+    // the unpaid funding carries forward and adds to what the longs are owed next period
+    env.block.time = start.plus_seconds(10800);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let carried: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(Uint128::zero(), carried.funding_pool);
+    assert!(carried.funding_shortfall.i128() > drained.funding_shortfall.i128());
+}
+
+#[test]
+pub fn funding_keeper_reward_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        Uint128::from(10_000_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+    for user in ["geekybot", "keeper"] {
+        try_deposit_collateral(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user, &coins(100_000_000_000, "uusd")),
+            100_000_000_000,
+            None,
+        )
+        .unwrap();
+    }
+
+    // a round trip leaves fees to pay keepers from
+    try_open_position(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(50_000_000_000u128),
+        1,
+        None,
+    )
+    .unwrap();
+    try_close_position(deps.as_mut(), env.clone(), mock_info("geekybot", &coins(0, "denom")), 1)
+        .unwrap();
+
+    let keeper_collateral = |deps: &cosmwasm_std::OwnedDeps<_, _, _>| -> Uint128 {
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetUser { user_address: "keeper".to_string() })
+            .unwrap();
+        let value: UserResponse = from_binary(&res).unwrap();
+        value.collateral
+    };
+    let collateral = keeper_collateral(&deps);
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetNextFundingTime { market_index: 1 }).unwrap();
+    let value: NextFundingTimeResponse = from_binary(&res).unwrap();
+    assert_eq!(start.seconds(), value.last_funding_rate_ts);
+    assert_eq!(start.plus_seconds(3600).seconds(), value.next_funding_ts);
+
+    // calls before funding is due are not paid
+    env.block.time = start.plus_seconds(1800);
+    let res = try_update_funding_rate(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap();
+    assert_eq!("0", res.attributes[1].value);
+    assert_eq!(collateral, keeper_collateral(&deps));
+
+    // the keeper that advances funding is paid once per period
+    env.block.time = start.plus_seconds(3600);
+    let res = try_update_funding_rate(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap();
+    assert_eq!("1000000", res.attributes[1].value);
+    assert_eq!(collateral + Uint128::from(1_000_000u128), keeper_collateral(&deps));
+    let res = try_update_funding_rate(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap();
+    assert_eq!("0", res.attributes[1].value);
+    assert_eq!(collateral + Uint128::from(1_000_000u128), keeper_collateral(&deps));
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetNextFundingTime { market_index: 1 }).unwrap();
+    let value: NextFundingTimeResponse = from_binary(&res).unwrap();
+    assert_eq!(start.plus_seconds(7200).seconds(), value.next_funding_ts);
+
+    env.block.time = start.plus_seconds(7200);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info("keeper", &[]), 1).unwrap();
+    assert_eq!(collateral + Uint128::from(2_000_000u128), keeper_collateral(&deps));
+}
+
+#[test]
+pub fn funding_rate_estimate_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    try_initialize_market(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+        1,
+        "LUNA-UST".to_string(),
+        Uint128::from(1_000_000_000_000_000u128),
+        Uint128::from(1_000_000_000_000_000u128),
+        3600,
+        Uint128::from(1000u128),
+        OracleSource::Oracle,
+        2000,
+        625,
+        500,
+        None,
+    )
+    .unwrap();
+    try_feeding_price(
+        deps.as_mut(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+        1,
+        10_000_000_000,
+    )
+    .unwrap();
+
+    // mark trades 5% above the oracle for the whole period
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(1_050_000_000_000_000u128),
+            market_index: 1,
+        },
+    )
+    .unwrap();
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+
+    env.block.time = start.plus_seconds(3000);
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetFundingRateEstimate { market_index: 1 }).unwrap();
+    let value: FundingRateEstimateResponse = from_binary(&res).unwrap();
+    assert!(!value.blocked_by_oracle);
+    assert_eq!(start.plus_seconds(3600).seconds(), value.next_funding_ts);
+    assert_eq!(600, value.time_remaining);
+    assert!(value.funding_rate_long.i128() > 0);
+    assert_eq!(value.funding_rate_long, value.funding_rate_short);
+
+    // the estimate taken when funding is due is the rate the update applies
+    env.block.time = start.plus_seconds(3600);
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetFundingRateEstimate { market_index: 1 }).unwrap();
+    let estimate: FundingRateEstimateResponse = from_binary(&res).unwrap();
+    assert_eq!(0, estimate.time_remaining);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 1).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let value: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(estimate.funding_rate_long, value.last_funding_rate);
+    assert_eq!(estimate.funding_rate_long, value.cumulative_funding_rate_long);
+    assert_eq!(estimate.mark_price_twap, value.last_mark_price_twap);
+}
+
+#[test]
+pub fn settle_funding_payment_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    // start on the hour so funding is due a full period after the market opens
+    let mut env = mock_env();
+    env.block.time = Timestamp::from_seconds(1_571_796_000);
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    for market_index in 1..=3 {
+        try_initialize_market(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+            market_index,
+            format!("MARKET-{}", market_index),
+            Uint128::from(1_000_000_000_000_000u128),
+            Uint128::from(1_000_000_000_000_000u128),
+            3600,
+            Uint128::from(1000u128),
+            OracleSource::Oracle,
+            2000,
+            625,
+            500,
+            None,
+        )
+        .unwrap();
+        try_feeding_price(
+            deps.as_mut(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+            market_index,
+            10_000_000_000,
+        )
+        .unwrap();
+    }
+    try_deposit_collateral(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(10_000_000, "uusd")),
+        10_000_000,
+        None,
+    )
+    .unwrap();
+
+    // the only position is in the highest market index
+    try_open_position(
+        deps.as_mut(),
+        env.clone(),
+        mock_info("geekybot", &coins(0, "denom")),
+        PositionDirection::Long,
+        Uint128::from(1_000_000u128),
+        3,
+        None,
+    )
+    .unwrap();
+    try_settle_funding_payment(deps.as_mut(), env.clone(), mock_info("geekybot", &[])).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetFundingPaymentHistoryLength {}).unwrap();
+    let value: FundingPaymentHistoryLengthResponse = from_binary(&res).unwrap();
+    assert_eq!(0, value.length);
+
+    // longs pay funding while mark trades above the oracle
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        mock_info(ADMIN_ACCOUNT, &coins(0, "denom")),
+        ExecuteMsg::MoveAMMPrice {
+            base_asset_reserve: Uint128::from(1_000_000_000_000_000u128),
+            quote_asset_reserve: Uint128::from(1_050_000_000_000_000u128),
+            market_index: 3,
+        },
+    )
+    .unwrap();
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 3).unwrap();
+    env.block.time = start.plus_seconds(3600);
+    try_update_funding_rate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &[]), 3).unwrap();
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetUser { user_address: "geekybot".to_string() }).unwrap();
+    let before: UserResponse = from_binary(&res).unwrap();
+    try_settle_funding_payment(deps.as_mut(), env.clone(), mock_info("geekybot", &[])).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetUser { user_address: "geekybot".to_string() }).unwrap();
+    let after: UserResponse = from_binary(&res).unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        env.clone(),
+        QueryMsg::GetFundingPaymentHistory {
+            user_address: "geekybot".to_string(),
+            start_after: None,
+            limit: None,
+        },
+    )
+    .unwrap();
+    let value: Vec<FundingPaymentHistoryResponse> = from_binary(&res).unwrap();
+    assert_eq!(1, value.len());
+    assert_eq!(3, value[0].market_index);
+    assert!(value[0].funding_payment.i128() < 0);
+    assert_eq!(
+        before.collateral.u128() as i128 + value[0].funding_payment.i128() / 10_000_000,
+        after.collateral.u128() as i128
+    );
+    assert!(after.collateral < before.collateral);
+}
+
+#[test]
+pub fn oracle_twap_window_and_cap_test() {
+    let mut deps = mock_dependencies(&coins(0, "token"));
+    let mut env = mock_env();
+    let start = env.block.time;
+
+    let msg = InstantiateMsg {
+        collateral_vault: String::from("collateral_vault"),
+        insurance_vault: String::from("insurance_vault"),
+        admin_controls_prices: true,
+        oracle: String::from(MOCK_CONTRACT_ADDR),
+    };
+
+    instantiate(deps.as_mut(), env.clone(), mock_info(ADMIN_ACCOUNT, &coins(0, "earth")), msg).unwrap();
+    for market_index in 1..=2 {
+        try_initialize_market(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "earth")),
+            market_index,
+            "LUNA-UST".to_string(),
+            Uint128::from(1_000_000_000_000_000u128),
+            Uint128::from(1_000_000_000_000_000u128),
+            3600,
+            Uint128::from(1000u128),
+            OracleSource::Oracle,
+            2000,
+            625,
+            500,
+            None,
+        )
+        .unwrap();
+        try_feeding_price(
+            deps.as_mut(),
+            mock_info(ADMIN_ACCOUNT, &coins(0, "tt")),
+            market_index,
+            10_000_000_000,
+        )
+        .unwrap();
+    }
+
+    // market 2 averages over a minute and lets the oracle pull it at most 10% per update
+    try_update_oracle_twap_parameters(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 2, 60, 0)
+        .unwrap_err();
+    try_update_oracle_twap_parameters(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 2, 60, 101)
+        .unwrap_err();
+    try_update_oracle_twap_parameters(deps.as_mut(), mock_info(ADMIN_ACCOUNT, &[]), 2, 60, 10)
+        .unwrap();
+
+    // the oracle jumps 50% a minute later on both markets
+    env.block.time = start.plus_seconds(60);
+    for market_index in 1..=2 {
+        update_oracle_price_twap(&mut deps.as_mut(), market_index, env.block.time.seconds(), 15_000_000_000)
+            .unwrap();
+    }
+
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 1 }).unwrap();
+    let hourly: MarketInfoResponse = from_binary(&res).unwrap();
+    let res = query(deps.as_ref(), env.clone(), QueryMsg::GetMarketInfo { market_index: 2 }).unwrap();
+    let minutely: MarketInfoResponse = from_binary(&res).unwrap();
+    assert_eq!(3600, hourly.oracle_twap_period);
+    assert_eq!(33, hourly.oracle_twap_cap_percent);
+    assert_eq!(60, minutely.oracle_twap_period);
+    assert_eq!(10, minutely.oracle_twap_cap_percent);
+
+    // each market only lets the oracle move its twap by its own cap
+    assert_eq!(14_950_000_000, hourly.last_oracle_price.i128());
+    assert_eq!(11_500_000_000, minutely.last_oracle_price.i128());
+
+    // a minute is the whole of the short window but a sliver of the hour
+    assert_eq!(
+        (10_000_000_000 * 3540 + 14_950_000_000 * 60) / 3600,
+        hourly.last_oracle_price_twap.i128()
+    );
+    assert_eq!(
+        (10_000_000_000 + 11_500_000_000 * 60) / 61,
+        minutely.last_oracle_price_twap.i128()
+    );
+    assert!(minutely.last_oracle_price_twap.i128() > hourly.last_oracle_price_twap.i128());
+}
@@ -166,6 +166,7 @@ pub fn test_deposit_withdraw() {
         margin_ratio_initial,
         margin_ratio_partial,
         margin_ratio_maintenance,
+        None,
     )
     .unwrap();
 
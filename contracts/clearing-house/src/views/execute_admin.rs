@@ -12,16 +12,19 @@ use crate::states::state::FEESTRUCTURE;
 use crate::states::state::ORACLEGUARDRAILS;
 use crate::states::state::ORDERSTATE;
 use crate::states::state::STATE;
+use crate::states::liquidation::{LiquidationAuctionConfig, LIQUIDATION_AUCTION_CONFIG};
+use crate::states::referral::{ReferrerRewardRate, REFERRERS};
+use crate::states::state::{DiscountTokenConfig, FeeTierVolumes, DISCOUNT_TOKEN, FEE_TIER_VOLUMES};
 
 use ariel::helper::addr_validate_to_lower;
 use ariel::helper::VaultInterface;
 use ariel::number::Number128;
 use ariel::types::OraclePriceData;
 use ariel::types::{
-    FeeStructure, OracleGuardRails, OracleSource, 
+    CurveType, DiscountToken, FeeStructure, OracleGuardRails, OracleSource,
 };
 use cosmwasm_std::{
-    to_binary, CosmosMsg, Decimal, DepsMut, Env, MessageInfo, Response, Uint128,
+    to_binary, CosmosMsg, Decimal, DepsMut, Env, Fraction, MessageInfo, Response, Uint128,
     WasmMsg,
 };
 
@@ -39,6 +42,7 @@ pub fn try_initialize_market(
     margin_ratio_initial: u32,
     margin_ratio_partial: u32,
     margin_ratio_maintenance: u32,
+    curve_type: Option<CurveType>,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &_info.sender.clone())?;
     let now = env.block.time.seconds();
@@ -59,6 +63,23 @@ pub fn try_initialize_market(
         amm_peg_multiplier,
     )?;
 
+    let curve_type = curve_type.unwrap_or_default();
+    helpers::amm::validate_curve_type(&curve_type, init_mark_price)?;
+
+    // a concentrated range trades on virtual reserves deepened by its bounds
+    let (amm_base_asset_reserve, amm_quote_asset_reserve) = match curve_type {
+        CurveType::Concentrated { min_price, max_price } => {
+            helpers::amm::calculate_concentrated_reserves(
+                amm_base_asset_reserve,
+                amm_quote_asset_reserve,
+                init_mark_price,
+                min_price,
+                max_price,
+            )?
+        }
+        _ => (amm_base_asset_reserve, amm_quote_asset_reserve),
+    };
+
     let a = Amm {
         oracle: state.oracle,
         oracle_source,
@@ -83,6 +104,28 @@ pub fn try_initialize_market(
         last_oracle_price_twap_ts: now,
         last_oracle_price: Number128::zero(),
         minimum_base_asset_trade_size: Uint128::from(10000000 as u128),
+        repeg_budget: Uint128::zero(),
+        dynamic_k_enabled: false,
+        minimum_sqrt_k: amm_base_asset_reserve,
+        maximum_sqrt_k: amm_base_asset_reserve,
+        last_k_update_open_interest: Uint128::zero(),
+        last_k_update_total_fee_minus_distributions: Uint128::zero(),
+        base_spread: 0,
+        max_spread: 0,
+        total_spread_revenue: Uint128::zero(),
+        max_funding_rate: 0,
+        interest_rate: 0,
+        funding_clamp: 0,
+        premium_index_sum: Number128::zero(),
+        premium_index_duration: 0,
+        premium_index_ts: now,
+        last_premium: Number128::zero(),
+        funding_pool: Uint128::zero(),
+        funding_fee_share: DEFAULT_FUNDING_FEE_SHARE,
+        funding_shortfall: Number128::zero(),
+        oracle_twap_period: amm_periodicity,
+        oracle_twap_cap_percent: DEFAULT_ORACLE_TWAP_CAP_PERCENT,
+        curve_type,
     };
 
     // Verify there's no overflow
@@ -263,6 +306,7 @@ pub fn try_repeg_amm_curve(
             ts: now,
             record_id: curve_history_info_length,
             market_index,
+            record_type: Type::Repeg,
             peg_multiplier_before,
             base_asset_reserve_before,
             quote_asset_reserve_before,
@@ -361,10 +405,15 @@ pub fn try_reset_amm_oracle_twap(
 pub fn try_update_funding_rate(
     mut deps: DepsMut,
     env: Env,
+    info: MessageInfo,
     market_index: u64,
 ) -> Result<Response, ContractError> {
     let now = env.block.time.seconds();
     let funding_paused = STATE.load(deps.storage).unwrap().funding_paused;
+    let last_funding_rate_ts = MARKETS
+        .load(deps.storage, market_index.to_string())?
+        .amm
+        .last_funding_rate_ts;
     controller::funding::update_funding_rate(
         &mut deps,
         market_index,
@@ -372,7 +421,16 @@ pub fn try_update_funding_rate(
         funding_paused,
         None,
     )?;
-    Ok(Response::new().add_attribute("method", "try_update_funding_rate"))
+
+    // only the caller that actually advances funding is rewarded
+    let mut reward = Uint128::zero();
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    if market.amm.last_funding_rate_ts > last_funding_rate_ts {
+        reward = controller::funding::pay_funding_update_reward(&mut deps, market_index, &info.sender)?;
+    }
+    Ok(Response::new()
+        .add_attribute("method", "try_update_funding_rate")
+        .add_attribute("keeper_reward", reward.to_string()))
 }
 
 pub fn try_update_k(
@@ -390,10 +448,10 @@ pub fn try_update_k(
     let base_asset_amount = market.base_asset_amount.i128().clone();
     let open_interest = market.open_interest.clone();
 
-    let price_before = helpers::amm::calculate_price(
+    let price_before = helpers::amm::calculate_curve_price(
+        &market.amm,
         market.amm.quote_asset_reserve,
         market.amm.base_asset_reserve,
-        market.amm.peg_multiplier,
     )?;
 
     let peg_multiplier_before = market.amm.peg_multiplier;
@@ -424,10 +482,10 @@ pub fn try_update_k(
     }
 
     let amm = &market.amm;
-    let price_after = helpers::amm::calculate_price(
+    let price_after = helpers::amm::calculate_curve_price(
+        amm,
         amm.quote_asset_reserve,
         amm.base_asset_reserve,
-        amm.peg_multiplier,
     )?;
 
     let price_change_too_large = (price_before.u128() as i128)
@@ -472,6 +530,7 @@ pub fn try_update_k(
             ts: now,
             record_id: curve_history_info_length,
             market_index,
+            record_type: Type::UpdateK,
             peg_multiplier_before,
             base_asset_reserve_before,
             quote_asset_reserve_before,
@@ -498,6 +557,153 @@ pub fn try_update_k(
     Ok(Response::new().add_attribute("method", "try_update_k"))
 }
 
+pub fn try_update_repeg_budget(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    repeg_budget: Uint128,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.repeg_budget = repeg_budget;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_repeg_budget"))
+}
+
+pub fn try_update_dynamic_k(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    enabled: bool,
+    minimum_sqrt_k: Uint128,
+    maximum_sqrt_k: Uint128,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if minimum_sqrt_k.is_zero() || minimum_sqrt_k > maximum_sqrt_k {
+        return Err(ContractError::InvalidDynamicKBounds);
+    }
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.dynamic_k_enabled = enabled;
+            market.amm.minimum_sqrt_k = minimum_sqrt_k;
+            market.amm.maximum_sqrt_k = maximum_sqrt_k;
+            market.amm.last_k_update_open_interest = market.open_interest;
+            market.amm.last_k_update_total_fee_minus_distributions =
+                market.amm.total_fee_minus_distributions;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_dynamic_k"))
+}
+
+pub fn try_update_spread(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    base_spread: u32,
+    max_spread: u32,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if base_spread > max_spread || max_spread as i128 >= PRICE_SPREAD_PRECISION {
+        return Err(ContractError::InvalidSpread);
+    }
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.base_spread = base_spread;
+            market.amm.max_spread = max_spread;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_spread"))
+}
+
+pub fn try_update_funding_parameters(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    max_funding_rate: u32,
+    interest_rate: u32,
+    funding_clamp: u32,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if max_funding_rate as i128 > PRICE_SPREAD_PRECISION
+        || interest_rate as i128 > PRICE_SPREAD_PRECISION
+        || funding_clamp as i128 > PRICE_SPREAD_PRECISION
+    {
+        return Err(ContractError::InvalidFundingParameters);
+    }
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.max_funding_rate = max_funding_rate;
+            market.amm.interest_rate = interest_rate;
+            market.amm.funding_clamp = funding_clamp;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_funding_parameters"))
+}
+
+pub fn try_update_funding_fee_share(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    funding_fee_share: u32,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if funding_fee_share as i128 > PRICE_SPREAD_PRECISION {
+        return Err(ContractError::InvalidFundingParameters);
+    }
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.funding_fee_share = funding_fee_share;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_funding_fee_share"))
+}
+
+pub fn try_update_oracle_twap_parameters(
+    deps: DepsMut,
+    info: MessageInfo,
+    market_index: u64,
+    oracle_twap_period: u64,
+    oracle_twap_cap_percent: u32,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if oracle_twap_period == 0 || oracle_twap_cap_percent == 0 || oracle_twap_cap_percent > 100 {
+        return Err(ContractError::InvalidOracleTwapParameters);
+    }
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    MARKETS.update(
+        deps.storage,
+        market_index.to_string(),
+        |_m| -> Result<Market, ContractError> {
+            market.amm.oracle_twap_period = oracle_twap_period;
+            market.amm.oracle_twap_cap_percent = oracle_twap_cap_percent;
+            Ok(market)
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_oracle_twap_parameters"))
+}
+
 pub fn try_update_margin_ratio(
     deps: DepsMut,
     info: MessageInfo,
@@ -605,6 +811,7 @@ pub fn try_update_fee(
     deps: DepsMut,
     info: MessageInfo,
     fee: Decimal,
+    maker_fee: Number128,
     first_tier_minimum_balance: Uint128,
     first_tier_discount: Decimal,
     second_tier_minimum_balance: Uint128,
@@ -617,8 +824,18 @@ pub fn try_update_fee(
     referee_discount: Decimal,
 ) -> Result<Response, ContractError> {
     ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+
+    // a maker rebate larger than the taker fee could be farmed by trading against yourself
+    if !maker_fee.is_positive
+        && Uint128::from(maker_fee.amount).checked_mul(Uint128::from(fee.denominator()))?
+            > Uint128::from(fee.numerator()).checked_mul(MAKER_FEE_PRECISION)?
+    {
+        return Err(ContractError::InvalidMakerFee);
+    }
+
     let fee_structure = FeeStructure {
         fee,
+        maker_fee,
         first_tier_minimum_balance,
         second_tier_minimum_balance,
         third_tier_minimum_balance,
@@ -656,6 +873,118 @@ pub fn try_update_order_state_structure(
     Ok(Response::new().add_attribute("method", "try_update_order_filler_reward_structure"))
 }
 
+pub fn try_update_referrer_reward_rate(
+    deps: DepsMut,
+    info: MessageInfo,
+    referrer: String,
+    referrer_reward: Decimal,
+    referee_discount: Decimal,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    let referrer_addr = addr_validate_to_lower(deps.api, &referrer)?;
+
+    // the referral split and the largest token discount all come out of the same fee
+    let fee_structure = FEESTRUCTURE.load(deps.storage)?;
+    if referrer_reward + referee_discount + fee_structure.first_tier_discount > Decimal::one() {
+        return Err(ContractError::InvalidReferralRewardRate);
+    }
+
+    let mut referrer = REFERRERS.may_load(deps.storage, &referrer_addr)?.unwrap_or_default();
+    referrer.reward_rate = Some(ReferrerRewardRate {
+        referrer_reward,
+        referee_discount,
+    });
+    REFERRERS.save(deps.storage, &referrer_addr, &referrer)?;
+    Ok(Response::new().add_attribute("method", "try_update_referrer_reward_rate"))
+}
+
+pub fn try_update_fee_tier_volumes(
+    deps: DepsMut,
+    info: MessageInfo,
+    first_tier_minimum_volume: Uint128,
+    second_tier_minimum_volume: Uint128,
+    third_tier_minimum_volume: Uint128,
+    fourth_tier_minimum_volume: Uint128,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+
+    // tiers are checked from first to fourth, so the thresholds must be descending
+    if first_tier_minimum_volume < second_tier_minimum_volume
+        || second_tier_minimum_volume < third_tier_minimum_volume
+        || third_tier_minimum_volume < fourth_tier_minimum_volume
+    {
+        return Err(ContractError::InvalidFeeTierVolumes);
+    }
+
+    let fee_tier_volumes = FeeTierVolumes {
+        first_tier_minimum_volume,
+        second_tier_minimum_volume,
+        third_tier_minimum_volume,
+        fourth_tier_minimum_volume,
+    };
+    FEE_TIER_VOLUMES.save(deps.storage, &fee_tier_volumes)?;
+    Ok(Response::new().add_attribute("method", "try_update_fee_tier_volumes"))
+}
+
+pub fn try_update_discount_token(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: Option<DiscountToken>,
+    staking_contract: Option<String>,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+
+    // without a token, fee tiers come from trading volume only
+    let token = match token {
+        Some(token) => token,
+        None => {
+            DISCOUNT_TOKEN.remove(deps.storage);
+            return Ok(Response::new().add_attribute("method", "try_update_discount_token"));
+        }
+    };
+    if let DiscountToken::Cw20 { contract_addr } = &token {
+        addr_validate_to_lower(deps.api, contract_addr)?;
+    }
+    let staking_contract = match staking_contract {
+        Some(s) => Some(addr_validate_to_lower(deps.api, &s)?),
+        None => None,
+    };
+
+    DISCOUNT_TOKEN.save(
+        deps.storage,
+        &DiscountTokenConfig {
+            token,
+            staking_contract,
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_discount_token"))
+}
+
+pub fn try_update_liquidation_auction_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+    duration: u64,
+    start_discount: Decimal,
+    max_discount: Decimal,
+) -> Result<Response, ContractError> {
+    ADMIN.assert_admin(deps.as_ref(), &info.sender.clone())?;
+    if duration == 0 || start_discount > max_discount || max_discount >= Decimal::one() {
+        return Err(ContractError::InvalidLiquidationAuctionConfig);
+    }
+
+    LIQUIDATION_AUCTION_CONFIG.save(
+        deps.storage,
+        &LiquidationAuctionConfig {
+            enabled,
+            duration,
+            start_discount,
+            max_discount,
+        },
+    )?;
+    Ok(Response::new().add_attribute("method", "try_update_liquidation_auction_config"))
+}
+
 pub fn try_update_market_oracle(
     deps: DepsMut,
     info: MessageInfo,
@@ -7,13 +7,14 @@ use crate::helpers::position::calculate_withdrawal_amounts;
 use crate::states::history::*;
 use crate::ContractError;
 
+use crate::states::liquidation::{LIQUIDATION_AUCTIONS, LIQUIDATION_AUCTION_CONFIG};
 use crate::states::market::LiquidationStatus;
 use crate::states::market::LiquidationType;
 use crate::states::market::{Market, MARKETS};
 use crate::states::state::FEESTRUCTURE;
 use crate::states::state::ORACLEGUARDRAILS;
 use crate::states::state::STATE;
-use crate::states::user::{User, POSITIONS, USERS};
+use crate::states::user::{get_open_positions, User, POSITIONS, USERS};
 
 use ariel::helper::addr_validate_to_lower;
 use ariel::helper::assert_sent_uusd_balance;
@@ -22,10 +23,10 @@ use ariel::helper::VaultInterface;
 use ariel::number::Number128;
 use ariel::types::OrderType;
 use ariel::types::{
-    DepositDirection, OrderParams, PositionDirection,
+    DepositDirection, LiquidatedMarket, MakerOrderId, OrderParams, PositionDirection,
 };
 use cosmwasm_std::{
-    coins, to_binary, CosmosMsg, DepsMut, Env, Fraction, MessageInfo, Response, Uint128,
+    coins, to_binary, Addr, CosmosMsg, DepsMut, Env, Fraction, MessageInfo, Response, Uint128,
     WasmMsg,
 };
 
@@ -52,7 +53,12 @@ pub fn try_deposit_collateral(
                 total_token_discount: Uint128::zero(),
                 total_referral_reward: Uint128::zero(),
                 total_referee_discount: Uint128::zero(),
-                referrer: Some(addr_validate_to_lower(deps.api, &referrer.unwrap())?),
+                referrer: Some(controller::referral::resolve_referrer(
+                    &deps,
+                    &user_address,
+                    &referrer.unwrap(),
+                )?),
+                next_order_id: 0,
             };
         } else {
             user = User {
@@ -63,6 +69,7 @@ pub fn try_deposit_collateral(
                 total_referral_reward: Uint128::zero(),
                 total_referee_discount: Uint128::zero(),
                 referrer: None,
+                next_order_id: 0,
             };
         }
     }
@@ -329,16 +336,21 @@ pub fn try_open_position(
         return Err(ContractError::InsufficientCollateral.into());
     }
 
-    // todo add referrer and discount token
     let referrer = user.referrer.clone();
-    let discount_token = Uint128::zero();
+    let fee_structure =
+        controller::referral::get_referral_fee_structure(deps.storage, &fee_structure, &referrer)?;
+    let discount_token =
+        controller::volume::get_discount_token_balance(deps.storage, &deps.querier, &user_address)?;
+    let volume_fee_tier = controller::volume::get_volume_fee_tier(deps.storage, &user_address, now)?;
     let (user_fee, fee_to_market, token_discount, referrer_reward, referee_discount) =
         helpers::fees::calculate_fee_for_trade(
             quote_asset_amount,
             &fee_structure,
             discount_token,
+            &volume_fee_tier,
             &referrer,
         )?;
+    controller::volume::record_user_volume(&mut deps, &user_address, quote_asset_amount, now)?;
 
     {
         let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
@@ -347,6 +359,16 @@ pub fn try_open_position(
             .amm
             .total_fee_minus_distributions
             .checked_add(fee_to_market)?;
+        if let Some(referrer_addr) = &referrer {
+            controller::referral::accrue_referral_reward(
+                deps.storage,
+                &mut market,
+                market_index,
+                referrer_addr,
+                &user_address,
+                referrer_reward,
+            )?;
+        }
         MARKETS.update(
             deps.storage,
             market_index.to_string(),
@@ -365,21 +387,6 @@ pub fn try_open_position(
     user.total_token_discount = user.total_token_discount.checked_add(token_discount)?;
     user.total_referee_discount = user.total_referee_discount.checked_add(referee_discount)?;
 
-    // Update the referrer's collateral with their reward
-    if referrer.is_some() {
-        let mut _referrer = USERS.load(deps.storage, &referrer.clone().unwrap())?;
-        _referrer.total_referral_reward = _referrer
-            .total_referral_reward
-            .checked_add(referrer_reward)?;
-        // todo what this signifies
-        // referrer.exit(ctx.program_id)?;
-        USERS.update(
-            deps.storage,
-            &referrer.unwrap().clone(),
-            |_m| -> Result<User, ContractError> { Ok(_referrer) },
-        )?;
-    }
-
     let is_oracle_mark_too_divergent_before = helpers::amm::is_oracle_mark_too_divergent(
         oracle_mark_spread_pct_before,
         &oracle_guard_rails,
@@ -415,6 +422,7 @@ pub fn try_open_position(
             mark_price_before,
             mark_price_after,
             fee: user_fee,
+            maker_fee: Number128::zero(),
             referrer_reward,
             referee_discount,
             token_discount,
@@ -495,15 +503,20 @@ pub fn try_close_position(
     market = MARKETS.load(deps.storage, market_index.to_string())?;
     let base_asset_amount = Uint128::from(base_asset_amount.unsigned_abs());
     let referrer = user.referrer.clone();
-    let discount_token = Uint128::zero();
-
+    let fee_structure =
+        controller::referral::get_referral_fee_structure(deps.storage, &fee_structure, &referrer)?;
+    let discount_token =
+        controller::volume::get_discount_token_balance(deps.storage, &deps.querier, &user_address)?;
+    let volume_fee_tier = controller::volume::get_volume_fee_tier(deps.storage, &user_address, now)?;
     let (user_fee, fee_to_market, token_discount, referrer_reward, referee_discount) =
         helpers::fees::calculate_fee_for_trade(
             quote_asset_amount,
             &fee_structure,
             discount_token,
+            &volume_fee_tier,
             &referrer,
         )?;
+    controller::volume::record_user_volume(&mut deps, &user_address, quote_asset_amount, now)?;
 
     market.amm.total_fee = market.amm.total_fee.checked_add(fee_to_market)?;
     market.amm.total_fee_minus_distributions = market
@@ -521,15 +534,14 @@ pub fn try_close_position(
     user.total_token_discount = user.total_token_discount.checked_add(token_discount)?;
     user.total_referee_discount = user.total_referee_discount.checked_add(referee_discount)?;
 
-    if referrer.is_some() {
-        let mut _referrer = USERS.load(deps.storage, &referrer.clone().unwrap())?;
-        _referrer.total_referral_reward = _referrer
-            .total_referral_reward
-            .checked_add(referrer_reward)?;
-        USERS.update(
+    if let Some(referrer_addr) = &referrer {
+        controller::referral::accrue_referral_reward(
             deps.storage,
-            &referrer.unwrap().clone(),
-            |_m| -> Result<User, ContractError> { Ok(_referrer) },
+            &mut market,
+            market_index,
+            referrer_addr,
+            &user_address,
+            referrer_reward,
         )?;
     }
 
@@ -612,6 +624,7 @@ pub fn try_close_position(
             mark_price_before,
             mark_price_after,
             fee: user_fee,
+            maker_fee: Number128::zero(),
             referrer_reward,
             referee_discount,
             token_discount,
@@ -672,6 +685,106 @@ pub fn try_cancel_order(
     Ok(Response::new().add_attribute("method", "try_cancel_order"))
 }
 
+pub fn try_cancel_order_by_user_id(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    user_order_id: u64,
+) -> Result<Response, ContractError> {
+    let now = env.block.time.seconds();
+    let state = STATE.load(deps.storage)?;
+    let oracle = state.oracle;
+    controller::order::cancel_order_by_user_order_id(
+        &mut deps,
+        &info.sender.clone(),
+        user_order_id,
+        &oracle,
+        now,
+    )?;
+    Ok(Response::new().add_attribute("method", "try_cancel_order_by_user_id"))
+}
+
+pub fn try_cancel_all_orders(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    market_index: Option<u64>,
+    direction: Option<PositionDirection>,
+) -> Result<Response, ContractError> {
+    let now = env.block.time.seconds();
+    let state = STATE.load(deps.storage)?;
+    let oracle = state.oracle;
+    let (canceled_orders, skipped_orders) = controller::order::cancel_all_orders(
+        &mut deps,
+        &info.sender.clone(),
+        market_index,
+        direction,
+        &oracle,
+        now,
+    )?;
+    Ok(Response::new()
+        .add_attribute("method", "try_cancel_all_orders")
+        .add_attribute("canceled_orders", canceled_orders.len().to_string())
+        .add_attribute(
+            "skipped_orders",
+            skipped_orders
+                .iter()
+                .map(|order_id| order_id.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ))
+}
+
+pub fn try_batch_orders(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    place: Vec<OrderParams>,
+    cancel: Vec<u64>,
+) -> Result<Response, ContractError> {
+    let now = env.block.time.seconds();
+    let state = STATE.load(deps.storage)?;
+    let oracle = state.oracle;
+    if place.iter().any(|order| order.order_type == OrderType::Market) {
+        return Err(ContractError::MarketOrderMustBeInPlaceAndFill.into());
+    }
+
+    controller::order::batch_orders(
+        &mut deps,
+        &info.sender.clone(),
+        place,
+        cancel,
+        &oracle,
+        now,
+    )?;
+    Ok(Response::new().add_attribute("method", "try_batch_orders"))
+}
+
+pub fn try_modify_order(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    order_id: u64,
+    new_price: Option<Uint128>,
+    new_base_asset_amount: Option<Uint128>,
+    new_trigger_price: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let now = env.block.time.seconds();
+    let state = STATE.load(deps.storage)?;
+    let oracle = state.oracle;
+    controller::order::modify_order(
+        &mut deps,
+        &info.sender.clone(),
+        order_id,
+        new_price,
+        new_base_asset_amount,
+        new_trigger_price,
+        &oracle,
+        now,
+    )?;
+    Ok(Response::new().add_attribute("method", "try_modify_order"))
+}
+
 //todo who is filler? is sender is filler and passing the user address?
 pub fn try_expire_orders(
     mut deps: DepsMut,
@@ -692,6 +805,7 @@ pub fn try_fill_order(
     order_id: u64,
     user_address: String,
     market_index: u64,
+    maker_orders: Option<Vec<MakerOrderId>>,
 ) -> Result<Response, ContractError> {
     let now = env.block.time.seconds();
     let user_address = addr_validate_to_lower(deps.api, &user_address.to_string())?;
@@ -701,6 +815,7 @@ pub fn try_fill_order(
         &info.sender.clone(),
         market_index,
         order_id,
+        maker_orders.unwrap_or_default(),
         now,
     )?;
     if base_asset_amount.is_zero() {
@@ -709,8 +824,24 @@ pub fn try_fill_order(
     Ok(Response::new().add_attribute("method", "try_fill_order"))
 }
 
-//todo later
+/// Removes and reports an ended liquidation auction for the user's position in the market
+fn take_ended_liquidation_auction(
+    deps: &mut DepsMut,
+    user_address: &Addr,
+    market_index: u64,
+    now: u64,
+) -> Result<bool, ContractError> {
+    match LIQUIDATION_AUCTIONS.may_load(deps.storage, (user_address, market_index.to_string()))? {
+        Some(auction) if now >= auction.end_ts => {
+            LIQUIDATION_AUCTIONS.remove(deps.storage, (user_address, market_index.to_string()));
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
 
+/// Liquidates an account below its maintenance margin into the amm, fully or partially.
+/// With auctions enabled, a market's position is only closed into the amm once its auction has ended.
 pub fn try_liquidate(
     mut deps: DepsMut,
     env: Env,
@@ -723,6 +854,15 @@ pub fn try_liquidate(
     let now = env.block.time.seconds();
 
     controller::funding::settle_funding_payment(&mut deps, &user_address, now)?;
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+    for position in get_open_positions(deps.storage, &user_address)? {
+        controller::amm::update_oracle_price_twap_from_oracle(
+            &mut deps,
+            position.market_index,
+            now,
+            &oracle_guard_rails,
+        )?;
+    }
 
     let mut user = USERS.load(deps.storage, &user_address)?;
 
@@ -732,7 +872,7 @@ pub fn try_liquidate(
         adjusted_total_collateral,
         unrealized_pnl,
         base_asset_value,
-        market_statuses,
+        mut market_statuses,
         mut margin_requirement,
         margin_ratio,
     } = controller::margin::calculate_liquidation_status(
@@ -740,6 +880,21 @@ pub fn try_liquidate(
         &user_address
     )?;
 
+    // with auctions enabled, the amm only absorbs what liquidators did not take over
+    let liquidation_auction_config = LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?;
+    let liquidation_auctions_enabled = liquidation_auction_config.enabled;
+    if liquidation_auctions_enabled && liquidation_type != LiquidationType::NONE {
+        match LIQUIDATION_AUCTIONS.may_load(deps.storage, (&user_address, market_index.to_string()))? {
+            None => return Err(ContractError::LiquidationAuctionRequired),
+            Some(auction) if now < auction.end_ts => {
+                return Err(ContractError::LiquidationAuctionInProgress)
+            }
+            Some(_) => {
+                LIQUIDATION_AUCTIONS.remove(deps.storage, (&user_address, market_index.to_string()));
+            }
+        }
+    }
+
     let res: Response = Response::new().add_attribute("method", "try_liquidate");
     let collateral = user.collateral;
     if liquidation_type == LiquidationType::NONE {
@@ -754,10 +909,25 @@ pub fn try_liquidate(
         return Err(ContractError::SufficientCollateral.into());
     }
 
+    if !market_statuses
+        .iter()
+        .any(|m| m.market_index == market_index && !m.base_asset_value.is_zero())
+    {
+        return Err(ContractError::UserHasNoPositionInMarket);
+    }
+    // the liquidator's market goes first, the rest largest maintenance requirement first
+    market_statuses.sort_by(|a, b| {
+        (b.market_index == market_index)
+            .cmp(&(a.market_index == market_index))
+            .then(b.maintenance_margin_requirement.cmp(&a.maintenance_margin_requirement))
+            .then(a.market_index.cmp(&b.market_index))
+    });
+
     let is_dust_position = adjusted_total_collateral <= QUOTE_PRECISION;
 
     let mut base_asset_value_closed: Uint128 = Uint128::zero();
     let mut liquidation_fee = Uint128::zero();
+    let mut liquidated_markets: Vec<LiquidatedMarket> = vec![];
 
     let is_full_liquidation = liquidation_type == LiquidationType::FULL || is_dust_position;
 
@@ -792,8 +962,15 @@ pub fn try_liquidate(
                 }
             }
 
-            let market_position = POSITIONS.load(deps.storage, (&user_address, market_index.to_string()))?;
-            // todo initialize position
+            // the other markets are only closed into the amm once their own auction has ended
+            if liquidation_auctions_enabled
+                && market_status.market_index != market_index
+                && !take_ended_liquidation_auction(&mut deps, &user_address, market_status.market_index, now)?
+            {
+                continue;
+            }
+
+            let market_position = POSITIONS.load(deps.storage, (&user_address, market_status.market_index.to_string()))?;
 
             let mark_price_before_i128 = mark_price_before.u128() as i128;
             let close_position_slippage = match market_status.close_position_slippage {
@@ -835,8 +1012,6 @@ pub fn try_liquidate(
                     .ok_or_else(|| (ContractError::MathError))?
             };
 
-            let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
-    
             let oracle_mark_too_divergent_after_close = helpers::amm::is_oracle_mark_too_divergent(
                 oracle_mark_divergence_after_close,
                 &oracle_guard_rails,
@@ -872,8 +1047,8 @@ pub fn try_liquidate(
                     direction_to_close,
                     quote_asset_amount,
                     &user_address,
-                    market_index,
-                    market_index,
+                    market_status.market_index,
+                    market_status.market_index,
                     now,
                     Some(mark_price_before),
                 )?;
@@ -883,8 +1058,8 @@ pub fn try_liquidate(
                 let (quote_asset_amount, base_asset_amount, _) = controller::position::close(
                     &mut deps,
                     &user_address,
-                    market_index,
-                    market_index,
+                    market_status.market_index,
+                    market_status.market_index,
                     now,
                     None,
                     Some(mark_price_before),
@@ -922,11 +1097,12 @@ pub fn try_liquidate(
                     mark_price_before,
                     mark_price_after,
                     fee: Uint128::zero(),
+                    maker_fee: Number128::zero(),
                     referrer_reward: Uint128::zero(),
                     referee_discount: Uint128::zero(),
                     token_discount: Uint128::zero(),
                     liquidation: true,
-                    market_index,
+                    market_index: market_status.market_index,
                     oracle_price: market_status.oracle_status.price_data.price,
                 },
             )?;
@@ -943,6 +1119,12 @@ pub fn try_liquidate(
                 .checked_div(base_asset_value)?;
 
             liquidation_fee = liquidation_fee.checked_add(market_liquidation_fee)?;
+            liquidated_markets.push(LiquidatedMarket {
+                market_index: market_status.market_index,
+                base_asset_amount: Uint128::from(base_asset_amount),
+                base_asset_value_closed: quote_asset_amount,
+                liquidation_fee: market_liquidation_fee,
+            });
 
             let adjusted_total_collateral_after_fee =
                 adjusted_total_collateral.checked_sub(liquidation_fee)?;
@@ -964,7 +1146,7 @@ pub fn try_liquidate(
             }
 
             let oracle_status = &market_status.oracle_status;
-            let market = MARKETS.load(deps.storage, market_index.to_string())?;
+            let market = MARKETS.load(deps.storage, market_status.market_index.to_string())?;
             let mark_price_before = market_status.mark_price_before;
 
             let oracle_is_valid = oracle_status.is_valid;
@@ -981,7 +1163,14 @@ pub fn try_liquidate(
                 }
             }
 
-            let market_position = POSITIONS.load(deps.storage, (&user_address, market_index.to_string()))?;
+            if liquidation_auctions_enabled
+                && market_status.market_index != market_index
+                && !take_ended_liquidation_auction(&mut deps, &user_address, market_status.market_index, now)?
+            {
+                continue;
+            }
+
+            let market_position = POSITIONS.load(deps.storage, (&user_address, market_status.market_index.to_string()))?;
 
             let mut quote_asset_amount = market_status
                 .base_asset_value
@@ -1035,7 +1224,6 @@ pub fn try_liquidate(
                     .ok_or_else(|| (ContractError::MathError))?
             };
 
-            let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
             let oracle_mark_too_divergent_after_reduce =
                 helpers::amm::is_oracle_mark_too_divergent(
                     oracle_mark_divergence_after_reduce,
@@ -1073,8 +1261,8 @@ pub fn try_liquidate(
                 direction_to_reduce,
                 quote_asset_amount,
                 &user_address,
-                market_index,
-                market_index,
+                market_status.market_index,
+                market_status.market_index,
                 now,
                 Some(mark_price_before),
             )?
@@ -1107,11 +1295,12 @@ pub fn try_liquidate(
                     mark_price_before,
                     mark_price_after,
                     fee: Uint128::zero(),
+                    maker_fee: Number128::zero(),
                     referrer_reward: Uint128::zero(),
                     referee_discount: Uint128::zero(),
                     token_discount: Uint128::zero(),
                     liquidation: true,
-                    market_index,
+                    market_index: market_status.market_index,
                     oracle_price: market_status.oracle_status.price_data.price,
                 },
             )?;
@@ -1128,6 +1317,12 @@ pub fn try_liquidate(
                 .checked_div(maximum_base_asset_value_closed)?;
 
             liquidation_fee = liquidation_fee.checked_add(market_liquidation_fee)?;
+            liquidated_markets.push(LiquidatedMarket {
+                market_index: market_status.market_index,
+                base_asset_amount: Uint128::from(base_asset_amount),
+                base_asset_value_closed: quote_asset_amount,
+                liquidation_fee: market_liquidation_fee,
+            });
 
             let adjusted_total_collateral_after_fee =
                 adjusted_total_collateral.checked_sub(liquidation_fee)?;
@@ -1193,6 +1388,11 @@ pub fn try_liquidate(
         });
         messages.push(message);
     }
+    messages.extend(controller::bankruptcy::resolve_bankruptcy(
+        &mut deps,
+        &user_address,
+        now,
+    )?);
 
     let liquidation_history_info_length = LIQUIDATION_HISTORY_INFO
         .load(deps.storage)?
@@ -1213,7 +1413,9 @@ pub fn try_liquidate(
             ts: now,
             record_id: liquidation_history_info_length,
             user: user_address,
+            market_index,
             partial: !is_full_liquidation,
+            markets: liquidated_markets,
             base_asset_value,
             base_asset_value_closed,
             liquidation_fee,
@@ -1229,6 +1431,125 @@ pub fn try_liquidate(
     Ok(res.add_messages(messages))
 }
 
+pub fn try_liquidate_perp(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    user: String,
+    market_index: u64,
+    max_base_asset_amount: Uint128,
+    limit_price: Uint128,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.exchange_paused {
+        return Err(ContractError::ExchangePaused);
+    }
+    let user_address = addr_validate_to_lower(deps.api, &user)?;
+    let now = env.block.time.seconds();
+
+    let (base_asset_amount, quote_asset_amount) = controller::liquidation::liquidate_perp(
+        &mut deps,
+        &user_address,
+        &info.sender,
+        market_index,
+        max_base_asset_amount,
+        limit_price,
+        now,
+    )?;
+    let messages = controller::bankruptcy::resolve_bankruptcy(&mut deps, &user_address, now)?;
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("method", "try_liquidate_perp")
+        .add_attribute("base_asset_amount", base_asset_amount.to_string())
+        .add_attribute("quote_asset_amount", quote_asset_amount.to_string()))
+}
+
+pub fn try_start_liquidation_auction(
+    mut deps: DepsMut,
+    env: Env,
+    user: String,
+    market_index: u64,
+) -> Result<Response, ContractError> {
+    let user_address = addr_validate_to_lower(deps.api, &user)?;
+    let now = env.block.time.seconds();
+
+    let auction = controller::liquidation::start_liquidation_auction(
+        &mut deps,
+        &user_address,
+        market_index,
+        now,
+    )?;
+    Ok(Response::new()
+        .add_attribute("method", "try_start_liquidation_auction")
+        .add_attribute("base_asset_amount", auction.base_asset_amount.to_string())
+        .add_attribute("end_ts", auction.end_ts.to_string()))
+}
+
+pub fn try_bid_liquidation_auction(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    user: String,
+    market_index: u64,
+    base_asset_amount: Uint128,
+) -> Result<Response, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if state.exchange_paused {
+        return Err(ContractError::ExchangePaused);
+    }
+    let user_address = addr_validate_to_lower(deps.api, &user)?;
+    let now = env.block.time.seconds();
+
+    let base_asset_amount_filled = controller::liquidation::bid_liquidation_auction(
+        &mut deps,
+        &user_address,
+        &info.sender,
+        market_index,
+        base_asset_amount,
+        now,
+    )?;
+    let messages = controller::bankruptcy::resolve_bankruptcy(&mut deps, &user_address, now)?;
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("method", "try_bid_liquidation_auction")
+        .add_attribute("base_asset_amount", base_asset_amount_filled.to_string()))
+}
+
+pub fn try_resolve_bankruptcy(
+    mut deps: DepsMut,
+    env: Env,
+    user: String,
+) -> Result<Response, ContractError> {
+    let user_address = addr_validate_to_lower(deps.api, &user)?;
+    let now = env.block.time.seconds();
+
+    let messages = controller::bankruptcy::resolve_bankruptcy(&mut deps, &user_address, now)?;
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("method", "try_resolve_bankruptcy"))
+}
+
+pub fn try_register_referral_code(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+) -> Result<Response, ContractError> {
+    controller::referral::register_referral_code(&mut deps, &info.sender, code.clone())?;
+    Ok(Response::new()
+        .add_attribute("method", "try_register_referral_code")
+        .add_attribute("code", code))
+}
+
+pub fn try_claim_referral_rewards(
+    mut deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let claimed = controller::referral::claim_referral_rewards(&mut deps, &info.sender)?;
+    Ok(Response::new()
+        .add_attribute("method", "try_claim_referral_rewards")
+        .add_attribute("claimed", claimed.to_string()))
+}
+
 pub fn try_settle_funding_payment(
     mut deps: DepsMut,
     env: Env,
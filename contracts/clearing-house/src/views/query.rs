@@ -1,9 +1,24 @@
+use std::convert::TryInto;
+
 use crate::helpers::amm::use_oracle_price_for_margin_calculation;
+use crate::helpers::amm::{
+    calculate_new_mark_twap, calculate_new_oracle_price_twap, normalise_oracle_price,
+};
+use crate::helpers::funding::{
+    calculate_funding_rate, calculate_funding_rate_long_short, calculate_next_update_wait,
+    calculate_period_adjustment, calculate_premium, sample_premium_index,
+};
+use crate::helpers::oracle;
 use crate::helpers::position::{calculate_updated_collateral, calculate_slippage};
 use crate::states::constants::{
     AMM_TO_QUOTE_PRECISION_RATIO, DEFAULT_LIMIT, MARGIN_PRECISION, MARK_PRICE_PRECISION, MAX_LIMIT,
 };
+use crate::controller::volume;
+use crate::helpers::liquidation::{
+    calculate_auction_discount, calculate_auction_price, calculate_estimated_liquidator_reward,
+};
 use crate::helpers::oracle::get_oracle_status;
+use crate::helpers::order::{calculate_base_asset_amount_market_can_execute, get_valid_oracle_price};
 use crate::helpers::position::{
     calculate_base_asset_value_and_pnl, calculate_base_asset_value_and_pnl_with_oracle_price,
     direction_to_close_position,
@@ -12,7 +27,10 @@ use crate::ContractError;
 // use crate::helpers::casting::cast_to_i64;
 use crate::states::history::*;
 use crate::states::market::{LiquidationStatus, LiquidationType, MarketStatus, MARKETS};
-use crate::states::state::{ADMIN, STATE, ORACLEGUARDRAILS, ORDERSTATE, FEESTRUCTURE};
+use crate::states::order::{get_order_id_for_user_order_id, ORDERS};
+use crate::states::state::{ADMIN, STATE, ORACLEGUARDRAILS, ORDERSTATE, FEESTRUCTURE, FEE_TIER_VOLUMES, DISCOUNT_TOKEN};
+use crate::states::liquidation::{LIQUIDATION_AUCTIONS, LIQUIDATION_AUCTION_CONFIG};
+use crate::states::referral::{REFEREES, REFERRERS, UNCLAIMED_REFERRAL_REWARDS};
 use crate::states::user::{POSITIONS, USERS};
 
 use ariel::helper::addr_validate_to_lower;
@@ -20,9 +38,10 @@ use ariel::helper::addr_validate_to_lower;
 use ariel::number::Number128;
 use ariel::response::*;
 
-use ariel::types::{OracleGuardRails, PositionDirection};
-use cosmwasm_std::{Addr, Deps, Order, Uint128};
-use cw_storage_plus::{Bound, PrimaryKey};
+use ariel::types::{OracleGuardRails, OrderAction, OrderStatus, OrderType, PositionDirection};
+use ariel::types::Order as UserOrder;
+use cosmwasm_std::{Addr, Deps, Env, Order, StdError, Uint128};
+use cw_storage_plus::{Bound, PrimaryKey, U64Key};
 
 pub fn get_user(deps: Deps, user_address: String) -> Result<UserResponse, ContractError> {
     let user = USERS.load(
@@ -216,6 +235,7 @@ pub fn get_fee_structure(deps: Deps) -> Result<FeeStructureResponse, ContractErr
     let fs = FEESTRUCTURE.load(deps.storage)?;
     let res = FeeStructureResponse {
         fee: fs.fee,
+        maker_fee: fs.maker_fee,
         first_tier_minimum_balance: fs.first_tier_minimum_balance,
         first_tier_discount: fs.first_tier_discount,
         second_tier_minimum_balance: fs.second_tier_minimum_balance,
@@ -230,6 +250,184 @@ pub fn get_fee_structure(deps: Deps) -> Result<FeeStructureResponse, ContractErr
     Ok(res)
 }
 
+pub fn get_fee_tier_volumes(deps: Deps) -> Result<FeeTierVolumesResponse, ContractError> {
+    let ftv = FEE_TIER_VOLUMES.load(deps.storage)?;
+    let res = FeeTierVolumesResponse {
+        first_tier_minimum_volume: ftv.first_tier_minimum_volume,
+        second_tier_minimum_volume: ftv.second_tier_minimum_volume,
+        third_tier_minimum_volume: ftv.third_tier_minimum_volume,
+        fourth_tier_minimum_volume: ftv.fourth_tier_minimum_volume,
+    };
+    Ok(res)
+}
+
+pub fn get_user_fee_tier(
+    deps: Deps,
+    user_address: String,
+    now: u64,
+) -> Result<UserFeeTierResponse, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user_address)?;
+    Ok(UserFeeTierResponse {
+        tier: volume::get_user_fee_tier(deps.storage, &deps.querier, &user_addr, now)?,
+        rolling_volume: volume::get_rolling_volume(deps.storage, &user_addr, now)?,
+        discount_token_balance: volume::get_discount_token_balance(
+            deps.storage,
+            &deps.querier,
+            &user_addr,
+        )?,
+    })
+}
+
+pub fn get_discount_token(deps: Deps) -> Result<DiscountTokenResponse, ContractError> {
+    let config = DISCOUNT_TOKEN.may_load(deps.storage)?;
+    Ok(DiscountTokenResponse {
+        token: config.clone().map(|c| c.token),
+        staking_contract: config.and_then(|c| c.staking_contract).map(|a| a.to_string()),
+    })
+}
+
+/// Scans at most `limit` users after `start_after` and returns the liquidatable ones among them.
+pub fn get_liquidatable_users(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<LiquidatableUsersResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = match start_after {
+        Some(start) => Some(Bound::exclusive(
+            addr_validate_to_lower(deps.api, &start)?.as_bytes(),
+        )),
+        None => None,
+    };
+    let state = STATE.load(deps.storage)?;
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+
+    let user_addrs: Vec<Addr> = USERS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|k| String::from_utf8(k).map(Addr::unchecked))
+        .collect::<Result<Vec<Addr>, _>>()
+        .map_err(StdError::from)?;
+
+    let mut users: Vec<LiquidatableUserResponse> = vec![];
+    for user_addr in user_addrs.iter() {
+        let liquidation_status =
+            calculate_liquidation_status(&deps, user_addr, &oracle_guard_rails)?;
+        if liquidation_status.liquidation_type == LiquidationType::NONE {
+            continue;
+        }
+        users.push(LiquidatableUserResponse {
+            user: user_addr.to_string(),
+            estimated_liquidator_reward: calculate_estimated_liquidator_reward(
+                &state,
+                &liquidation_status.liquidation_type,
+                liquidation_status.total_collateral,
+            )?,
+            liquidation_type: liquidation_status.liquidation_type,
+            margin_ratio: liquidation_status.margin_ratio,
+        });
+    }
+
+    Ok(LiquidatableUsersResponse {
+        users,
+        last_user: user_addrs.last().map(|a| a.to_string()),
+    })
+}
+
+pub fn get_liquidation_auction_config(
+    deps: Deps,
+) -> Result<LiquidationAuctionConfigResponse, ContractError> {
+    let config = LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?;
+    Ok(LiquidationAuctionConfigResponse {
+        enabled: config.enabled,
+        duration: config.duration,
+        start_discount: config.start_discount,
+        max_discount: config.max_discount,
+    })
+}
+
+pub fn get_liquidation_auction(
+    deps: Deps,
+    user_address: String,
+    market_index: u64,
+    now: u64,
+) -> Result<LiquidationAuctionResponse, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user_address)?;
+    let config = LIQUIDATION_AUCTION_CONFIG.load(deps.storage)?;
+    let auction = LIQUIDATION_AUCTIONS
+        .may_load(deps.storage, (&user_addr, market_index.to_string()))?
+        .ok_or(ContractError::LiquidationAuctionNotFound)?;
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+
+    let current_discount =
+        calculate_auction_discount(&config, auction.start_ts, now)?;
+    let current_price = calculate_auction_price(
+        market.amm.mark_price()?,
+        current_discount,
+        auction.direction,
+    )?;
+    Ok(LiquidationAuctionResponse {
+        user: user_address,
+        market_index,
+        direction: auction.direction,
+        base_asset_amount: auction.base_asset_amount,
+        partial: auction.partial,
+        start_ts: auction.start_ts,
+        end_ts: auction.end_ts,
+        current_discount,
+        current_price,
+    })
+}
+
+pub fn get_referral_stats(
+    deps: Deps,
+    referrer: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<ReferralStatsResponse, ContractError> {
+    let referrer_addr = addr_validate_to_lower(deps.api, &referrer)?;
+    let user = USERS.load(deps.storage, &referrer_addr)?;
+    let info = REFERRERS.may_load(deps.storage, &referrer_addr)?.unwrap_or_default();
+    let fee_structure = FEESTRUCTURE.load(deps.storage)?;
+    let (referrer_reward, referee_discount) = match info.reward_rate {
+        Some(rate) => (rate.referrer_reward, rate.referee_discount),
+        None => (fee_structure.referrer_reward, fee_structure.referee_discount),
+    };
+
+    let unclaimed_referral_reward = UNCLAIMED_REFERRAL_REWARDS
+        .prefix(&referrer_addr)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter_map(|r| r.ok())
+        .fold(Uint128::zero(), |acc, (_, amount)| acc + amount);
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|start| start.joined_key())
+        .map(Bound::Exclusive);
+    let referees = REFEREES
+        .prefix(&referrer_addr)
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter_map(|referees| {
+            referees.ok().map(|referee| RefereeResponse {
+                referee: String::from_utf8(referee.0).unwrap_or_default(),
+                referrer_reward: referee.1,
+            })
+        })
+        .take(limit)
+        .collect();
+
+    Ok(ReferralStatsResponse {
+        referrer: referrer_addr.to_string(),
+        codes: info.codes,
+        referrer_reward,
+        referee_discount,
+        total_referral_reward: user.total_referral_reward,
+        total_claimed: info.total_claimed,
+        unclaimed_referral_reward,
+        referees,
+    })
+}
+
 pub fn get_curve_history_length(deps: Deps) -> Result<CurveHistoryLengthResponse, ContractError> {
     let ch_info = CURVE_HISTORY_INFO.load(deps.storage)?;
     let length = CurveHistoryLengthResponse {
@@ -257,6 +455,7 @@ pub fn get_curve_history(
                     ts: curve.1.ts,
                     record_id: curve.1.record_id,
                     market_index: curve.1.market_index,
+                    record_type: curve.1.record_type,
                     peg_multiplier_before: curve.1.peg_multiplier_before,
                     base_asset_reserve_before: curve.1.base_asset_reserve_before,
                     quote_asset_reserve_before: curve.1.quote_asset_reserve_before,
@@ -376,6 +575,72 @@ pub fn get_funding_rate_history_length(
     };
     Ok(length)
 }
+pub fn get_next_funding_time(
+    deps: Deps,
+    market_index: u64,
+) -> Result<NextFundingTimeResponse, ContractError> {
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let next_update_wait = calculate_next_update_wait(&market.amm)?;
+    Ok(NextFundingTimeResponse {
+        market_index,
+        last_funding_rate_ts: market.amm.last_funding_rate_ts,
+        next_funding_ts: market
+            .amm
+            .last_funding_rate_ts
+            .checked_add(next_update_wait)
+            .ok_or(ContractError::MathError)?,
+    })
+}
+
+pub fn get_funding_rate_estimate(
+    deps: Deps,
+    env: Env,
+    market_index: u64,
+) -> Result<FundingRateEstimateResponse, ContractError> {
+    let now = env.block.time.seconds();
+    let mut market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+
+    let (blocked_by_oracle, oracle_price_data) =
+        oracle::block_operation(&market.amm, &guard_rails, None)?;
+    let normalised_oracle_price = normalise_oracle_price(&market.amm, &oracle_price_data, None)?;
+    if !blocked_by_oracle {
+        let mark_price = market.amm.mark_price()?;
+        sample_premium_index(&mut market.amm, mark_price, normalised_oracle_price, now)?;
+    }
+
+    let mark_price_twap = calculate_new_mark_twap(&market.amm, now, None)?;
+    let oracle_price_twap = if normalised_oracle_price > 0 {
+        calculate_new_oracle_price_twap(&market.amm, now, normalised_oracle_price)?
+    } else {
+        market.amm.last_oracle_price_twap.i128()
+    };
+
+    let period_adjustment = calculate_period_adjustment(market.amm.funding_period)?;
+    let premium = calculate_premium(&market.amm, mark_price_twap, oracle_price_twap)?;
+    let funding_rate =
+        calculate_funding_rate(&market.amm, premium, oracle_price_twap, period_adjustment)?;
+    let (funding_rate_long, funding_rate_short, _) =
+        calculate_funding_rate_long_short(&market, funding_rate)?;
+
+    let next_funding_ts = market
+        .amm
+        .last_funding_rate_ts
+        .checked_add(calculate_next_update_wait(&market.amm)?)
+        .ok_or(ContractError::MathError)?;
+
+    Ok(FundingRateEstimateResponse {
+        market_index,
+        funding_rate_long: Number128::new(funding_rate_long),
+        funding_rate_short: Number128::new(funding_rate_short),
+        mark_price_twap,
+        oracle_price_twap: Number128::new(oracle_price_twap),
+        next_funding_ts,
+        time_remaining: next_funding_ts.saturating_sub(now),
+        blocked_by_oracle,
+    })
+}
+
 pub fn get_funding_rate_history(
     deps: Deps,
     start_after: Option<String>,
@@ -403,6 +668,9 @@ pub fn get_funding_rate_history(
                             .cumulative_funding_rate_short,
                         oracle_price_twap: funding_record.1.oracle_price_twap,
                         mark_price_twap: funding_record.1.mark_price_twap,
+                        capped_funding_amount: funding_record.1.capped_funding_amount,
+                        funding_pool: funding_record.1.funding_pool,
+                        funding_shortfall: funding_record.1.funding_shortfall,
                     })
             })
             .take(limit)
@@ -440,7 +708,9 @@ pub fn get_liquidation_history(
                 ts: record.1.ts,
                 record_id: record.1.record_id,
                 user: record.1.user.to_string(),
+                market_index: record.1.market_index,
                 partial: record.1.partial,
+                markets: record.1.markets,
                 base_asset_value: record.1.base_asset_value,
                 base_asset_value_closed: record.1.base_asset_value_closed,
                 liquidation_fee: record.1.liquidation_fee,
@@ -458,6 +728,96 @@ pub fn get_liquidation_history(
     Ok(liq_history)
 }
 
+pub fn get_bankruptcy_history_length(
+    deps: Deps,
+) -> Result<BankruptcyHistoryLengthResponse, ContractError> {
+    let bh_info = BANKRUPTCY_HISTORY_INFO.load(deps.storage)?;
+    Ok(BankruptcyHistoryLengthResponse { length: bh_info.len })
+}
+pub fn get_bankruptcy_history(
+    deps: Deps,
+    user_address: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<BankruptcyHistoryResponse>, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user_address)?;
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|start| start.joined_key())
+        .map(Bound::Exclusive);
+    let bankruptcy_history = BANKRUPTCY_HISTORY
+        .prefix(&user_addr)
+        .range(deps.storage, start, None, Order::Descending)
+        .filter_map(|records| {
+            records.ok().map(|record| BankruptcyHistoryResponse {
+                ts: record.1.ts,
+                record_id: record.1.record_id,
+                user: record.1.user.to_string(),
+                market_index: record.1.market_index,
+                loss: record.1.loss,
+                insurance_fund_paid: record.1.insurance_fund_paid,
+                socialized_loss: record.1.socialized_loss,
+                cumulative_funding_rate_delta: record.1.cumulative_funding_rate_delta,
+            })
+        })
+        .take(limit)
+        .collect();
+    Ok(bankruptcy_history)
+}
+
+pub fn get_order_history_length(deps: Deps) -> Result<OrderHistoryLengthResponse, ContractError> {
+    let oh_info = ORDER_HISTORY_INFO.load(deps.storage)?;
+    let length = OrderHistoryLengthResponse {
+        length: oh_info.len as u64,
+    };
+    Ok(length)
+}
+pub fn get_order_history(
+    deps: Deps,
+    user_address: Option<String>,
+    market_index: Option<u64>,
+    action: Option<OrderAction>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<OrderHistoryResponse>, ContractError> {
+    let user_addr = match user_address {
+        Some(user_address) => Some(addr_validate_to_lower(deps.api, &user_address)?),
+        None => None,
+    };
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    // newest first, so the page ends below the last record already seen
+    let end = start_after
+        .map(|start| U64Key::new(start).joined_key())
+        .map(Bound::Exclusive);
+    let order_history = ORDER_HISTORY
+        .range(deps.storage, None, end, Order::Descending)
+        .filter_map(|records| records.ok())
+        .filter(|record| user_addr.as_ref().map_or(true, |u| &record.1.user == u))
+        .filter(|record| market_index.map_or(true, |m| record.1.order.market_index == m))
+        .filter(|record| action.as_ref().map_or(true, |a| &record.1.action == a))
+        .map(|record| OrderHistoryResponse {
+            ts: record.1.ts,
+            record_id: record.0.as_slice().try_into().map(u64::from_be_bytes).unwrap_or_default(),
+            user: record.1.user.to_string(),
+            order: to_order_response(record.1.order),
+            action: record.1.action,
+            filler: record.1.filler.to_string(),
+            trade_record_id: record.1.trade_record_id,
+            base_asset_amount_filled: record.1.base_asset_amount_filled,
+            quote_asset_amount_filled: record.1.quote_asset_amount_filled,
+            fee: record.1.fee,
+            maker_fee: record.1.maker_fee,
+            filler_reward: record.1.filler_reward,
+            quote_asset_amount_surplus: record.1.quote_asset_amount_surplus,
+            market_index: record.1.position_index,
+        })
+        .take(limit)
+        .collect();
+    Ok(order_history)
+}
+
 pub fn get_trade_history_length(deps: Deps) -> Result<TradeHistoryLengthResponse, ContractError> {
     let th_info = TRADE_HISTORY_INFO.load(deps.storage)?;
     let length = TradeHistoryLengthResponse {
@@ -486,6 +846,7 @@ pub fn get_trade_history(
                 mark_price_before: record.1.mark_price_before,
                 mark_price_after: record.1.mark_price_after,
                 fee: record.1.fee,
+                maker_fee: record.1.maker_fee,
                 referrer_reward: record.1.referrer_reward,
                 referee_discount: record.1.referee_discount,
                 token_discount: record.1.token_discount,
@@ -531,11 +892,179 @@ pub fn get_market_info(deps: Deps, market_index: u64) -> Result<MarketInfoRespon
         last_oracle_price_twap_ts: market.amm.last_oracle_price_twap_ts,
         last_oracle_price: market.amm.last_oracle_price,
         minimum_base_asset_trade_size: market.amm.minimum_base_asset_trade_size,
-        minimum_quote_asset_trade_size: market.amm.minimum_quote_asset_trade_size
+        minimum_quote_asset_trade_size: market.amm.minimum_quote_asset_trade_size,
+        repeg_budget: market.amm.repeg_budget,
+        dynamic_k_enabled: market.amm.dynamic_k_enabled,
+        minimum_sqrt_k: market.amm.minimum_sqrt_k,
+        maximum_sqrt_k: market.amm.maximum_sqrt_k,
+        last_k_update_total_fee_minus_distributions: market
+            .amm
+            .last_k_update_total_fee_minus_distributions,
+        base_spread: market.amm.base_spread,
+        max_spread: market.amm.max_spread,
+        total_spread_revenue: market.amm.total_spread_revenue,
+        max_funding_rate: market.amm.max_funding_rate,
+        interest_rate: market.amm.interest_rate,
+        funding_clamp: market.amm.funding_clamp,
+        funding_pool: market.amm.funding_pool,
+        funding_fee_share: market.amm.funding_fee_share,
+        funding_shortfall: market.amm.funding_shortfall,
+        oracle_twap_period: market.amm.oracle_twap_period,
+        oracle_twap_cap_percent: market.amm.oracle_twap_cap_percent,
+        curve_type: market.amm.curve_type,
     };
     Ok(market_info)
 }
 
+pub fn get_order(
+    deps: Deps,
+    user: String,
+    order_id: u64,
+) -> Result<OrderResponse, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user)?;
+    let order = ORDERS
+        .may_load(deps.storage, (&user_addr, U64Key::new(order_id)))?
+        .ok_or(ContractError::OrderDoesNotExist)?;
+    Ok(to_order_response(order))
+}
+
+pub fn get_order_by_user_id(
+    deps: Deps,
+    user: String,
+    user_order_id: u64,
+) -> Result<OrderResponse, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user)?;
+    let order_id = get_order_id_for_user_order_id(deps.storage, &user_addr, user_order_id)?;
+    let order = ORDERS.load(deps.storage, (&user_addr, U64Key::new(order_id)))?;
+    Ok(to_order_response(order))
+}
+
+pub fn get_user_orders(
+    deps: Deps,
+    user: String,
+    market_index: Option<u64>,
+    order_type: Option<OrderType>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<OrderResponse>, ContractError> {
+    let user_addr = addr_validate_to_lower(deps.api, &user)?;
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after
+        .map(|start| U64Key::new(start).joined_key())
+        .map(Bound::Exclusive);
+
+    let orders = ORDERS
+        .prefix(&user_addr)
+        .range(deps.storage, start, None, Order::Ascending)
+        .filter_map(|orders| orders.ok().map(|order| order.1))
+        .filter(|order| market_index.map_or(true, |m| order.market_index == m))
+        .filter(|order| order_type.as_ref().map_or(true, |t| &order.order_type == t))
+        .take(limit)
+        .map(to_order_response)
+        .collect();
+    Ok(orders)
+}
+
+// orders in the market the amm could fill right now, i.e. limit price crossed or trigger condition hit
+// users are scanned a page at a time, `limit` bounds the number of users looked at
+pub fn get_fillable_orders(
+    deps: Deps,
+    now: u64,
+    market_index: u64,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<FillableOrdersResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = match start_after {
+        Some(start) => Some(Bound::exclusive(
+            addr_validate_to_lower(deps.api, &start)?.as_bytes(),
+        )),
+        None => None,
+    };
+    let market = MARKETS.load(deps.storage, market_index.to_string())?;
+    let oracle = STATE.load(deps.storage)?.oracle;
+    let oracle_guard_rails = ORACLEGUARDRAILS.load(deps.storage)?;
+    let mark_price = market.amm.mark_price()?;
+
+    let user_addrs: Vec<Addr> = USERS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|k| String::from_utf8(k).map(Addr::unchecked))
+        .collect::<Result<Vec<Addr>, _>>()
+        .map_err(StdError::from)?;
+
+    let mut fillable_orders: Vec<FillableOrderResponse> = vec![];
+    for user_addr in user_addrs.iter() {
+        let orders: Vec<UserOrder> = ORDERS
+            .prefix(user_addr)
+            .range(deps.storage, None, None, Order::Ascending)
+            .filter_map(|orders| orders.ok().map(|order| order.1))
+            .filter(|order| order.market_index == market_index && order.status == OrderStatus::Open)
+            .collect();
+
+        for order in orders {
+            let valid_oracle_price = match get_valid_oracle_price(
+                Some(&oracle),
+                &market,
+                &order,
+                &oracle_guard_rails,
+                now,
+            ) {
+                Ok(price) => price,
+                Err(_) => continue,
+            };
+            let base_asset_amount_market_can_execute = calculate_base_asset_amount_market_can_execute(
+                &order,
+                &market,
+                Some(mark_price),
+                valid_oracle_price,
+            )
+            .unwrap_or_else(|_| Uint128::zero());
+            if base_asset_amount_market_can_execute.is_zero() {
+                continue;
+            }
+            fillable_orders.push(FillableOrderResponse {
+                user: user_addr.to_string(),
+                order: to_order_response(order),
+                base_asset_amount_market_can_execute,
+            });
+        }
+    }
+
+    Ok(FillableOrdersResponse {
+        orders: fillable_orders,
+        last_user: user_addrs.last().map(|a| a.to_string()),
+    })
+}
+
+fn to_order_response(order: UserOrder) -> OrderResponse {
+    OrderResponse {
+        ts: order.ts,
+        order_id: order.order_id,
+        user_order_id: order.user_order_id,
+        status: order.status,
+        order_type: order.order_type,
+        market_index: order.market_index,
+        price: order.price,
+        user_base_asset_amount: order.user_base_asset_amount,
+        quote_asset_amount: order.quote_asset_amount,
+        base_asset_amount: order.base_asset_amount,
+        base_asset_amount_filled: order.base_asset_amount_filled,
+        quote_asset_amount_filled: order.quote_asset_amount_filled,
+        fee: order.fee,
+        direction: order.direction,
+        reduce_only: order.reduce_only,
+        post_only: order.post_only,
+        immediate_or_cancel: order.immediate_or_cancel,
+        discount_tier: order.discount_tier,
+        trigger_price: order.trigger_price,
+        trigger_condition: order.trigger_condition,
+        referrer: order.referrer.to_string(),
+        oracle_price_offset: order.oracle_price_offset,
+    }
+}
+
 // get list in response
 pub fn get_active_positions(
     deps: Deps,
@@ -2,7 +2,8 @@ use cosmwasm_std::{Decimal, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::types::{OracleSource, OrderParams, PositionDirection};
+use crate::number::Number128;
+use crate::types::{CurveType, DiscountToken, MakerOrderId, OracleSource, OrderParams, PositionDirection};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
@@ -27,6 +28,7 @@ pub enum ExecuteMsg {
         margin_ratio_initial: u32,
         margin_ratio_partial: u32,
         margin_ratio_maintenance: u32,
+        curve_type: Option<CurveType>,
     },
     //deposit collateral, updates user struct
     DepositCollateral {
@@ -55,6 +57,23 @@ pub enum ExecuteMsg {
         market_index: u64,
         order_id: u64,
     },
+    CancelOrderByUserId {
+        user_order_id: u64,
+    },
+    CancelAllOrders {
+        market_index: Option<u64>,
+        direction: Option<PositionDirection>,
+    },
+    BatchOrders {
+        place: Vec<OrderParams>,
+        cancel: Vec<u64>,
+    },
+    ModifyOrder {
+        order_id: u64,
+        new_price: Option<Uint128>,
+        new_base_asset_amount: Option<Uint128>,
+        new_trigger_price: Option<Uint128>,
+    },
     ExpireOrders {
         user_address: String,
     },
@@ -62,11 +81,30 @@ pub enum ExecuteMsg {
         order_id: u64,
         user_address: String,
         market_index: u64,
+        maker_orders: Option<Vec<MakerOrderId>>,
     },
     Liquidate {
         user: String,
         market_index: u64,
     },
+    LiquidatePerp {
+        user: String,
+        market_index: u64,
+        max_base_asset_amount: Uint128,
+        limit_price: Uint128,
+    },
+    StartLiquidationAuction {
+        user: String,
+        market_index: u64,
+    },
+    BidLiquidationAuction {
+        user: String,
+        market_index: u64,
+        base_asset_amount: Uint128,
+    },
+    ResolveBankruptcy {
+        user: String,
+    },
     MoveAMMPrice {
         base_asset_reserve: Uint128,
         quote_asset_reserve: Uint128,
@@ -100,6 +138,10 @@ pub enum ExecuteMsg {
     },
     //user calls it we get the user identification from msg address sender
     SettleFundingPayment {},
+    RegisterReferralCode {
+        code: String,
+    },
+    ClaimReferralRewards {},
     UpdateFundingRate {
         market_index: u64,
     },
@@ -107,6 +149,36 @@ pub enum ExecuteMsg {
         market_index: u64,
         sqrt_k: Uint128,
     },
+    UpdateRepegBudget {
+        market_index: u64,
+        repeg_budget: Uint128,
+    },
+    UpdateDynamicK {
+        market_index: u64,
+        enabled: bool,
+        minimum_sqrt_k: Uint128,
+        maximum_sqrt_k: Uint128,
+    },
+    UpdateSpread {
+        market_index: u64,
+        base_spread: u32,
+        max_spread: u32,
+    },
+    UpdateFundingParameters {
+        market_index: u64,
+        max_funding_rate: u32,
+        interest_rate: u32,
+        funding_clamp: u32,
+    },
+    UpdateFundingFeeShare {
+        market_index: u64,
+        funding_fee_share: u32,
+    },
+    UpdateOracleTwapParameters {
+        market_index: u64,
+        oracle_twap_period: u64,
+        oracle_twap_cap_percent: u32,
+    },
     UpdateMarginRatio {
         market_index: u64,
         margin_ratio_initial: u32,
@@ -130,6 +202,7 @@ pub enum ExecuteMsg {
     },
     UpdateFee {
         fee_: Decimal,
+        maker_fee: Number128,
         first_tier_minimum_balance: Uint128,
         first_tier_discount: Decimal,
         second_tier_minimum_balance: Uint128,
@@ -141,6 +214,27 @@ pub enum ExecuteMsg {
         referrer_reward: Decimal,
         referee_discount: Decimal,
     },
+    UpdateReferrerRewardRate {
+        referrer: String,
+        referrer_reward: Decimal,
+        referee_discount: Decimal,
+    },
+    UpdateFeeTierVolumes {
+        first_tier_minimum_volume: Uint128,
+        second_tier_minimum_volume: Uint128,
+        third_tier_minimum_volume: Uint128,
+        fourth_tier_minimum_volume: Uint128,
+    },
+    UpdateDiscountToken {
+        token: Option<DiscountToken>,
+        staking_contract: Option<String>,
+    },
+    UpdateLiquidationAuctionConfig {
+        enabled: bool,
+        duration: u64,
+        start_discount: Decimal,
+        max_discount: Decimal,
+    },
     UpdateOraceGuardRails {
         use_for_liquidations: bool,
         mark_oracle_divergence: Decimal,
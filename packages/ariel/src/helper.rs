@@ -1,10 +1,12 @@
 use cosmwasm_std::{
-    Addr, Api, BalanceResponse, BankQuery, MessageInfo, QuerierWrapper, QueryRequest, StdError,
-    StdResult, Uint128,
+    to_binary, Addr, Api, BalanceResponse, BankQuery, MessageInfo, QuerierWrapper, QueryRequest,
+    StdError, StdResult, Uint128, WasmQuery,
 };
 use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
 
+use crate::types::DiscountToken;
+
 pub fn addr_validate_to_lower(api: &dyn Api, addr: &str) -> StdResult<Addr> {
     if addr.to_lowercase() != addr {
         return Err(StdError::generic_err(format!(
@@ -47,6 +49,70 @@ pub fn query_balance(querier: &QuerierWrapper, account_addr: Addr) -> StdResult<
     Ok(balance.amount.amount.u128())
 }
 
+pub fn query_token_balance(
+    querier: &QuerierWrapper,
+    token: &DiscountToken,
+    account_addr: &Addr,
+) -> StdResult<Uint128> {
+    match token {
+        DiscountToken::Native { denom } => {
+            let balance: BalanceResponse = querier.query(&QueryRequest::Bank(BankQuery::Balance {
+                address: String::from(account_addr),
+                denom: denom.clone(),
+            }))?;
+            Ok(balance.amount.amount)
+        }
+        DiscountToken::Cw20 { contract_addr } => {
+            let balance: Cw20BalanceResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: contract_addr.clone(),
+                msg: to_binary(&Cw20QueryMsg::Balance {
+                    address: String::from(account_addr),
+                })?,
+            }))?;
+            Ok(balance.balance)
+        }
+    }
+}
+
+pub fn query_staked_balance(
+    querier: &QuerierWrapper,
+    staking_contract: &Addr,
+    account_addr: &Addr,
+) -> StdResult<Uint128> {
+    let staked: StakedBalanceResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: String::from(staking_contract),
+        msg: to_binary(&StakingQueryMsg::StakedBalanceAtHeight {
+            address: String::from(account_addr),
+            height: None,
+        })?,
+    }))?;
+    Ok(staked.balance)
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20QueryMsg {
+    Balance { address: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct Cw20BalanceResponse {
+    pub balance: Uint128,
+}
+
+// cw20-stake style staking contract interface
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingQueryMsg {
+    StakedBalanceAtHeight { address: String, height: Option<u64> },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct StakedBalanceResponse {
+    pub balance: Uint128,
+    pub height: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum VaultInterface {
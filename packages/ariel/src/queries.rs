@@ -1,4 +1,5 @@
 use schemars::JsonSchema;
+use crate::types::{OrderAction, OrderType};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -33,6 +34,25 @@ pub enum QueryMsg {
     GetFullLiquidatorSharePercentage {},
     GetMaxDepositLimit {},
     GetFeeStructure {},
+    GetFeeTierVolumes {},
+    GetDiscountToken {},
+    GetReferralStats {
+        referrer: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    GetUserFeeTier {
+        user_address: String,
+    },
+    GetLiquidatableUsers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    GetLiquidationAuctionConfig {},
+    GetLiquidationAuction {
+        user_address: String,
+        market_index: u64,
+    },
     GetCurveHistoryLength {},
     GetCurveHistory {
         start_after: Option<String>,
@@ -50,6 +70,12 @@ pub enum QueryMsg {
         start_after: Option<String>,
         limit: Option<u32>,
     },
+    GetNextFundingTime {
+        market_index: u64,
+    },
+    GetFundingRateEstimate {
+        market_index: u64,
+    },
     GetFundingRateHistoryLength {},
     GetFundingRateHistory {
         start_after: Option<String>,
@@ -62,6 +88,20 @@ pub enum QueryMsg {
         start_after: Option<String>,
         limit: Option<u32>,
     },
+    GetBankruptcyHistoryLength {},
+    GetBankruptcyHistory {
+        user_address: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    GetOrderHistoryLength {},
+    GetOrderHistory {
+        user_address: Option<String>,
+        market_index: Option<u64>,
+        action: Option<OrderAction>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
     GetTradeHistoryLength {},
     GetTradeHistory {
         start_after: Option<String>,
@@ -70,4 +110,24 @@ pub enum QueryMsg {
     GetMarketInfo {
         market_index: u64,
     },
+    GetOrder {
+        user: String,
+        order_id: u64,
+    },
+    GetOrderByUserId {
+        user: String,
+        user_order_id: u64,
+    },
+    GetUserOrders {
+        user: String,
+        market_index: Option<u64>,
+        order_type: Option<OrderType>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    GetFillableOrders {
+        market_index: u64,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
 }
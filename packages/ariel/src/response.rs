@@ -2,7 +2,7 @@ use cosmwasm_std::{Uint128, Decimal};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{types::{DepositDirection, OracleSource, PositionDirection}, number::Number128};
+use crate::{types::{CurveType, DepositDirection, DiscountToken, LiquidatedMarket, LiquidationType, Type, OracleSource, OrderAction, OrderDiscountTier, OrderStatus, OrderTriggerCondition, OrderType, PositionDirection}, number::Number128};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct UserResponse {
@@ -116,6 +116,7 @@ pub struct MarketLengthResponse {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct FeeStructureResponse {
     pub fee: Decimal,
+    pub maker_fee: Number128,
     pub first_tier_minimum_balance: Uint128,
     pub first_tier_discount : Decimal,
     pub second_tier_minimum_balance : Uint128,
@@ -155,6 +156,7 @@ pub struct CurveHistoryResponse {
     pub ts: u64,
     pub record_id: u64,
     pub market_index: u64,
+    pub record_type: Type,
     pub peg_multiplier_before: Uint128,
     pub base_asset_reserve_before: Uint128,
     pub quote_asset_reserve_before: Uint128,
@@ -223,6 +225,28 @@ pub struct FundingRateHistoryResponse {
     pub cumulative_funding_rate_short: Number128,
     pub oracle_price_twap: Number128,
     pub mark_price_twap: Uint128,
+    pub capped_funding_amount: Uint128,
+    pub funding_pool: Uint128,
+    pub funding_shortfall: Number128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NextFundingTimeResponse {
+    pub market_index: u64,
+    pub last_funding_rate_ts: u64,
+    pub next_funding_ts: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingRateEstimateResponse {
+    pub market_index: u64,
+    pub funding_rate_long: Number128,
+    pub funding_rate_short: Number128,
+    pub mark_price_twap: Uint128,
+    pub oracle_price_twap: Number128,
+    pub next_funding_ts: u64,
+    pub time_remaining: u64,
+    pub blocked_by_oracle: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -235,7 +259,9 @@ pub struct LiquidationHistoryResponse {
     pub ts: u64,
     pub record_id: u64,
     pub user: String,
+    pub market_index: u64,
     pub partial: bool,
+    pub markets: Vec<LiquidatedMarket>,
     pub base_asset_value: Uint128,
     pub base_asset_value_closed: Uint128,
     pub liquidation_fee: Uint128,
@@ -248,6 +274,46 @@ pub struct LiquidationHistoryResponse {
     pub margin_ratio: Uint128,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BankruptcyHistoryLengthResponse {
+    pub length: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BankruptcyHistoryResponse {
+    pub ts: u64,
+    pub record_id: u64,
+    pub user: String,
+    pub market_index: u64,
+    pub loss: Uint128,
+    pub insurance_fund_paid: Uint128,
+    pub socialized_loss: Uint128,
+    pub cumulative_funding_rate_delta: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderHistoryLengthResponse {
+    pub length: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderHistoryResponse {
+    pub ts: u64,
+    pub record_id: u64,
+    pub user: String,
+    pub order: OrderResponse,
+    pub action: OrderAction,
+    pub filler: String,
+    pub trade_record_id: u64,
+    pub base_asset_amount_filled: Uint128,
+    pub quote_asset_amount_filled: Uint128,
+    pub fee: Uint128,
+    pub maker_fee: Number128,
+    pub filler_reward: Uint128,
+    pub quote_asset_amount_surplus: Uint128,
+    pub market_index: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct TradeHistoryLengthResponse {
     pub length: u64,
@@ -263,6 +329,7 @@ pub struct TradeHistoryResponse {
     pub mark_price_before: Uint128,
     pub mark_price_after: Uint128,
     pub fee: Uint128,
+    pub maker_fee: Number128,
     pub referrer_reward: Uint128,
     pub referee_discount: Uint128,
     pub token_discount: Uint128,
@@ -302,7 +369,139 @@ pub struct MarketInfoResponse {
     pub last_oracle_price_twap_ts: u64,
     pub last_oracle_price: Number128,
     pub minimum_base_asset_trade_size: Uint128,
-    pub minimum_quote_asset_trade_size: Uint128
+    pub minimum_quote_asset_trade_size: Uint128,
+    pub repeg_budget: Uint128,
+    pub dynamic_k_enabled: bool,
+    pub minimum_sqrt_k: Uint128,
+    pub maximum_sqrt_k: Uint128,
+    pub last_k_update_total_fee_minus_distributions: Uint128,
+    pub base_spread: u32,
+    pub max_spread: u32,
+    pub total_spread_revenue: Uint128,
+    pub max_funding_rate: u32,
+    pub interest_rate: u32,
+    pub funding_clamp: u32,
+    pub funding_pool: Uint128,
+    pub funding_fee_share: u32,
+    pub funding_shortfall: Number128,
+    pub oracle_twap_period: u64,
+    pub oracle_twap_cap_percent: u32,
+    pub curve_type: CurveType,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderResponse {
+    pub ts: u64,
+    pub order_id: u64,
+    pub user_order_id: Option<u64>,
+    pub status: OrderStatus,
+    pub order_type: OrderType,
+    pub market_index: u64,
+    pub price: Uint128,
+    pub user_base_asset_amount: Number128,
+    pub quote_asset_amount: Uint128,
+    pub base_asset_amount: Uint128,
+    pub base_asset_amount_filled: Uint128,
+    pub quote_asset_amount_filled: Uint128,
+    pub fee: Uint128,
+    pub direction: PositionDirection,
+    pub reduce_only: bool,
+    pub post_only: bool,
+    pub immediate_or_cancel: bool,
+    pub discount_tier: OrderDiscountTier,
+    pub trigger_price: Uint128,
+    pub trigger_condition: OrderTriggerCondition,
+    pub referrer: String,
+    pub oracle_price_offset: Number128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTierVolumesResponse {
+    pub first_tier_minimum_volume: Uint128,
+    pub second_tier_minimum_volume: Uint128,
+    pub third_tier_minimum_volume: Uint128,
+    pub fourth_tier_minimum_volume: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserFeeTierResponse {
+    pub tier: OrderDiscountTier,
+    pub rolling_volume: Uint128, // quote asset volume over the last 30 days
+    pub discount_token_balance: Uint128, // held plus staked
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DiscountTokenResponse {
+    pub token: Option<DiscountToken>,
+    pub staking_contract: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatableUserResponse {
+    pub user: String,
+    pub liquidation_type: LiquidationType,
+    pub margin_ratio: Uint128,
+    pub estimated_liquidator_reward: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatableUsersResponse {
+    pub users: Vec<LiquidatableUserResponse>,
+    // last user looked at, pass as start_after to continue the scan
+    pub last_user: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidationAuctionConfigResponse {
+    pub enabled: bool,
+    pub duration: u64,
+    pub start_discount: Decimal,
+    pub max_discount: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidationAuctionResponse {
+    pub user: String,
+    pub market_index: u64,
+    pub direction: PositionDirection,
+    pub base_asset_amount: Uint128,
+    pub partial: bool,
+    pub start_ts: u64,
+    pub end_ts: u64,
+    pub current_discount: Decimal,
+    pub current_price: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RefereeResponse {
+    pub referee: String,
+    pub referrer_reward: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralStatsResponse {
+    pub referrer: String,
+    pub codes: Vec<String>,
+    pub referrer_reward: Decimal,
+    pub referee_discount: Decimal,
+    pub total_referral_reward: Uint128,
+    pub total_claimed: Uint128,
+    pub unclaimed_referral_reward: Uint128,
+    pub referees: Vec<RefereeResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FillableOrderResponse {
+    pub user: String,
+    pub order: OrderResponse,
+    pub base_asset_amount_market_can_execute: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FillableOrdersResponse {
+    pub orders: Vec<FillableOrderResponse>,
+    // last user looked at, pass as start_after to continue the scan
+    pub last_user: Option<String>,
 }
 
 // #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
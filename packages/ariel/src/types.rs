@@ -43,6 +43,22 @@ impl Default for DepositDirection {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum OrderAction {
+    Place,
+    Cancel,
+    Fill,
+    Expire,
+    Modify,
+}
+
+impl Default for OrderAction {
+    // UpOnly
+    fn default() -> Self {
+        OrderAction::Place
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum OracleSource {
     Oracle,
@@ -58,6 +74,25 @@ impl Default for OracleSource {
     }
 }
 
+/// Shape of the vAMM's bonding curve, selected when the market is initialized
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveType {
+    // x * y = k
+    ConstantProduct,
+    // curve-style stableswap invariant, flatter around the peg for pegged assets
+    StableSwap { amplification: u64 },
+    // constant product on virtual reserves packed between the two mark prices, deeper inside the
+    // range and without liquidity outside of it
+    Concentrated { min_price: Uint128, max_price: Uint128 },
+}
+
+impl Default for CurveType {
+    fn default() -> Self {
+        CurveType::ConstantProduct
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct OracleStatus {
     pub price_data: OraclePriceData,
@@ -79,6 +114,8 @@ pub struct OraclePriceData {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Order {
     pub ts: u64,
+    pub order_id: u64,
+    pub user_order_id: Option<u64>,
     pub status: OrderStatus,
     pub order_type: OrderType,
     pub position_index : u64,
@@ -129,6 +166,27 @@ impl Default for OrderTriggerCondition {
     }
 }
 
+// kind of curve adjustment in the curve history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum Type {
+    Repeg,
+    UpdateK,
+}
+
+impl Default for Type {
+    // UpOnly
+    fn default() -> Self {
+        Type::Repeg
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub enum LiquidationType {
+    NONE,
+    PARTIAL,
+    FULL,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub enum OrderDiscountTier {
     None,
@@ -138,10 +196,20 @@ pub enum OrderDiscountTier {
     Fourth,
 }
 
+// token whose balance qualifies traders for the fee discount tiers
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountToken {
+    Cw20 { contract_addr: String },
+    Native { denom: String },
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct FeeStructure {
+    // charged to takers and to orders that aren't post only
     pub fee: Decimal,
+    // charged to makers in MAKER_FEE_PRECISION, negative values are rebates
+    pub maker_fee: Number128,
 
     pub first_tier_minimum_balance: Uint128,
     pub first_tier_discount: Decimal,
@@ -186,4 +254,20 @@ pub struct OrderParams {
     pub trigger_condition: OrderTriggerCondition,
     pub position_limit: Uint128,
     pub oracle_price_offset: Number128,
-}
\ No newline at end of file
+    pub user_order_id: Option<u64>,
+}
+
+// resting order of another user a filler wants to match against
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MakerOrderId {
+    pub user_address: String,
+    pub order_id: u64,
+}
+// what a single liquidation closed in one market
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatedMarket {
+    pub market_index: u64,
+    pub base_asset_amount: Uint128,
+    pub base_asset_value_closed: Uint128,
+    pub liquidation_fee: Uint128,
+}